@@ -7,6 +7,11 @@ pub enum CartridgeType {
     Mbc1,
     Mbc1Ram,
     Mbc1RamBattery,
+    Mbc3TimerBattery,
+    Mbc3TimerRamBattery,
+    Mbc3,
+    Mbc3Ram,
+    Mbc3RamBattery,
 }
 
 #[repr(C, packed)]
@@ -50,6 +55,11 @@ impl CartridgeHeader {
             0x01 => CartridgeType::Mbc1,
             0x02 => CartridgeType::Mbc1Ram,
             0x03 => CartridgeType::Mbc1RamBattery,
+            0x0F => CartridgeType::Mbc3TimerBattery,
+            0x10 => CartridgeType::Mbc3TimerRamBattery,
+            0x11 => CartridgeType::Mbc3,
+            0x12 => CartridgeType::Mbc3Ram,
+            0x13 => CartridgeType::Mbc3RamBattery,
 
             _ => todo!(),
         }
@@ -62,11 +72,46 @@ impl CartridgeHeader {
             0x02 => "MBC1+RAM",
             0x03 => "MBC1+RAM+BATTERY",
             // Add more cartridge types as needed
+            0x0F => "MBC3+TIMER+BATTERY",
             0x10 => "MBC3+TIMER+RAM+BATTERY",
+            0x11 => "MBC3",
+            0x12 => "MBC3+RAM",
+            0x13 => "MBC3+RAM+BATTERY",
             _ => "Unknown",
         }
     }
 
+    /// Whether the game wants to run in CGB mode. Bit 7 is set both for
+    /// colour-only games and for ones that also run on the DMG.
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 == 0x80
+    }
+
+    /// Whether the game uses Super Game Boy features. The SGB ignores the
+    /// flag unless the old licensee code points at the new one.
+    pub fn supports_sgb(&self) -> bool {
+        self.sgb_flag == 0x03 && self.old_licensee_code == 0x33
+    }
+
+    /// Sum of the 16 bytes of the title area. The CGB boot ROM uses it to
+    /// recognise DMG games and pick their colours.
+    pub fn title_checksum(&self) -> u8 {
+        self.title
+            .iter()
+            .chain(self.manufacturer_code.iter())
+            .chain(std::iter::once(&self.cgb_flag))
+            .fold(0u8, |acc, &byte| acc.wrapping_add(byte))
+    }
+
+    pub fn header_checksum(&self) -> u8 {
+        self.header_checksum
+    }
+
+    pub fn is_nintendo_licensed(&self) -> bool {
+        self.old_licensee_code == 0x01
+            || (self.old_licensee_code == 0x33 && self.new_licensee_code == *b"01")
+    }
+
     pub fn rom_size_str(&self) -> String {
         format!("{} Kib", 32 * (1 << self.rom_size))
     }
@@ -1,4 +1,5 @@
 use crate::cartridge::MBC;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 pub struct MBC0 {
     rom: Vec<u8>,
@@ -16,4 +17,25 @@ impl MBC for MBC0 {
     }
 
     fn write_byte(&mut self, _addr: u16, _value: u8) {}
+
+    fn rom_bank(&self) -> u16 {
+        1
+    }
+
+    fn ram_bank(&self) -> u16 {
+        0
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+}
+
+// Nothing but ROM
+impl SaveState for MBC0 {
+    fn save(&self, _state: &mut StateWriter) {}
+
+    fn load(&mut self, _state: &mut StateReader) -> Result<(), String> {
+        Ok(())
+    }
 }
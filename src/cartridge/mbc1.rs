@@ -1,4 +1,5 @@
 use crate::cartridge::MBC;
+use crate::state::{SaveState, StateReader, StateWriter};
 
 pub struct MBC1 {
     rom: Vec<u8>,
@@ -21,13 +22,7 @@ impl MBC for MBC1 {
         match addr {
             0x0..=0x3FFF => self.rom[addr as usize],
             0x4000..=0x7FFF => {
-                let bank: u16 = if self.rom_bank == 0 {
-                    1
-                } else {
-                    self.rom_bank as u16
-                };
-
-                let idx = (bank * 0x4000) | (addr & 0x3FFF);
+                let idx = (self.rom_bank() * 0x4000) | (addr & 0x3FFF);
 
                 self.rom[idx as usize]
             }
@@ -51,4 +46,33 @@ impl MBC for MBC1 {
             }
         }
     }
+
+    fn rom_bank(&self) -> u16 {
+        if self.rom_bank == 0 {
+            1
+        } else {
+            self.rom_bank as u16
+        }
+    }
+
+    // Only one bank of RAM is supported
+    fn ram_bank(&self) -> u16 {
+        0
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+}
+
+impl SaveState for MBC1 {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.rom_bank);
+        state.bytes(&self.ram);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.rom_bank = state.u8()?;
+        state.bytes(&mut self.ram)
+    }
 }
@@ -0,0 +1,321 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cartridge::MBC;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+const RAM_BANK_SIZE: usize = 0x2000;
+const RAM_BANKS: usize = 4;
+
+pub struct MBC3 {
+    rom: Vec<u8>,
+    rom_bank: u8,
+    ram: Vec<u8>,
+    ram_enabled: bool,
+
+    // 0x00-0x03 select a RAM bank, 0x08-0x0C one of the clock registers
+    ram_bank: u8,
+
+    rtc: RealTimeClock,
+    latched_rtc: [u8; 5],
+    latch_armed: bool,
+}
+
+/// The MBC3 clock counts seconds, minutes, hours and a 9-bit day counter. It
+/// is advanced from the host clock whenever the game looks at it.
+struct RealTimeClock {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days: u16,
+    halted: bool,
+    day_carry: bool,
+    last_update: u64,
+}
+
+impl RealTimeClock {
+    fn new() -> RealTimeClock {
+        RealTimeClock {
+            seconds: 0,
+            minutes: 0,
+            hours: 0,
+            days: 0,
+            halted: false,
+            day_carry: false,
+            last_update: now(),
+        }
+    }
+
+    fn update(&mut self) {
+        let now = now();
+        let elapsed = now.saturating_sub(self.last_update);
+        self.last_update = now;
+
+        if self.halted || elapsed == 0 {
+            return;
+        }
+
+        let total = self.seconds as u64
+            + self.minutes as u64 * 60
+            + self.hours as u64 * 3600
+            + self.days as u64 * 86400
+            + elapsed;
+
+        self.seconds = (total % 60) as u8;
+        self.minutes = ((total / 60) % 60) as u8;
+        self.hours = ((total / 3600) % 24) as u8;
+
+        let days = total / 86400;
+        if days > 0x1FF {
+            self.day_carry = true;
+        }
+        self.days = (days & 0x1FF) as u16;
+    }
+
+    fn registers(&self) -> [u8; 5] {
+        [
+            self.seconds,
+            self.minutes,
+            self.hours,
+            self.days as u8,
+            ((self.days >> 8) as u8 & 0x1)
+                | ((self.halted as u8) << 6)
+                | ((self.day_carry as u8) << 7),
+        ]
+    }
+
+    fn write(&mut self, register: u8, value: u8) {
+        self.update();
+
+        match register {
+            0x08 => self.seconds = value & 0x3F,
+            0x09 => self.minutes = value & 0x3F,
+            0x0A => self.hours = value & 0x1F,
+            0x0B => self.days = (self.days & 0x100) | value as u16,
+            0x0C => {
+                self.days = (self.days & 0xFF) | ((value as u16 & 0x1) << 8);
+                self.halted = value & 0x40 == 0x40;
+                self.day_carry = value & 0x80 == 0x80;
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+// The clock keeps the host time it was last updated at, so it catches up
+// with the time that passed since the state was saved
+impl SaveState for RealTimeClock {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.seconds);
+        state.u8(self.minutes);
+        state.u8(self.hours);
+        state.u16(self.days);
+        state.bool(self.halted);
+        state.bool(self.day_carry);
+        state.u64(self.last_update);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.seconds = state.u8()?;
+        self.minutes = state.u8()?;
+        self.hours = state.u8()?;
+        self.days = state.u16()?;
+        self.halted = state.bool()?;
+        self.day_carry = state.bool()?;
+        self.last_update = state.u64()?;
+        Ok(())
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl MBC3 {
+    pub fn new(rom: Vec<u8>) -> MBC3 {
+        MBC3 {
+            rom,
+            rom_bank: 1,
+            ram: vec![0; RAM_BANK_SIZE * RAM_BANKS],
+            ram_enabled: false,
+            ram_bank: 0,
+            rtc: RealTimeClock::new(),
+            latched_rtc: [0; 5],
+            latch_armed: false,
+        }
+    }
+
+    fn ram_index(&self, addr: u16) -> usize {
+        self.ram_bank as usize * RAM_BANK_SIZE + (addr as usize - 0xA000)
+    }
+}
+
+impl MBC for MBC3 {
+    fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            0x0..=0x3FFF => self.rom[addr as usize],
+            0x4000..=0x7FFF => {
+                let idx = (self.rom_bank as usize * 0x4000) | (addr as usize & 0x3FFF);
+                self.rom[idx % self.rom.len()]
+            }
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                match self.ram_bank {
+                    0x00..=0x03 => self.ram[self.ram_index(addr)],
+                    0x08..=0x0C => self.latched_rtc[(self.ram_bank - 0x08) as usize],
+                    _ => 0xFF,
+                }
+            }
+
+            // Nothing else is mapped to the cartridge
+            _ => 0xFF,
+        }
+    }
+
+    fn write_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0..=0x1FFF => self.ram_enabled = value & 0x0F == 0x0A,
+            0x2000..=0x3FFF => self.rom_bank = (value & 0x7F).max(1),
+            0x4000..=0x5FFF => self.ram_bank = value,
+
+            // Writing 0 then 1 copies the running clock into the registers
+            // the game can read
+            0x6000..=0x7FFF => {
+                if self.latch_armed && value == 1 {
+                    self.rtc.update();
+                    self.latched_rtc = self.rtc.registers();
+                }
+                self.latch_armed = value == 0;
+            }
+
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return;
+                }
+
+                match self.ram_bank {
+                    0x00..=0x03 => {
+                        let index = self.ram_index(addr);
+                        self.ram[index] = value;
+                    }
+                    0x08..=0x0C => {
+                        self.rtc.write(self.ram_bank, value);
+                        self.latched_rtc = self.rtc.registers();
+                    }
+                    _ => (),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn rom_bank(&self) -> u16 {
+        self.rom_bank as u16
+    }
+
+    // Clock registers show up as the bank that selects them
+    fn ram_bank(&self) -> u16 {
+        self.ram_bank as u16
+    }
+
+    fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+}
+
+impl SaveState for MBC3 {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.rom_bank);
+        state.bytes(&self.ram);
+        state.bool(self.ram_enabled);
+        state.u8(self.ram_bank);
+        self.rtc.save(state);
+        state.bytes(&self.latched_rtc);
+        state.bool(self.latch_armed);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.rom_bank = state.u8()?;
+        state.bytes(&mut self.ram)?;
+        self.ram_enabled = state.bool()?;
+        self.ram_bank = state.u8()?;
+        self.rtc.load(state)?;
+        state.bytes(&mut self.latched_rtc)?;
+        self.latch_armed = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MBC3;
+    use crate::cartridge::MBC;
+
+    fn mbc3() -> MBC3 {
+        // Each bank starts with its own number
+        let mut rom = vec![0; 0x4000 * 8];
+        for bank in 0..8 {
+            rom[bank * 0x4000] = bank as u8;
+        }
+        MBC3::new(rom)
+    }
+
+    #[test]
+    fn switches_rom_banks() {
+        let mut mbc = mbc3();
+        assert_eq!(mbc.read_byte(0x4000), 1);
+
+        mbc.write_byte(0x2000, 5);
+        assert_eq!(mbc.read_byte(0x4000), 5);
+
+        // Bank 0 can't be mapped at 0x4000
+        mbc.write_byte(0x2000, 0);
+        assert_eq!(mbc.read_byte(0x4000), 1);
+    }
+
+    #[test]
+    fn ram_is_only_accessible_while_enabled() {
+        let mut mbc = mbc3();
+        mbc.write_byte(0xA000, 0x42);
+        assert_eq!(mbc.read_byte(0xA000), 0xFF);
+
+        mbc.write_byte(0x0000, 0x0A);
+        mbc.write_byte(0x4000, 2);
+        mbc.write_byte(0xA000, 0x42);
+        assert_eq!(mbc.read_byte(0xA000), 0x42);
+
+        mbc.write_byte(0x4000, 0);
+        assert_eq!(mbc.read_byte(0xA000), 0);
+    }
+
+    #[test]
+    fn clock_registers_are_latched() {
+        let mut mbc = mbc3();
+        mbc.write_byte(0x0000, 0x0A);
+
+        // Halt the clock so it doesn't move while testing
+        mbc.write_byte(0x4000, 0x0C);
+        mbc.write_byte(0xA000, 0x40);
+        mbc.write_byte(0x4000, 0x08);
+        mbc.write_byte(0xA000, 30);
+
+        mbc.write_byte(0x6000, 0);
+        mbc.write_byte(0x6000, 1);
+        assert_eq!(mbc.read_byte(0xA000), 30);
+
+        mbc.write_byte(0x4000, 0x0C);
+        assert_eq!(mbc.read_byte(0xA000), 0x40);
+    }
+
+    #[test]
+    fn unmapped_accesses_are_open_bus() {
+        let mut mbc = mbc3();
+        mbc.write_byte(0xC000, 0x12);
+        assert_eq!(mbc.read_byte(0xC000), 0xFF);
+    }
+}
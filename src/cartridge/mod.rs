@@ -1,36 +1,65 @@
 use header::{CartridgeHeader, CartridgeType};
 use mbc0::MBC0;
 use mbc1::MBC1;
+use mbc3::MBC3;
+
+use crate::state::{SaveState, StateReader, StateWriter};
 
 pub mod header;
 
 mod mbc0;
 mod mbc1;
+mod mbc3;
 
-pub trait MBC {
+/// A memory bank controller. Its banking registers, cartridge RAM and clock
+/// are part of save states.
+pub trait MBC: SaveState {
     fn read_byte(&self, addr: u16) -> u8;
     fn write_byte(&mut self, addr: u16, value: u8);
+
+    /// The ROM bank mapped at 0x4000-0x7FFF.
+    fn rom_bank(&self) -> u16;
+
+    /// The RAM bank mapped at 0xA000-0xBFFF.
+    fn ram_bank(&self) -> u16;
+
+    /// The whole ROM, every bank of it.
+    fn rom(&self) -> &[u8];
 }
 
 pub struct Cartridge {
     pub header: CartridgeHeader,
+
+    // CRC-32 of the whole ROM. Save states are tied to the ROM they were
+    // made with.
+    pub checksum: u32,
     mbc: Box<dyn MBC>,
 }
 
 impl Cartridge {
     pub fn new(rom: Vec<u8>) -> Cartridge {
         let header = CartridgeHeader::new(&rom).unwrap();
+        let checksum = crc32(&rom);
 
         let mbc: Box<dyn MBC> = match header.cartridge_type() {
             CartridgeType::RomOnly => Box::new(MBC0::new(rom)),
             CartridgeType::Mbc1 => Box::new(MBC1::new(rom)),
+            CartridgeType::Mbc3TimerBattery
+            | CartridgeType::Mbc3TimerRamBattery
+            | CartridgeType::Mbc3
+            | CartridgeType::Mbc3Ram
+            | CartridgeType::Mbc3RamBattery => Box::new(MBC3::new(rom)),
             _ => {
                 dbg!(header.cartridge_type());
                 todo!()
             }
         };
 
-        Cartridge { header, mbc }
+        Cartridge {
+            header,
+            checksum,
+            mbc,
+        }
     }
 
     pub fn read_byte(&self, addr: u16) -> u8 {
@@ -40,4 +69,49 @@ impl Cartridge {
     pub fn write_byte(&mut self, addr: u16, value: u8) {
         self.mbc.write_byte(addr, value)
     }
+
+    pub fn rom_bank(&self) -> u16 {
+        self.mbc.rom_bank()
+    }
+
+    pub fn ram_bank(&self) -> u16 {
+        self.mbc.ram_bank()
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        self.mbc.rom()
+    }
+}
+
+impl SaveState for Cartridge {
+    fn save(&self, state: &mut StateWriter) {
+        self.mbc.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.mbc.load(state)
+    }
+}
+
+pub const ROM_BANK_SIZE: usize = 0x4000;
+
+/// Where an address in a ROM bank is in the ROM. Bank 0 is mapped at
+/// 0x0000-0x3FFF and the others at 0x4000-0x7FFF.
+pub fn rom_offset(bank: u16, addr: u16) -> usize {
+    bank as usize * ROM_BANK_SIZE + (addr as usize & 0x3FFF)
+}
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFFFFFF_u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
 }
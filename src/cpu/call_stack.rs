@@ -0,0 +1,156 @@
+use std::fmt;
+
+use crate::mmu::address::BankedAddress;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FrameKind {
+    Call,
+    Rst,
+    Interrupt,
+}
+
+/// A call, RST or interrupt that hasn't returned yet.
+#[derive(Clone, Copy, Debug)]
+pub struct Frame {
+    pub kind: FrameKind,
+
+    // The instruction that called, or the one the interrupt came in before
+    pub caller: BankedAddress,
+    pub entry: BankedAddress,
+    pub return_addr: u16,
+
+    // Pointing at the return address
+    pub sp: u16,
+}
+
+/// The calls that haven't returned yet, kept alongside the real stack.
+/// Games don't always return to where they were called from, and sometimes
+/// drop return addresses by moving SP, so the two can disagree. When they
+/// do, frames whose return address must have been overwritten are dropped
+/// and the last problem is kept to warn about.
+#[derive(Default, Debug)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    pub problem: Option<String>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack {
+            frames: Vec::new(),
+            problem: None,
+        }
+    }
+
+    /// Outermost first.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn call(&mut self, frame: Frame) {
+        // Anything at or below the new return address has been written over
+        if self.drop_below(frame.sp.wrapping_add(1)) {
+            self.problem = Some(format!(
+                "Call at {} wrote over the return address of an unfinished call",
+                frame.caller
+            ));
+        }
+
+        self.frames.push(frame);
+    }
+
+    /// A return from `at` with SP at `sp`, going to `return_addr`.
+    pub fn ret(&mut self, at: BankedAddress, sp: u16, return_addr: u16) {
+        if self.drop_below(sp) {
+            self.problem = Some(format!("Return at {} skipped unfinished calls", at));
+        }
+
+        match self.frames.last() {
+            Some(frame) if frame.sp == sp => {
+                if frame.return_addr != return_addr {
+                    self.problem = Some(format!(
+                        "Return at {} went to {:#06X} instead of {:#06X}",
+                        at, return_addr, frame.return_addr
+                    ));
+                }
+                self.frames.pop();
+            }
+
+            // Something like PUSH then RET to jump
+            _ => {
+                self.problem = Some(format!(
+                    "Return at {} to {:#06X} has no matching call",
+                    at, return_addr
+                ));
+            }
+        }
+    }
+
+    /// Drop frames with their return address below `sp`. Returns whether
+    /// there were any.
+    fn drop_below(&mut self, sp: u16) -> bool {
+        let live = self.frames.partition_point(|f| f.sp >= sp);
+        let dropped = live < self.frames.len();
+        self.frames.truncate(live);
+        dropped
+    }
+}
+
+impl fmt::Display for FrameKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameKind::Call => write!(f, "call"),
+            FrameKind::Rst => write!(f, "rst"),
+            FrameKind::Interrupt => write!(f, "interrupt"),
+        }
+    }
+}
+
+fn save_address(state: &mut StateWriter, addr: BankedAddress) {
+    state.u16(addr.bank.unwrap_or(0));
+    state.u16(addr.addr);
+}
+
+fn load_address(state: &mut StateReader) -> Result<BankedAddress, String> {
+    let bank = state.u16()?;
+    Ok(BankedAddress::in_bank(bank, state.u16()?))
+}
+
+impl SaveState for CallStack {
+    fn save(&self, state: &mut StateWriter) {
+        state.u32(self.frames.len() as u32);
+        for frame in self.frames.iter() {
+            state.u8(frame.kind as u8);
+            save_address(state, frame.caller);
+            save_address(state, frame.entry);
+            state.u16(frame.return_addr);
+            state.u16(frame.sp);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        let length = state.u32()?;
+
+        self.frames.clear();
+        for _ in 0..length {
+            let kind = match state.u8()? {
+                0 => FrameKind::Call,
+                1 => FrameKind::Rst,
+                2 => FrameKind::Interrupt,
+                _ => return Err("Save state has an invalid call stack".to_string()),
+            };
+
+            self.frames.push(Frame {
+                kind,
+                caller: load_address(state)?,
+                entry: load_address(state)?,
+                return_addr: state.u16()?,
+                sp: state.u16()?,
+            });
+        }
+
+        self.problem = None;
+        Ok(())
+    }
+}
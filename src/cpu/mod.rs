@@ -1,12 +1,17 @@
+use call_stack::{CallStack, Frame, FrameKind};
 use colored::*;
 use registers::Registers;
 
 use crate::{
+    cartridge::header::CartridgeHeader,
     debugger::enable_debug,
+    gameboy::model::Model,
     instructions::{parse, r16::R16, r8::R8, Instruction},
     mmu::MMU,
+    state::{SaveState, StateReader, StateWriter},
 };
 
+pub mod call_stack;
 mod execution;
 mod flag_register;
 mod registers;
@@ -15,6 +20,7 @@ mod registers;
 pub struct CPU {
     pub registers: Registers,
     pub ime: bool,
+    pub call_stack: CallStack,
 }
 
 impl Default for CPU {
@@ -28,11 +34,23 @@ impl CPU {
         CPU {
             registers: Registers::new(),
             ime: false,
+            call_stack: CallStack::new(),
+        }
+    }
+
+    /// A CPU in the state the given model's boot ROM leaves it in.
+    pub fn after_boot(model: Model, header: &CartridgeHeader) -> CPU {
+        CPU {
+            registers: Registers::after_boot(model, header),
+            ime: false,
+            call_stack: CallStack::new(),
         }
     }
 
     pub fn step(&mut self, mmu: &mut MMU) -> u8 {
         let (instruction, mut length, cycles) = self.ins(mmu);
+        let (pc, sp) = (self.registers.pc, self.registers.sp);
+        mmu.log_instruction(pc, length);
 
         let mut just_set_ei = false;
         match instruction {
@@ -139,7 +157,10 @@ impl CPU {
                     length = 0
                 }
             }
-            Instruction::Stop => {}
+            Instruction::Stop => {
+                // On the CGB STOP is also how the CPU changes speed
+                mmu.switch_speed();
+            }
             Instruction::ILLEGAL => {
                 println!("{}", "Illegal instruction encountered.".red());
                 length = 0;
@@ -148,10 +169,10 @@ impl CPU {
         };
 
         self.registers.pc = self.registers.pc.wrapping_add(length);
-        mmu.ppu.do_cycle(cycles as u32 / 4);
+        self.track_call_stack(mmu, &instruction, pc, sp);
 
-        // Increase DIV register
-        mmu.timer.do_cycles(cycles);
+        // Run the PPU, timer and DMA alongside the instruction
+        mmu.do_cycles(cycles);
 
         // Handle interrupts
         if self.ime && !just_set_ei {
@@ -161,52 +182,92 @@ impl CPU {
                 self.registers.pc
             };
 
-            // vblank
+            // Only the highest priority interrupt is serviced. The rest wait
+            // until the handler re-enables interrupts.
             if mmu.ie & 1 > 0 && mmu.ppu.vblank_irq {
+                // vblank
                 mmu.ppu.vblank_irq = false;
                 self.ime = false;
 
-                self.push(mmu, return_pc);
-                self.registers.pc = 0x40;
-            }
-
-            // stat
-            if mmu.ie & 2 > 0 && mmu.ppu.stat_irq {
+                self.interrupt(mmu, return_pc, 0x40);
+            } else if mmu.ie & 2 > 0 && mmu.ppu.stat_irq {
+                // stat
                 mmu.ppu.stat_irq = false;
                 self.ime = false;
 
-                self.push(mmu, return_pc);
-                self.registers.pc = 0x48;
-            }
-
-            // timer
-            if mmu.ie & 4 > 0 && mmu.timer.timer_irq {
+                self.interrupt(mmu, return_pc, 0x48);
+            } else if mmu.ie & 4 > 0 && mmu.timer.timer_irq {
+                // timer
                 mmu.timer.timer_irq = false;
                 self.ime = false;
 
-                self.push(mmu, return_pc);
-                self.registers.pc = 0x50;
-            }
-
-            // joypad
-            if mmu.ie & 0x10 > 0 && mmu.joypad.joypad_irq {
+                self.interrupt(mmu, return_pc, 0x50);
+            } else if mmu.ie & 0x10 > 0 && mmu.joypad.joypad_irq {
+                // joypad
                 mmu.joypad.joypad_irq = false;
                 self.ime = false;
 
-                self.push(mmu, return_pc);
-                self.registers.pc = 0x60;
+                self.interrupt(mmu, return_pc, 0x60);
             }
         }
 
         cycles
     }
 
+    fn interrupt(&mut self, mmu: &mut MMU, return_pc: u16, vector: u16) {
+        self.push(mmu, return_pc);
+        self.registers.pc = vector;
+
+        self.call_stack.call(Frame {
+            kind: FrameKind::Interrupt,
+            caller: mmu.banked(return_pc),
+            entry: mmu.banked(vector),
+            return_addr: return_pc,
+            sp: self.registers.sp,
+        });
+    }
+
+    /// Follow calls and returns on the shadow call stack. `pc` and `sp` are
+    /// from before the instruction. Conditional ones only count when taken,
+    /// which moves SP.
+    fn track_call_stack(&mut self, mmu: &MMU, instruction: &Instruction, pc: u16, sp: u16) {
+        let (kind, length) = match instruction {
+            Instruction::CallImm16(_) | Instruction::CallCondImm16(_, _) => (FrameKind::Call, 3),
+            Instruction::RstTgt3(_) => (FrameKind::Rst, 1),
+            Instruction::Ret | Instruction::RetCond(_) | Instruction::Reti => {
+                if self.registers.sp == sp.wrapping_add(2) {
+                    self.call_stack.ret(mmu.banked(pc), sp, self.registers.pc);
+                }
+                return;
+            }
+            _ => return,
+        };
+
+        if self.registers.sp == sp.wrapping_sub(2) {
+            self.call_stack.call(Frame {
+                kind,
+                caller: mmu.banked(pc),
+                entry: mmu.banked(self.registers.pc),
+                return_addr: pc.wrapping_add(length),
+                sp: self.registers.sp,
+            });
+        }
+    }
+
     fn ins(&self, mmu: &MMU) -> (Instruction, u16, u8) {
-        let opcode = mmu.read_byte(self.registers.pc);
-        let arg_1 = mmu.read_byte(self.registers.pc + 1);
-        let arg_2 = mmu.read_byte(self.registers.pc + 2);
+        let pc = self.registers.pc;
+        let opcode = mmu.peek_byte(pc);
+        let arg_1 = mmu.peek_byte(pc.wrapping_add(1));
+        let arg_2 = mmu.peek_byte(pc.wrapping_add(2));
+        let (instruction, length, cycles) = parse(opcode, arg_1, arg_2);
+
+        // Only the bytes the instruction is made of are actually read, so
+        // only they can set off a watchpoint
+        for offset in 0..length {
+            mmu.fetch_byte(pc.wrapping_add(offset));
+        }
 
-        parse(opcode, arg_1, arg_2)
+        (instruction, length, cycles)
     }
 
     fn set_r8_byte(&mut self, mmu: &mut MMU, reg: R8, value: u8) {
@@ -236,3 +297,183 @@ impl CPU {
         (big << 8) | little
     }
 }
+
+impl SaveState for CPU {
+    fn save(&self, state: &mut StateWriter) {
+        self.registers.save(state);
+        state.bool(self.ime);
+        self.call_stack.save(state);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.registers.load(state)?;
+        self.ime = state.bool()?;
+        self.call_stack.load(state)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::call_stack::FrameKind;
+    use super::CPU;
+    use crate::gameboy::model::Model;
+    use crate::mmu::watchpoint::{Access, ValueFilter, Watchpoint};
+    use crate::mmu::MMU;
+
+    // A ROM only cartridge with `code` at 0x150, and nops everywhere else
+    fn mmu_with_code(code: &[u8]) -> MMU {
+        let mut rom = vec![0; 0x8000];
+        rom[0x150..0x150 + code.len()].copy_from_slice(code);
+        MMU::new(rom, Model::Dmg, None)
+    }
+
+    fn watch_reads(mmu: &mut MMU, addr: u16) {
+        mmu.watchpoints.push(Watchpoint {
+            start: addr,
+            end: addr,
+            bank: None,
+            access: Access::Read,
+            filter: ValueFilter::Any,
+        });
+    }
+
+    #[test]
+    fn read_watchpoints_only_see_bytes_of_the_instruction() {
+        // nop, then ld a, $12
+        let mut mmu = mmu_with_code(&[0x00, 0x3E, 0x12]);
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0x150;
+
+        watch_reads(&mut mmu, 0x151);
+        watch_reads(&mut mmu, 0x152);
+
+        cpu.step(&mut mmu);
+        assert_eq!(mmu.take_watch_hit(), None);
+
+        cpu.step(&mut mmu);
+        let hit = mmu.take_watch_hit().unwrap();
+        assert_eq!(hit.addr.addr, 0x151);
+        assert_eq!(hit.new, 0x3E);
+    }
+
+    #[test]
+    fn only_the_highest_priority_interrupt_is_serviced() {
+        let mut mmu = mmu_with_code(&[]);
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0x150;
+        cpu.registers.sp = 0xFFFE;
+        cpu.ime = true;
+
+        mmu.ie = 0x05;
+        mmu.ppu.vblank_irq = true;
+        mmu.timer.timer_irq = true;
+
+        cpu.step(&mut mmu);
+        assert_eq!(cpu.registers.pc, 0x40);
+        assert!(!cpu.ime);
+        assert!(!mmu.ppu.vblank_irq);
+        assert!(mmu.timer.timer_irq);
+        assert_eq!(cpu.registers.sp, 0xFFFC);
+    }
+
+    // Where each frame on the call stack went, innermost last
+    fn call_stack(cpu: &CPU) -> Vec<(FrameKind, u16, u16)> {
+        cpu.call_stack
+            .frames()
+            .iter()
+            .map(|frame| (frame.kind, frame.entry.addr, frame.return_addr))
+            .collect()
+    }
+
+    #[test]
+    fn call_stack_follows_calls_and_returns() {
+        let mut rom = vec![0; 0x8000];
+        // call $0160
+        rom[0x150..0x153].copy_from_slice(&[0xCD, 0x60, 0x01]);
+        // rst $08, then ret
+        rom[0x160..0x162].copy_from_slice(&[0xCF, 0xC9]);
+        // ret
+        rom[0x08] = 0xC9;
+        // reti
+        rom[0x40] = 0xD9;
+
+        let mut mmu = MMU::new(rom, Model::Dmg, None);
+        mmu.write_byte(0xFF50, 0x01);
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0x150;
+        cpu.registers.sp = 0xFFFE;
+
+        cpu.step(&mut mmu);
+        assert_eq!(call_stack(&cpu), [(FrameKind::Call, 0x160, 0x153)]);
+
+        cpu.step(&mut mmu);
+        assert_eq!(
+            call_stack(&cpu),
+            [
+                (FrameKind::Call, 0x160, 0x153),
+                (FrameKind::Rst, 0x08, 0x161)
+            ]
+        );
+
+        cpu.step(&mut mmu);
+        assert_eq!(call_stack(&cpu), [(FrameKind::Call, 0x160, 0x153)]);
+
+        cpu.step(&mut mmu);
+        assert_eq!(call_stack(&cpu), []);
+        assert_eq!(cpu.registers.pc, 0x153);
+
+        // The nop at 0x153 runs, then the interrupt comes in
+        cpu.ime = true;
+        mmu.ie = 0x01;
+        mmu.ppu.vblank_irq = true;
+        cpu.step(&mut mmu);
+        assert_eq!(call_stack(&cpu), [(FrameKind::Interrupt, 0x40, 0x154)]);
+
+        cpu.step(&mut mmu);
+        assert_eq!(call_stack(&cpu), []);
+        assert_eq!(cpu.registers.pc, 0x154);
+        assert_eq!(cpu.call_stack.problem, None);
+    }
+
+    #[test]
+    fn call_stack_notices_returns_without_calls() {
+        // ld bc, $0200, push bc, ret
+        let mut mmu = mmu_with_code(&[0x01, 0x00, 0x02, 0xC5, 0xC9]);
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0x150;
+        cpu.registers.sp = 0xFFFE;
+
+        for _ in 0..3 {
+            cpu.step(&mut mmu);
+        }
+        assert_eq!(cpu.registers.pc, 0x200);
+        assert_eq!(call_stack(&cpu), []);
+        assert!(cpu
+            .call_stack
+            .problem
+            .unwrap()
+            .contains("has no matching call"));
+    }
+
+    #[test]
+    fn call_stack_drops_calls_skipped_by_moving_sp() {
+        // call $0160, which does call $0170, which does inc sp, inc sp, ret
+        // straight back to 0x153
+        let mut rom = vec![0; 0x8000];
+        rom[0x150..0x153].copy_from_slice(&[0xCD, 0x60, 0x01]);
+        rom[0x160..0x163].copy_from_slice(&[0xCD, 0x70, 0x01]);
+        rom[0x170..0x173].copy_from_slice(&[0x33, 0x33, 0xC9]);
+        let mut mmu = MMU::new(rom, Model::Dmg, None);
+        let mut cpu = CPU::new();
+        cpu.registers.pc = 0x150;
+        cpu.registers.sp = 0xFFFE;
+
+        for _ in 0..5 {
+            cpu.step(&mut mmu);
+        }
+        assert_eq!(cpu.registers.pc, 0x153);
+        assert_eq!(call_stack(&cpu), []);
+        assert!(cpu.call_stack.problem.unwrap().contains("skipped"));
+    }
+}
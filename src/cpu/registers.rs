@@ -1,8 +1,10 @@
 use std::fmt;
 
 use crate::{
-    debugger::is_gameboy_doctor,
+    cartridge::header::CartridgeHeader,
+    gameboy::model::Model,
     instructions::{r16::R16, r16mem::R16mem, r16stk::R16stk, r8::R8},
+    state::{SaveState, StateReader, StateWriter},
 };
 
 use super::flag_register::FlagsRegister;
@@ -28,33 +30,82 @@ impl Default for Registers {
 
 impl Registers {
     pub fn new() -> Registers {
-        if is_gameboy_doctor() {
-            Registers {
-                a: 0x1,
-                f: FlagsRegister::from(0xB0),
-                b: 0,
-                c: 0x13,
-                d: 0,
-                e: 0xD8,
-                h: 0x01,
-                l: 0x4D,
-                sp: 0xFFFE,
-                pc: 0x100,
-            }
+        Registers {
+            a: 0,
+            b: 0,
+            c: 0,
+            d: 0,
+            e: 0,
+            f: FlagsRegister::from(0),
+            h: 0,
+            l: 0,
+            sp: 0,
+            pc: 0,
+        }
+    }
+
+    /// The register values the given model's boot ROM hands over to the
+    /// game. Games look at A (and B on the CGB) to tell which model they are
+    /// running on.
+    pub fn after_boot(model: Model, header: &CartridgeHeader) -> Registers {
+        // The DMG boot ROMs leave the flags from comparing the header
+        // checksum
+        let checksum_flags = if header.header_checksum() == 0 {
+            0x80
         } else {
-            Registers {
-                a: 0,
-                b: 0,
-                c: 0,
-                d: 0,
-                e: 0,
-                f: FlagsRegister::from(0),
-                h: 0,
-                l: 0,
-                sp: 0,
-                pc: 0,
+            0xB0
+        };
+
+        let (a, f, bc, de, hl) = match model {
+            Model::Dmg0 => (0x01, 0x00, 0xFF13, 0x00C1, 0x8403),
+            Model::Dmg => (0x01, checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Mgb => (0xFF, checksum_flags, 0x0013, 0x00D8, 0x014D),
+            Model::Sgb => (0x01, 0x00, 0x0014, 0x0000, 0xC060),
+            Model::Sgb2 => (0xFF, 0x00, 0x0014, 0x0000, 0xC060),
+            Model::Cgb | Model::Agb => {
+                let (b, de, hl) = if header.supports_cgb() {
+                    (0x00, 0xFF56, 0x000D)
+                } else {
+                    // For DMG games B holds the title checksum used to pick
+                    // colours
+                    let b = if header.is_nintendo_licensed() {
+                        header.title_checksum()
+                    } else {
+                        0
+                    };
+                    let hl = if b == 0x43 || b == 0x58 {
+                        0x991A
+                    } else {
+                        0x007C
+                    };
+                    (b, 0x0008, hl)
+                };
+
+                // The AGB boot ROM finishes with an extra INC B, which games
+                // use to tell they are running on a GBA
+                let (b, f) = if model == Model::Agb {
+                    let zero = (b == 0xFF) as u8;
+                    let half_carry = (b & 0xF == 0xF) as u8;
+                    (b.wrapping_add(1), (zero << 7) | (half_carry << 5))
+                } else {
+                    (b, 0x80)
+                };
+
+                (0x11, f, (b as u16) << 8, de, hl)
             }
-        }
+        };
+
+        let mut registers = Registers {
+            a,
+            f: FlagsRegister::from(f),
+            sp: 0xFFFE,
+            pc: 0x100,
+            ..Registers::new()
+        };
+        registers.set_r16(R16::BC, bc);
+        registers.set_r16(R16::DE, de);
+        registers.set_r16(R16::HL, hl);
+        registers
     }
 
     pub fn set_r16(&mut self, register: R16, value: u16) {
@@ -150,6 +201,25 @@ impl Registers {
     }
 }
 
+impl SaveState for Registers {
+    fn save(&self, state: &mut StateWriter) {
+        for register in [R16stk::AF, R16stk::BC, R16stk::DE, R16stk::HL] {
+            state.u16(self.get_r16_stk(register));
+        }
+        state.u16(self.sp);
+        state.u16(self.pc);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        for register in [R16stk::AF, R16stk::BC, R16stk::DE, R16stk::HL] {
+            self.set_r16_stk(register, state.u16()?);
+        }
+        self.sp = state.u16()?;
+        self.pc = state.u16()?;
+        Ok(())
+    }
+}
+
 impl fmt::Debug for Registers {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("Registers")
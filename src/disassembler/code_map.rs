@@ -0,0 +1,576 @@
+use std::collections::{BTreeMap, HashMap};
+
+use super::{read_rom, Line};
+use crate::cartridge::{rom_offset, ROM_BANK_SIZE};
+use crate::instructions::r16::R16;
+use crate::instructions::r16mem::R16mem;
+use crate::instructions::r16stk::R16stk;
+use crate::instructions::r8::R8;
+use crate::instructions::Instruction;
+use crate::mmu::address::BankedAddress;
+use crate::mmu::code_data_log::CodeDataLog;
+
+// Where the boot ROM hands over, then the RST and interrupt vectors
+const ENTRY_POINT: usize = 0x0100;
+const VECTORS: [usize; 13] = [
+    0x00, 0x08, 0x10, 0x18, 0x20, 0x28, 0x30, 0x38, 0x40, 0x48, 0x50, 0x58, 0x60,
+];
+
+// The logo, title and the rest of the cartridge header
+const HEADER: std::ops::Range<usize> = 0x0104..0x0150;
+
+// More than this and it's probably not a jump table
+const MAX_TABLE_ENTRIES: usize = 256;
+
+// How far to look into a subroutine to see if it jumps through a table
+const MAX_ROUTINE_LENGTH: usize = 24;
+
+/// What a byte of ROM turned out to be.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ByteKind {
+    Unknown,
+
+    /// The first byte of an instruction
+    Opcode,
+    Operand,
+
+    /// Known not to be code, like the cartridge header
+    Data,
+
+    /// A code address in a jump table, low byte first
+    Pointer,
+    PointerHigh,
+}
+
+/// How an address is reached, which its label is named after. Later ones
+/// take precedence.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Reference {
+    Branch,
+    Jump,
+    Call,
+    Table,
+    Vector,
+}
+
+/// Which bytes of a ROM are code, found by following jumps and calls from
+/// the entry point, the RST and interrupt vectors and any code a code/data
+/// log saw run. Everything else is taken to be data. Positions are offsets
+/// into the ROM.
+pub struct CodeMap {
+    pub kinds: Vec<ByteKind>,
+    pub references: BTreeMap<usize, Reference>,
+
+    /// Where each jump, call and table entry goes, when it's known
+    pub targets: HashMap<usize, usize>,
+}
+
+/// Subroutines that jump through a table instead of returning.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Routine {
+    Plain,
+
+    /// Pops its return address and jumps through the table there, so the
+    /// table comes right after the call
+    InlineTable,
+
+    /// Jumps through a table the caller points HL at
+    HlTable,
+}
+
+/// What's known about the registers partway through a run of code.
+#[derive(Default)]
+struct Registers {
+    a: Option<u8>,
+
+    // The ROM bank last written to the MBC
+    bank: Option<u16>,
+
+    // The last address loaded into HL, and whether HL still holds it or has
+    // been read through since
+    table: Option<u16>,
+    hl_intact: bool,
+    read_through_hl: bool,
+}
+
+impl Registers {
+    fn update(&mut self, instruction: &Instruction) {
+        match *instruction {
+            Instruction::LdR8Imm8(R8::A, value) => self.a = Some(value),
+            Instruction::XorAR8(R8::A) => self.a = Some(0),
+            _ if writes_a(instruction) => self.a = None,
+            _ => (),
+        }
+
+        match *instruction {
+            Instruction::LdImm16memA(0x2000..=0x3FFF) => {
+                // The MBC maps bank 1 for bank 0
+                self.bank = self.a.map(|bank| (bank as u16).max(1));
+            }
+            Instruction::LdR16Imm16(R16::HL, addr) => {
+                self.table = Some(addr);
+                self.hl_intact = true;
+                self.read_through_hl = false;
+            }
+            Instruction::LdR8R8(_, R8::HL)
+            | Instruction::LdAR16mem(R16mem::HLI)
+            | Instruction::LdAR16mem(R16mem::HLD) => {
+                self.read_through_hl = true;
+                self.hl_intact = false;
+            }
+            _ if writes_hl(instruction) => self.hl_intact = false,
+            _ => (),
+        }
+    }
+}
+
+fn writes_a(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::LdAImm16mem(_)
+            | Instruction::LdAR16mem(_)
+            | Instruction::LdR8Imm8(R8::A, _)
+            | Instruction::LdR8R8(R8::A, _)
+            | Instruction::LdhACmem
+            | Instruction::LdhAImm8mem(_)
+            | Instruction::AdcAImm8(_)
+            | Instruction::AdcAR8(_)
+            | Instruction::AddAImm8(_)
+            | Instruction::AddAR8(_)
+            | Instruction::AndAImm8(_)
+            | Instruction::AndAR8(_)
+            | Instruction::OrAImm8(_)
+            | Instruction::OrAR8(_)
+            | Instruction::SbcAImm8(_)
+            | Instruction::SbcAR8(_)
+            | Instruction::SubAImm8(_)
+            | Instruction::SubAR8(_)
+            | Instruction::XorAImm8(_)
+            | Instruction::XorAR8(_)
+            | Instruction::IncR8(R8::A)
+            | Instruction::DecR8(R8::A)
+            | Instruction::Rlca
+            | Instruction::Rrca
+            | Instruction::Rla
+            | Instruction::Rra
+            | Instruction::Daa
+            | Instruction::Cpl
+            | Instruction::RlcR8(R8::A)
+            | Instruction::RrcR8(R8::A)
+            | Instruction::RlR8(R8::A)
+            | Instruction::RrR8(R8::A)
+            | Instruction::SlaR8(R8::A)
+            | Instruction::SraR8(R8::A)
+            | Instruction::SwapR8(R8::A)
+            | Instruction::SrlR8(R8::A)
+            | Instruction::ResB3R8(_, R8::A)
+            | Instruction::SetB3R8(_, R8::A)
+            | Instruction::PopR16stk(R16stk::AF)
+    )
+}
+
+fn writes_hl(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::LdR16Imm16(R16::HL, _)
+            | Instruction::LdHlSpImm8(_)
+            | Instruction::LdR8Imm8(R8::H | R8::L, _)
+            | Instruction::LdR8R8(R8::H | R8::L, _)
+            | Instruction::LdR16memA(R16mem::HLI | R16mem::HLD)
+            | Instruction::AddHlR16(_)
+            | Instruction::IncR16(R16::HL)
+            | Instruction::DecR16(R16::HL)
+            | Instruction::IncR8(R8::H | R8::L)
+            | Instruction::DecR8(R8::H | R8::L)
+            | Instruction::PopR16stk(R16stk::HL)
+    )
+}
+
+/// The bank and address a ROM offset is mapped at.
+pub fn rom_address(offset: usize) -> BankedAddress {
+    let bank = (offset / ROM_BANK_SIZE) as u16;
+    let addr = (offset % ROM_BANK_SIZE) as u16 + if bank == 0 { 0x0000 } else { 0x4000 };
+    BankedAddress::in_bank(bank, addr)
+}
+
+/// Decode the instruction at a ROM offset.
+pub fn decode_rom(rom: &[u8], offset: usize) -> Line {
+    let at = rom_address(offset);
+    let bank = at.bank.unwrap_or(0);
+    Line::decode(at, |addr| read_rom(rom, bank, addr))
+}
+
+struct Analyser<'a> {
+    rom: &'a [u8],
+    log: Option<&'a CodeDataLog>,
+    map: CodeMap,
+    routines: HashMap<usize, Routine>,
+
+    // Where code starts, and whether that's only a guess
+    queue: Vec<(usize, bool)>,
+
+    // The bank at 0x4000 when the ROM is too small to switch banks
+    fixed_bank: Option<u16>,
+}
+
+impl Analyser<'_> {
+    fn decode(&self, offset: usize) -> Line {
+        decode_rom(self.rom, offset)
+    }
+
+    /// The ROM offset of an address that code in `bank` jumps to, if it's in
+    /// ROM and the bank can be worked out.
+    fn resolve(&self, bank: u16, addr: u16, switched: Option<u16>) -> Option<usize> {
+        let bank = match addr {
+            0x0000..=0x3FFF => 0,
+            0x4000..=0x7FFF if bank != 0 => switched.unwrap_or(bank),
+            0x4000..=0x7FFF => switched.or(self.fixed_bank)?,
+            _ => return None,
+        };
+
+        let offset = rom_offset(bank, addr);
+        (offset < self.rom.len()).then_some(offset)
+    }
+
+    /// Whether the code/data log saw any of these bytes read but never run.
+    fn read_only(&self, offsets: std::ops::Range<usize>) -> bool {
+        self.log
+            .is_some_and(|log| offsets.into_iter().any(|offset| log.is_data(offset)))
+    }
+
+    fn reference(&mut self, from: usize, target: usize, reference: Reference, guessed: bool) {
+        let existing = self.map.references.entry(target).or_insert(reference);
+        *existing = (*existing).max(reference);
+        self.map.targets.insert(from, target);
+        self.queue.push((target, guessed));
+    }
+
+    /// Follow the code from `start` until it jumps away or returns. Guesses,
+    /// like jump table entries, stop at anything the code/data log saw read
+    /// but never run. Code that's jumped or called to directly doesn't, as
+    /// games read their own code and copy loops often read a little past
+    /// the end of what they copy.
+    fn trace(&mut self, start: usize, guessed: bool) {
+        let bank = rom_address(start).bank.unwrap_or(0);
+        let bank_end = ((bank as usize + 1) * ROM_BANK_SIZE).min(self.rom.len());
+        let mut registers = Registers::default();
+        let mut offset = start;
+
+        while offset < bank_end {
+            let line = self.decode(offset);
+            let end = offset + line.length() as usize;
+            if matches!(line.instruction, Instruction::ILLEGAL)
+                || end > bank_end
+                || self.map.kinds[offset..end]
+                    .iter()
+                    .any(|kind| *kind != ByteKind::Unknown)
+                || (guessed && self.read_only(offset..end))
+            {
+                break;
+            }
+
+            self.map.kinds[offset] = ByteKind::Opcode;
+            self.map.kinds[offset + 1..end].fill(ByteKind::Operand);
+
+            let target = line
+                .instruction
+                .target(line.addr.addr)
+                .and_then(|target| self.resolve(bank, target, registers.bank));
+
+            match line.instruction {
+                Instruction::CallImm16(_)
+                | Instruction::CallCondImm16(_, _)
+                | Instruction::RstTgt3(_) => {
+                    if let Some(target) = target {
+                        self.reference(offset, target, Reference::Call, false);
+
+                        match self.routine(target) {
+                            Routine::Plain => (),
+                            Routine::InlineTable => {
+                                self.table(end);
+                                break;
+                            }
+                            Routine::HlTable => {
+                                let table = registers.table.filter(|_| registers.hl_intact);
+                                if let Some(table) = table
+                                    .and_then(|table| self.resolve(bank, table, registers.bank))
+                                {
+                                    self.table(table);
+                                }
+                                break;
+                            }
+                        }
+                    }
+
+                    // Only the bank is likely to be the same afterwards
+                    registers = Registers {
+                        bank: registers.bank,
+                        ..Registers::default()
+                    };
+                    offset = end;
+                    continue;
+                }
+                Instruction::JpImm16(_) | Instruction::JpCondImm16(_, _) => {
+                    if let Some(target) = target {
+                        self.reference(offset, target, Reference::Jump, false);
+                    }
+                }
+                Instruction::JrImm8(_) | Instruction::JrCondImm8(_, _) => {
+                    if let Some(target) = target {
+                        self.reference(offset, target, Reference::Branch, false);
+                    }
+                }
+                Instruction::JpHl => {
+                    let table = registers.table.filter(|_| registers.read_through_hl);
+                    if let Some(table) =
+                        table.and_then(|table| self.resolve(bank, table, registers.bank))
+                    {
+                        self.table(table);
+                    }
+                }
+                _ => (),
+            }
+
+            if matches!(
+                line.instruction,
+                Instruction::JpImm16(_)
+                    | Instruction::JrImm8(_)
+                    | Instruction::JpHl
+                    | Instruction::Ret
+                    | Instruction::Reti
+            ) {
+                break;
+            }
+
+            registers.update(&line.instruction);
+            offset = end;
+        }
+    }
+
+    /// Whether the subroutine at `start` jumps through a table. Only looks
+    /// at the code up to its first jump.
+    fn routine(&mut self, start: usize) -> Routine {
+        if let Some(routine) = self.routines.get(&start) {
+            return *routine;
+        }
+
+        let mut offset = start;
+        let mut first_pop_hl = None;
+        let mut read_through_hl = false;
+        let mut routine = Routine::Plain;
+
+        for _ in 0..MAX_ROUTINE_LENGTH {
+            let line = self.decode(offset);
+            match line.instruction {
+                Instruction::PopR16stk(R16stk::HL) if first_pop_hl.is_none() => {
+                    first_pop_hl = Some(true)
+                }
+                Instruction::PopR16stk(_) | Instruction::PushR16stk(_) => {
+                    first_pop_hl.get_or_insert(false);
+                }
+                Instruction::LdR8R8(_, R8::HL) | Instruction::LdAR16mem(R16mem::HLI) => {
+                    read_through_hl = true
+                }
+                Instruction::JpHl => {
+                    routine = match (first_pop_hl, read_through_hl) {
+                        (Some(true), _) => Routine::InlineTable,
+                        (_, true) => Routine::HlTable,
+                        _ => Routine::Plain,
+                    };
+                    break;
+                }
+                Instruction::CallImm16(_)
+                | Instruction::CallCondImm16(_, _)
+                | Instruction::JpImm16(_)
+                | Instruction::JpCondImm16(_, _)
+                | Instruction::JrImm8(_)
+                | Instruction::JrCondImm8(_, _)
+                | Instruction::RstTgt3(_)
+                | Instruction::Ret
+                | Instruction::RetCond(_)
+                | Instruction::Reti
+                | Instruction::ILLEGAL => break,
+                _ => (),
+            }
+            offset += line.length() as usize;
+        }
+
+        self.routines.insert(start, routine);
+        routine
+    }
+
+    /// Read a table of code addresses at `start`. It ends at the first entry
+    /// that doesn't point at code in reach, or at the first code it points
+    /// to, as that usually comes straight after it.
+    fn table(&mut self, start: usize) {
+        let bank = rom_address(start).bank.unwrap_or(0);
+        let mut end = ((bank as usize + 1) * ROM_BANK_SIZE).min(self.rom.len());
+        let mut offset = start;
+
+        for _ in 0..MAX_TABLE_ENTRIES {
+            if offset + 2 > end
+                || self.map.kinds[offset..offset + 2]
+                    .iter()
+                    .any(|kind| *kind != ByteKind::Unknown)
+            {
+                break;
+            }
+
+            let pointer = u16::from_le_bytes([self.rom[offset], self.rom[offset + 1]]);
+            let Some(target) = self.resolve(bank, pointer, None) else {
+                break;
+            };
+            if (start..offset + 2).contains(&target)
+                || !matches!(self.map.kinds[target], ByteKind::Unknown | ByteKind::Opcode)
+            {
+                break;
+            }
+
+            self.map.kinds[offset] = ByteKind::Pointer;
+            self.map.kinds[offset + 1] = ByteKind::PointerHigh;
+            self.reference(offset, target, Reference::Jump, true);
+            if target > start {
+                end = end.min(target);
+            }
+
+            offset += 2;
+        }
+
+        if offset > start {
+            self.map.references.insert(start, Reference::Table);
+        }
+    }
+}
+
+impl CodeMap {
+    /// Work out which bytes of a ROM are code. Code a code/data log saw run
+    /// is followed too, and before anything else. What it saw read but never
+    /// run is data, unless code leads straight into it.
+    pub fn analyse(rom: &[u8], log: Option<&CodeDataLog>) -> CodeMap {
+        let mut analyser = Analyser {
+            rom,
+            log,
+            map: CodeMap {
+                kinds: vec![ByteKind::Unknown; rom.len()],
+                references: BTreeMap::new(),
+                targets: HashMap::new(),
+            },
+            routines: HashMap::new(),
+            queue: Vec::new(),
+            fixed_bank: (rom.len() <= 2 * ROM_BANK_SIZE).then_some(1),
+        };
+
+        if rom.len() >= HEADER.end {
+            analyser.map.kinds[HEADER].fill(ByteKind::Data);
+        }
+
+        for vector in VECTORS.into_iter().chain([ENTRY_POINT]) {
+            if vector < rom.len() {
+                analyser.map.references.insert(vector, Reference::Vector);
+                analyser.queue.push((vector, false));
+            }
+        }
+
+        // Everything that ran is traced before any of what it leads to, so
+        // guesses can't decode it out of step
+        if let Some(log) = log {
+            let ran = (0..rom.len()).filter(|&offset| log.flags(offset) & CodeDataLog::OPCODE != 0);
+            for start in ran {
+                analyser.trace(start, false);
+            }
+        }
+
+        while let Some((start, guessed)) = analyser.queue.pop() {
+            analyser.trace(start, guessed);
+        }
+
+        if let Some(log) = log {
+            for (offset, kind) in analyser.map.kinds.iter_mut().enumerate() {
+                if *kind == ByteKind::Unknown && log.is_data(offset) {
+                    *kind = ByteKind::Data;
+                }
+            }
+        }
+
+        analyser.map
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ByteKind, CodeMap};
+    use crate::mmu::code_data_log::CodeDataLog;
+
+    // The entry point jumps through a table of three routines
+    fn rom() -> Vec<u8> {
+        let mut rom = vec![0xFF; 0x8000];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            rom[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+
+        // jp $0150
+        put(0x100, &[0xC3, 0x50, 0x01]);
+
+        // ld hl, $0160; ld a, [hl+]; ld h, [hl]; ld l, a; jp hl
+        put(0x150, &[0x21, 0x60, 0x01, 0x2A, 0x66, 0x6F, 0xE9]);
+        put(0x160, &[0x70, 0x01, 0x80, 0x01, 0xEF, 0x00]);
+
+        // ret, nop; ret, and ld a, $00; ret which is really a nop at $00F0
+        put(0x170, &[0xC9]);
+        put(0x180, &[0x00, 0xC9]);
+        put(0x0EF, &[0x3E, 0x00, 0xC9]);
+        rom
+    }
+
+    #[test]
+    fn follows_jump_tables() {
+        let map = CodeMap::analyse(&rom(), None);
+
+        assert_eq!(map.kinds[0x160], ByteKind::Pointer);
+        assert_eq!(map.kinds[0x170], ByteKind::Opcode);
+        assert_eq!(map.kinds[0x180], ByteKind::Opcode);
+        assert_eq!(map.kinds[0x0EF], ByteKind::Opcode);
+        assert_eq!(map.kinds[0x0F0], ByteKind::Operand);
+    }
+
+    #[test]
+    fn code_that_ran_comes_before_guesses() {
+        let rom = rom();
+        let log = CodeDataLog::new(rom.len());
+        log.mark(0x150, CodeDataLog::OPCODE);
+        log.mark(0x0F0, CodeDataLog::OPCODE);
+        log.mark(0x0F1, CodeDataLog::OPCODE);
+
+        // The table the code at $0150 jumps through is found before the code
+        // at $00F0 would be traced, if it wasn't traced first
+        let map = CodeMap::analyse(&rom, Some(&log));
+        assert_eq!(map.kinds[0x0EF], ByteKind::Unknown);
+        assert_eq!(map.kinds[0x0F0], ByteKind::Opcode);
+        assert_eq!(map.kinds[0x0F1], ByteKind::Opcode);
+    }
+
+    #[test]
+    fn guesses_stop_at_data() {
+        let rom = rom();
+        let log = CodeDataLog::new(rom.len());
+        log.mark(0x180, CodeDataLog::DATA);
+        log.mark(0x181, CodeDataLog::DATA);
+
+        let map = CodeMap::analyse(&rom, Some(&log));
+        assert_eq!(map.kinds[0x170], ByteKind::Opcode);
+        assert_eq!(map.kinds[0x180], ByteKind::Data);
+        assert_eq!(map.kinds[0x181], ByteKind::Data);
+    }
+
+    #[test]
+    fn direct_jumps_go_through_data() {
+        // Copy loops read past the end of what they copy, into code
+        let rom = rom();
+        let log = CodeDataLog::new(rom.len());
+        log.mark(0x150, CodeDataLog::DATA);
+
+        let map = CodeMap::analyse(&rom, Some(&log));
+        assert_eq!(map.kinds[0x150], ByteKind::Opcode);
+        assert_eq!(map.kinds[0x170], ByteKind::Opcode);
+    }
+}
@@ -0,0 +1,111 @@
+pub mod code_map;
+pub mod rgbds;
+
+use std::fmt::{self, Write};
+
+use crate::cartridge::{rom_offset, ROM_BANK_SIZE};
+use crate::gameboy::symbols::Symbols;
+use crate::instructions::{parse, Instruction};
+use crate::mmu::address::{region_start, BankedAddress};
+
+/// An instruction decoded from memory, along with its bytes.
+pub struct Line {
+    pub addr: BankedAddress,
+    pub bytes: Vec<u8>,
+    pub instruction: Instruction,
+}
+
+impl Line {
+    /// Decode the instruction at `addr`, reading memory with `read`.
+    pub fn decode(addr: BankedAddress, read: impl Fn(u16) -> u8) -> Line {
+        let at = |offset: u16| read(addr.addr.wrapping_add(offset));
+        let (instruction, length, _) = parse(at(0), at(1), at(2));
+
+        Line {
+            addr,
+            bytes: (0..length).map(at).collect(),
+            instruction,
+        }
+    }
+
+    pub fn length(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self
+            .bytes
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<Vec<String>>()
+            .join(" ");
+
+        write!(f, "{}  {:<8}  {}", self.addr, bytes, self.instruction)
+    }
+}
+
+/// Read from a ROM bank as it's mapped, with padding past the end of it.
+pub fn read_rom(rom: &[u8], bank: u16, addr: u16) -> u8 {
+    let in_bank = match bank {
+        0 => addr < 0x4000,
+        _ => (0x4000..0x8000).contains(&addr),
+    };
+
+    match in_bank {
+        true => rom.get(rom_offset(bank, addr)).copied().unwrap_or(0xFF),
+        false => 0xFF,
+    }
+}
+
+/// Where an instruction at `at` goes to `target`, without knowing what's
+/// mapped. Targets in the same part of memory are in the same bank, and
+/// anywhere else in ROM or RAM is in the bank mapped at start up.
+fn static_target(at: BankedAddress, target: u16) -> BankedAddress {
+    match target {
+        _ if region_start(target) == region_start(at.addr) => BankedAddress {
+            bank: at.bank,
+            addr: target,
+        },
+        0x4000..=0x7FFF => BankedAddress::new(target),
+        0xD000..=0xDFFF => BankedAddress::in_bank(1, target),
+        _ => BankedAddress::in_bank(0, target),
+    }
+}
+
+/// A listing of one bank of a ROM, decoding every byte of it as code. Labels
+/// from `symbols` go on lines of their own and after the instructions that
+/// refer to them.
+pub fn bank_listing(rom: &[u8], bank: u16, symbols: &Symbols) -> Result<String, String> {
+    let banks = rom.len().div_ceil(ROM_BANK_SIZE);
+    if bank as usize >= banks {
+        return Err(format!("The ROM only has {} banks", banks));
+    }
+
+    let start: u32 = if bank == 0 { 0x0000 } else { 0x4000 };
+    let end = start + ROM_BANK_SIZE as u32;
+    let read = |addr: u16| read_rom(rom, bank, addr);
+
+    let mut listing = String::new();
+    let mut addr = start;
+    while addr < end {
+        let line = Line::decode(BankedAddress::in_bank(bank, addr as u16), read);
+
+        if let Some(label) = symbols.label_at(line.addr) {
+            writeln!(listing, "{}:", label).unwrap();
+        }
+
+        let target = line
+            .instruction
+            .target(line.addr.addr)
+            .and_then(|target| symbols.label(static_target(line.addr, target)))
+            .map(|label| format!(" <{}>", label))
+            .unwrap_or_default();
+        writeln!(listing, "    {}{}", line, target).unwrap();
+
+        addr += line.length() as u32;
+    }
+
+    Ok(listing)
+}
@@ -0,0 +1,510 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use super::code_map::{decode_rom, rom_address, ByteKind, CodeMap, Reference};
+use super::Line;
+use crate::cartridge::{rom_offset, ROM_BANK_SIZE};
+use crate::gameboy::symbols::Symbols;
+use crate::instructions::Instruction;
+
+// Runs of the same byte at least this long are written with `ds`
+const MIN_FILL: usize = 16;
+const BYTES_PER_LINE: usize = 16;
+
+fn vector_name(addr: u16) -> String {
+    match addr {
+        0x0100 => "Entry".to_string(),
+        0x0040 => "VBlankInterrupt".to_string(),
+        0x0048 => "StatInterrupt".to_string(),
+        0x0050 => "TimerInterrupt".to_string(),
+        0x0058 => "SerialInterrupt".to_string(),
+        0x0060 => "JoypadInterrupt".to_string(),
+        _ => format!("RST_{:02X}", addr),
+    }
+}
+
+/// Whether RGBDS takes `name` as a label. Local labels are left out, as they
+/// need their parent label before them.
+fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_@#$".contains(c))
+}
+
+fn data(bytes: &[u8]) -> String {
+    let bytes = bytes
+        .iter()
+        .map(|b| format!("${:02X}", b))
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!("db {}", bytes)
+}
+
+fn name<T: std::fmt::Debug>(operand: T) -> String {
+    format!("{:?}", operand).to_lowercase()
+}
+
+fn signed(value: u8) -> String {
+    match value as i8 {
+        offset if offset < 0 => format!("-{}", offset.unsigned_abs()),
+        offset => format!("{}", offset),
+    }
+}
+
+struct Writer<'a> {
+    rom: &'a [u8],
+    map: &'a CodeMap,
+    labels: HashMap<usize, String>,
+}
+
+impl Writer<'_> {
+    /// Labels from the symbols where they can go, and generated ones for
+    /// everything else code refers to. Labels can't go inside instructions
+    /// or jump table entries.
+    fn labels(rom: &[u8], map: &CodeMap, symbols: &Symbols) -> HashMap<usize, String> {
+        let mut labels = HashMap::new();
+        let mut names = HashSet::new();
+        let placeable = |offset: usize| {
+            offset < rom.len()
+                && !matches!(map.kinds[offset], ByteKind::Operand | ByteKind::PointerHigh)
+        };
+
+        for (addr, label) in symbols.labels() {
+            let offset = rom_offset(addr.bank.unwrap_or(0), addr.addr);
+            if rom_address(offset) != addr
+                || !placeable(offset)
+                || !is_label(label)
+                || labels.contains_key(&offset)
+                || !names.insert(label.to_string())
+            {
+                continue;
+            }
+            labels.insert(offset, label.to_string());
+        }
+
+        for (&offset, reference) in map.references.iter() {
+            if labels.contains_key(&offset) || !placeable(offset) {
+                continue;
+            }
+
+            let at = rom_address(offset);
+            let suffix = format!("{:02X}_{:04X}", at.bank.unwrap_or(0), at.addr);
+            let label = match reference {
+                Reference::Vector => vector_name(at.addr),
+                Reference::Table => format!("JumpTable_{}", suffix),
+                Reference::Call => format!("Call_{}", suffix),
+                Reference::Jump => format!("Jump_{}", suffix),
+                Reference::Branch => format!("Branch_{}", suffix),
+            };
+            if names.insert(label.clone()) {
+                labels.insert(offset, label);
+            }
+        }
+
+        labels
+    }
+
+    /// The label something at `offset` goes to, or its address.
+    fn target(&self, offset: usize, addr: u16) -> String {
+        self.map
+            .targets
+            .get(&offset)
+            .and_then(|target| self.labels.get(target))
+            .cloned()
+            .unwrap_or_else(|| format!("${:04X}", addr))
+    }
+
+    fn instruction(&self, offset: usize, line: &Line) -> String {
+        match line.instruction {
+            Instruction::Nop => "nop".to_string(),
+            Instruction::Stop if line.bytes[1] == 0 => "stop".to_string(),
+
+            // Older versions of RGBDS put a NOP after HALT, and turn these
+            // into LDH, so they're written as bytes
+            Instruction::Halt => format!("{} ; halt", data(&line.bytes)),
+            Instruction::LdAImm16mem(0xFF00..) | Instruction::LdImm16memA(0xFF00..) => {
+                format!("{} ; {}", data(&line.bytes), self.load(&line.instruction))
+            }
+
+            Instruction::LdAImm16mem(_)
+            | Instruction::LdAR16mem(_)
+            | Instruction::LdHlSpImm8(_)
+            | Instruction::LdImm16memA(_)
+            | Instruction::LdImm16memSp(_)
+            | Instruction::LdR16Imm16(_, _)
+            | Instruction::LdR16memA(_)
+            | Instruction::LdR8Imm8(_, _)
+            | Instruction::LdR8R8(_, _)
+            | Instruction::LdSpHl
+            | Instruction::LdhACmem
+            | Instruction::LdhAImm8mem(_)
+            | Instruction::LdhCmemA
+            | Instruction::LdhImm8memA(_) => self.load(&line.instruction),
+
+            Instruction::AdcAImm8(n) => format!("adc a, ${:02X}", n),
+            Instruction::AdcAR8(r) => format!("adc a, {}", name(r)),
+            Instruction::AddAImm8(n) => format!("add a, ${:02X}", n),
+            Instruction::AddAR8(r) => format!("add a, {}", name(r)),
+            Instruction::AddHlR16(r) => format!("add hl, {}", name(r)),
+            Instruction::AddSpImm8(e) => format!("add sp, {}", signed(e)),
+            Instruction::AndAImm8(n) => format!("and ${:02X}", n),
+            Instruction::AndAR8(r) => format!("and {}", name(r)),
+            Instruction::CpAImm8(n) => format!("cp ${:02X}", n),
+            Instruction::CpAR8(r) => format!("cp {}", name(r)),
+            Instruction::DecR16(r) => format!("dec {}", name(r)),
+            Instruction::DecR8(r) => format!("dec {}", name(r)),
+            Instruction::IncR16(r) => format!("inc {}", name(r)),
+            Instruction::IncR8(r) => format!("inc {}", name(r)),
+            Instruction::OrAImm8(n) => format!("or ${:02X}", n),
+            Instruction::OrAR8(r) => format!("or {}", name(r)),
+            Instruction::SbcAImm8(n) => format!("sbc a, ${:02X}", n),
+            Instruction::SbcAR8(r) => format!("sbc a, {}", name(r)),
+            Instruction::SubAImm8(n) => format!("sub ${:02X}", n),
+            Instruction::SubAR8(r) => format!("sub {}", name(r)),
+            Instruction::XorAImm8(n) => format!("xor ${:02X}", n),
+            Instruction::XorAR8(r) => format!("xor {}", name(r)),
+
+            Instruction::Rlca => "rlca".to_string(),
+            Instruction::Rrca => "rrca".to_string(),
+            Instruction::Rla => "rla".to_string(),
+            Instruction::Rra => "rra".to_string(),
+            Instruction::Daa => "daa".to_string(),
+            Instruction::Cpl => "cpl".to_string(),
+            Instruction::Scf => "scf".to_string(),
+            Instruction::Ccf => "ccf".to_string(),
+            Instruction::RlcR8(r) => format!("rlc {}", name(r)),
+            Instruction::RrcR8(r) => format!("rrc {}", name(r)),
+            Instruction::RlR8(r) => format!("rl {}", name(r)),
+            Instruction::RrR8(r) => format!("rr {}", name(r)),
+            Instruction::SlaR8(r) => format!("sla {}", name(r)),
+            Instruction::SraR8(r) => format!("sra {}", name(r)),
+            Instruction::SwapR8(r) => format!("swap {}", name(r)),
+            Instruction::SrlR8(r) => format!("srl {}", name(r)),
+
+            Instruction::CallCondImm16(cond, addr) => {
+                format!("call {}, {}", name(cond), self.target(offset, addr))
+            }
+            Instruction::CallImm16(addr) => format!("call {}", self.target(offset, addr)),
+            Instruction::JpCondImm16(cond, addr) => {
+                format!("jp {}, {}", name(cond), self.target(offset, addr))
+            }
+            Instruction::JpHl => "jp hl".to_string(),
+            Instruction::JpImm16(addr) => format!("jp {}", self.target(offset, addr)),
+
+            // Relative jumps need a label to go to
+            Instruction::JrCondImm8(cond, _) => match self.label_at_target(offset) {
+                Some(label) => format!("jr {}, {}", name(cond), label),
+                None => data(&line.bytes),
+            },
+            Instruction::JrImm8(_) => match self.label_at_target(offset) {
+                Some(label) => format!("jr {}", label),
+                None => data(&line.bytes),
+            },
+
+            Instruction::PopR16stk(r) => format!("pop {}", name(r)),
+            Instruction::PushR16stk(r) => format!("push {}", name(r)),
+            Instruction::Ret => "ret".to_string(),
+            Instruction::RetCond(cond) => format!("ret {}", name(cond)),
+            Instruction::Reti => "reti".to_string(),
+            Instruction::RstTgt3(target) => format!("rst ${:02X}", target),
+
+            Instruction::Di => "di".to_string(),
+            Instruction::Ei => "ei".to_string(),
+
+            Instruction::BitB3R8(bit, r) => format!("bit {}, {}", bit, name(r)),
+            Instruction::ResB3R8(bit, r) => format!("res {}, {}", bit, name(r)),
+            Instruction::SetB3R8(bit, r) => format!("set {}, {}", bit, name(r)),
+
+            Instruction::Stop | Instruction::ILLEGAL => data(&line.bytes),
+        }
+    }
+
+    fn load(&self, instruction: &Instruction) -> String {
+        match *instruction {
+            Instruction::LdAImm16mem(addr) => format!("ld a, [${:04X}]", addr),
+            Instruction::LdAR16mem(r) => format!("ld a, {}", name(r)),
+            Instruction::LdHlSpImm8(e) => match signed(e).strip_prefix('-') {
+                Some(offset) => format!("ld hl, sp - {}", offset),
+                None => format!("ld hl, sp + {}", signed(e)),
+            },
+            Instruction::LdImm16memA(addr) => format!("ld [${:04X}], a", addr),
+            Instruction::LdImm16memSp(addr) => format!("ld [${:04X}], sp", addr),
+            Instruction::LdR16Imm16(r, n) => format!("ld {}, ${:04X}", name(r), n),
+            Instruction::LdR16memA(r) => format!("ld {}, a", name(r)),
+            Instruction::LdR8Imm8(r, n) => format!("ld {}, ${:02X}", name(r), n),
+            Instruction::LdR8R8(to, from) => format!("ld {}, {}", name(to), name(from)),
+            Instruction::LdSpHl => "ld sp, hl".to_string(),
+            Instruction::LdhACmem => "ldh a, [c]".to_string(),
+            Instruction::LdhAImm8mem(n) => format!("ldh a, [$FF{:02X}]", n),
+            Instruction::LdhCmemA => "ldh [c], a".to_string(),
+            Instruction::LdhImm8memA(n) => format!("ldh [$FF{:02X}], a", n),
+            _ => unreachable!(),
+        }
+    }
+
+    fn label_at_target(&self, offset: usize) -> Option<&String> {
+        self.map
+            .targets
+            .get(&offset)
+            .and_then(|target| self.labels.get(target))
+    }
+
+    fn bank(&self, bank: u16) -> String {
+        let start = bank as usize * ROM_BANK_SIZE;
+        let end = (start + ROM_BANK_SIZE).min(self.rom.len());
+
+        let mut source = match bank {
+            0 => "SECTION \"ROM Bank $00\", ROM0[$0000]\n".to_string(),
+            _ => format!(
+                "SECTION \"ROM Bank ${:02X}\", ROMX[$4000], BANK[${:02X}]\n",
+                bank, bank
+            ),
+        };
+
+        let mut offset = start;
+        while offset < end {
+            if let Some(label) = self.labels.get(&offset) {
+                if self.map.references.get(&offset) != Some(&Reference::Branch) {
+                    source.push('\n');
+                }
+                writeln!(source, "{}:", label).unwrap();
+            }
+
+            match self.map.kinds[offset] {
+                ByteKind::Opcode => {
+                    let line = decode_rom(self.rom, offset);
+                    writeln!(source, "    {}", self.instruction(offset, &line)).unwrap();
+                    offset += line.length() as usize;
+                }
+                ByteKind::Pointer => {
+                    let pointer = u16::from_le_bytes([self.rom[offset], self.rom[offset + 1]]);
+                    writeln!(source, "    dw {}", self.target(offset, pointer)).unwrap();
+                    offset += 2;
+                }
+                _ => {
+                    let run = (offset + 1..end)
+                        .find(|&o| {
+                            matches!(self.map.kinds[o], ByteKind::Opcode | ByteKind::Pointer)
+                                || self.labels.contains_key(&o)
+                        })
+                        .unwrap_or(end);
+                    self.data(&mut source, &self.rom[offset..run]);
+                    offset = run;
+                }
+            }
+        }
+
+        source
+    }
+
+    /// Write bytes with `db`, and long runs of the same byte with `ds`.
+    fn data(&self, source: &mut String, bytes: &[u8]) {
+        let mut line_start = 0;
+        let mut i = 0;
+
+        while i < bytes.len() {
+            let fill = bytes[i..].iter().take_while(|b| **b == bytes[i]).count();
+            if fill >= MIN_FILL || i - line_start == BYTES_PER_LINE {
+                if line_start < i {
+                    writeln!(source, "    {}", data(&bytes[line_start..i])).unwrap();
+                }
+                line_start = i;
+            }
+
+            if fill >= MIN_FILL {
+                writeln!(source, "    ds {}, ${:02X}", fill, bytes[i]).unwrap();
+                i += fill;
+                line_start = i;
+            } else {
+                i += 1;
+            }
+        }
+
+        if line_start < bytes.len() {
+            writeln!(source, "    {}", data(&bytes[line_start..])).unwrap();
+        }
+    }
+}
+
+/// RGBDS source that assembles back into `rom`, as a list of file names
+/// and their contents. Everything that isn't decoded as code is written out
+/// byte for byte. Each bank gets a file with its own section, and the main
+/// file, named after `name`, includes them all.
+pub fn rgbds_source(
+    rom: &[u8],
+    map: &CodeMap,
+    symbols: &Symbols,
+    name: &str,
+) -> Vec<(String, String)> {
+    let writer = Writer {
+        rom,
+        map,
+        labels: Writer::labels(rom, map, symbols),
+    };
+
+    let mut main = format!(
+        "; Build with\n;     rgbasm -o {name}.o {name}.asm\n;     rgblink -o {name}.gb {name}.o\n\n"
+    );
+    let mut files = Vec::new();
+
+    for bank in 0..rom.len().div_ceil(ROM_BANK_SIZE) {
+        let file = format!("bank_{:02x}.asm", bank);
+        writeln!(main, "INCLUDE \"{}\"", file).unwrap();
+        files.push((file, writer.bank(bank as u16)));
+    }
+
+    files.insert(0, (format!("{}.asm", name), main));
+    files
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    use super::rgbds_source;
+    use crate::cartridge::ROM_BANK_SIZE;
+    use crate::disassembler::code_map::{decode_rom, ByteKind, CodeMap};
+    use crate::gameboy::symbols::Symbols;
+
+    const ROMS: [&str; 3] = [
+        "tetris.gb",
+        "test_roms/cpu_instrs.gb",
+        "test_roms/dmg-acid2.gb",
+    ];
+
+    fn rom(name: &str) -> Vec<u8> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("roms")
+            .join(name);
+        fs::read(path).unwrap()
+    }
+
+    fn byte(operand: &str) -> u8 {
+        u8::from_str_radix(operand.trim().trim_start_matches('$'), 16).unwrap()
+    }
+
+    /// Walk a bank's source, checking that it accounts for every byte of the
+    /// bank once and in order: data as the bytes themselves, and code as
+    /// whole instructions the analysis decoded.
+    fn check_bank(rom: &[u8], map: &CodeMap, bank: usize, source: &str) {
+        let mut offset = bank * ROM_BANK_SIZE;
+        let end = (offset + ROM_BANK_SIZE).min(rom.len());
+
+        for line in source.lines().skip(1) {
+            let (line, comment) = match line.split_once(';') {
+                Some((line, comment)) => (line.trim(), Some(comment.trim())),
+                None => (line.trim(), None),
+            };
+            if line.is_empty() || line.ends_with(':') {
+                continue;
+            }
+
+            // Instructions written as bytes, with the instruction after them
+            if let (Some(bytes), Some(instruction)) = (line.strip_prefix("db "), comment) {
+                let bytes: Vec<u8> = bytes.split(',').map(byte).collect();
+                assert_eq!(
+                    map.kinds[offset],
+                    ByteKind::Opcode,
+                    "{} at {:#X}",
+                    instruction,
+                    offset
+                );
+                assert_eq!(decode_rom(rom, offset).length() as usize, bytes.len());
+                assert_eq!(rom[offset..offset + bytes.len()], bytes);
+                offset += bytes.len();
+            } else if let Some(bytes) = line.strip_prefix("db ") {
+                for value in bytes.split(',').map(byte) {
+                    assert!(!matches!(
+                        map.kinds[offset],
+                        ByteKind::Opcode | ByteKind::Pointer
+                    ));
+                    assert_eq!(rom[offset], value, "db at {:#X}", offset);
+                    offset += 1;
+                }
+            } else if let Some(fill) = line.strip_prefix("ds ") {
+                let (count, value) = fill.split_once(',').unwrap();
+                let count: usize = count.parse().unwrap();
+                assert!(rom[offset..offset + count]
+                    .iter()
+                    .all(|b| *b == byte(value)));
+                offset += count;
+            } else if line.starts_with("dw ") {
+                assert_eq!(map.kinds[offset], ByteKind::Pointer, "dw at {:#X}", offset);
+                assert_eq!(map.kinds[offset + 1], ByteKind::PointerHigh);
+                offset += 2;
+            } else {
+                assert_eq!(
+                    map.kinds[offset],
+                    ByteKind::Opcode,
+                    "{} at {:#X}",
+                    line,
+                    offset
+                );
+                let length = decode_rom(rom, offset).length() as usize;
+                assert!(map.kinds[offset + 1..offset + length]
+                    .iter()
+                    .all(|kind| *kind == ByteKind::Operand));
+                offset += length;
+            }
+        }
+
+        assert_eq!(offset, end, "bank {} doesn't end where it should", bank);
+    }
+
+    #[test]
+    fn source_covers_every_byte_once() {
+        for name in ROMS {
+            let rom = rom(name);
+            let map = CodeMap::analyse(&rom, None);
+            let files = rgbds_source(&rom, &map, &Symbols::new(), "game");
+
+            assert_eq!(files.len() - 1, rom.len().div_ceil(ROM_BANK_SIZE));
+            for (bank, (_, source)) in files.iter().skip(1).enumerate() {
+                check_bank(&rom, &map, bank, source);
+            }
+        }
+    }
+
+    /// Needs RGBDS, so only runs when asked for with
+    /// `cargo test -- --ignored reassembles_with_rgbds`, as CI does.
+    #[test]
+    #[ignore = "needs RGBDS installed"]
+    fn reassembles_with_rgbds() {
+        for name in ROMS {
+            let rom = rom(name);
+            let map = CodeMap::analyse(&rom, None);
+            let dir: PathBuf = std::env::temp_dir().join(format!(
+                "cowboy-rgbds-{}-{}",
+                std::process::id(),
+                name.replace('/', "-")
+            ));
+            fs::create_dir_all(&dir).unwrap();
+
+            for (file, source) in rgbds_source(&rom, &map, &Symbols::new(), "game") {
+                fs::write(dir.join(file), source).unwrap();
+            }
+
+            let run = |program: &str, args: &[&str]| {
+                let status = Command::new(program)
+                    .args(args)
+                    .current_dir(&dir)
+                    .status()
+                    .unwrap_or_else(|e| panic!("Couldn't run {}: {}", program, e));
+                assert!(status.success(), "{} failed on {}", program, name);
+            };
+            run("rgbasm", &["-o", "game.o", "game.asm"]);
+            run("rgblink", &["-o", "game.gb", "game.o"]);
+
+            assert!(
+                fs::read(dir.join("game.gb")).unwrap() == rom,
+                "{} differs",
+                name
+            );
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
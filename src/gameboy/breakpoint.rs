@@ -0,0 +1,102 @@
+use std::fmt;
+
+use super::expression::Expression;
+use super::GameBoy;
+use crate::mmu::address::BankedAddress;
+
+/// Stops the emulator before the instruction at an address runs. Without a
+/// bank it stops in every bank.
+pub struct Breakpoint {
+    pub addr: BankedAddress,
+
+    // The label at the address, to show alongside it
+    pub label: Option<String>,
+
+    // Only stop when this is true
+    pub condition: Option<Expression>,
+
+    // Stop on this hit and every one after it. Only hits where the condition
+    // is true count.
+    pub hit_count: u32,
+    hits: u32,
+}
+
+impl Breakpoint {
+    pub fn new(addr: BankedAddress) -> Breakpoint {
+        Breakpoint {
+            addr,
+            label: None,
+            condition: None,
+            hit_count: 1,
+            hits: 0,
+        }
+    }
+
+    /// Count a hit. Returns whether there have been enough to stop.
+    pub fn hit(&mut self) -> bool {
+        self.hits += 1;
+        self.hits >= self.hit_count
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr)?;
+
+        if let Some(label) = &self.label {
+            write!(f, " <{}>", label)?;
+        }
+
+        if self.hit_count > 1 {
+            write!(f, " hit {}", self.hit_count)?;
+        }
+
+        if let Some(condition) = &self.condition {
+            write!(f, " if {}", condition)?;
+        }
+
+        write!(f, " (hits: {})", self.hits)
+    }
+}
+
+impl GameBoy {
+    /// Whether a breakpoint stops the instruction about to run. Counts a hit
+    /// on every breakpoint that matches.
+    pub(super) fn check_breakpoints(&mut self) -> bool {
+        let mut stop = false;
+
+        for i in 0..self.breakpoints.len() {
+            if self.breakpoint_matches(&self.breakpoints[i]) {
+                stop |= self.breakpoints[i].hit();
+            }
+        }
+
+        stop
+    }
+
+    /// Whether a breakpoint is at the instruction about to run and its
+    /// condition is true.
+    pub(super) fn breakpoint_matches(&self, breakpoint: &Breakpoint) -> bool {
+        let (pc, bank) = self.pc_and_bank();
+        breakpoint.addr.matches(pc, bank) && self.condition_holds(breakpoint)
+    }
+
+    /// Whether a breakpoint's condition is true for the instruction about to
+    /// run. Hit counts are left alone, as the reverse debugger uses this on
+    /// instructions that already ran once.
+    pub(super) fn at_breakpoint(&self) -> bool {
+        self.breakpoints.iter().any(|b| self.breakpoint_matches(b))
+    }
+
+    fn pc_and_bank(&self) -> (u16, u16) {
+        let pc = self.cpu.registers.pc;
+        (pc, self.mmu.bank(pc))
+    }
+
+    fn condition_holds(&self, breakpoint: &Breakpoint) -> bool {
+        breakpoint
+            .condition
+            .as_ref()
+            .is_none_or(|condition| condition.evaluate(self) != 0)
+    }
+}
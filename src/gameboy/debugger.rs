@@ -3,24 +3,116 @@ use std::io::{self, Write};
 
 use crate::{
     debugger::{disable_debug, enable_debug},
+    disassembler::Line,
     instructions::parse,
+    mmu::address::BankedAddress,
+    mmu::watchpoint::{Access, ValueFilter, Watchpoint},
 };
 
+use super::breakpoint::Breakpoint;
+use super::expression::{self, Expression};
+use super::stepping::RunUntil;
+use super::symbols::Symbols;
 use super::GameBoy;
 use colored::*;
 
 fn parse_number(s: &str) -> Option<u16> {
-    if s.to_lowercase().starts_with("0x") {
-        u16::from_str_radix(&s[2..], 16).ok()
-    } else if s.to_lowercase().starts_with("0b") {
-        u16::from_str_radix(&s[2..], 2).ok()
+    expression::parse_number(s).and_then(|n| u16::try_from(n).ok())
+}
+
+/// Parse an address, which can be in a bank written `bank:addr` with both in
+/// hex, or a label.
+pub(super) fn parse_address(s: &str, symbols: &Symbols) -> Option<BankedAddress> {
+    match s.split_once(':') {
+        Some((bank, addr)) => Some(BankedAddress::in_bank(
+            u16::from_str_radix(bank, 16).ok()?,
+            u16::from_str_radix(addr, 16).ok()?,
+        )),
+        None => parse_number(s)
+            .map(BankedAddress::new)
+            .or_else(|| symbols.address(s)),
+    }
+}
+
+/// Parse the arguments to `break`: an address, then optionally `hit n` to
+/// only stop from the nth hit on, and `if` followed by a condition.
+pub(super) fn parse_breakpoint(args: &[&str], symbols: &Symbols) -> Result<Breakpoint, String> {
+    let (addr, mut rest) = args.split_first().ok_or("Please provide an address")?;
+    let mut breakpoint = Breakpoint::new(parse_address(addr, symbols).ok_or("Invalid address")?);
+
+    if let ["hit", count, tail @ ..] = rest {
+        breakpoint.hit_count = count
+            .parse()
+            .ok()
+            .filter(|count| *count > 0)
+            .ok_or("Hit counts start from 1")?;
+        rest = tail;
+    }
+
+    match rest {
+        [] => (),
+        ["if", condition @ ..] if !condition.is_empty() => {
+            breakpoint.condition = Some(Expression::parse(&condition.join(" "), symbols)?);
+        }
+        _ => return Err("Usage: break a [hit n] [if condition]".to_string()),
+    }
+
+    Ok(breakpoint)
+}
+
+/// Parse the arguments to `watch`: the kind of access, one address or the
+/// first and last of a range, then optionally `=value` or `changed`.
+fn parse_watchpoint(args: &[&str], symbols: &Symbols) -> Option<Watchpoint> {
+    let (access, rest) = args.split_first()?;
+    let access = match access.to_lowercase().as_str() {
+        "r" | "read" => Access::Read,
+        "w" | "write" => Access::Write,
+        "rw" | "access" => Access::Any,
+        _ => return None,
+    };
+
+    let (filter, addresses) = match rest.split_last()? {
+        (&"changed", addresses) => (ValueFilter::Changes, addresses),
+        (last, addresses) if last.starts_with('=') => {
+            let value = parse_number(last.trim_start_matches('='))?;
+            (ValueFilter::Equals(u8::try_from(value).ok()?), addresses)
+        }
+        _ => (ValueFilter::Any, rest),
+    };
+
+    let (start, end) = match addresses {
+        [addr] => (parse_address(addr, symbols)?, parse_address(addr, symbols)?),
+        [start, end] => (parse_address(start, symbols)?, parse_address(end, symbols)?),
+        _ => return None,
+    };
+
+    // Both ends of a range are in the same bank
+    let bank = match (start.bank, end.bank) {
+        (Some(start), Some(end)) if start != end => return None,
+        (start, end) => start.or(end),
+    };
+
+    (start.addr <= end.addr).then_some(Watchpoint {
+        start: start.addr,
+        end: end.addr,
+        bank,
+        access,
+        filter,
+    })
+}
+
+fn print_value(value: i64) {
+    if value < 0 {
+        println!("{}", value);
     } else {
-        s.parse::<u16>().ok()
+        println!("{} ({:#X})", value, value);
     }
 }
 
 impl GameBoy {
     pub fn debugger_cli(&mut self) {
+        // So the trace is up to date while looking around
+        self.flush_trace();
         println!("{}", self.format_instruction());
 
         loop {
@@ -46,6 +138,52 @@ impl GameBoy {
                     return;
                 }
 
+                "n" | "next" => {
+                    self.step_over();
+                    disable_debug();
+                    return;
+                }
+
+                "fin" | "finish" => {
+                    self.step_out();
+                    disable_debug();
+                    return;
+                }
+
+                "fr" | "frame" => {
+                    self.run_to_vblank();
+                    disable_debug();
+                    return;
+                }
+
+                "u" | "until" => match args.as_slice() {
+                    [addr] => match parse_address(addr, &self.symbols) {
+                        Some(addr) => {
+                            self.run_until(RunUntil::Address(addr));
+                            disable_debug();
+                            return;
+                        }
+                        None => println!("{}", "ERR: Invalid address".red()),
+                    },
+                    _ => println!("{}", "ERR: Please provide an address".red()),
+                },
+
+                "back" => match self.reverse_step() {
+                    Ok(()) => println!("{}", self.format_instruction()),
+                    Err(e) => println!("{}", format!("ERR: {}", e).red()),
+                },
+
+                "rc" | "reverse-continue" => match self.reverse_continue() {
+                    Ok(true) => println!("{}", self.format_instruction()),
+                    Ok(false) => {
+                        let message =
+                            "No breakpoint hit, stopped at the oldest recorded instruction";
+                        println!("{}", message.yellow());
+                        println!("{}", self.format_instruction());
+                    }
+                    Err(e) => println!("{}", format!("ERR: {}", e).red()),
+                },
+
                 "d" | "debug" => println!("{:#?}", &self),
 
                 "ppu" => println!("{:#?}", &self.mmu.ppu),
@@ -68,46 +206,131 @@ impl GameBoy {
 
                 "ins" | "instructions" => self.print_instructions(),
 
-                "p" | "print" => self.print_memory_range(args),
+                "bt" | "backtrace" => self.print_backtrace(),
 
-                "b" | "break" => {
-                    if args.len() != 1 {
-                        println!("{:?}", self.breakpoints);
-                        println!("{}", "ERR: Please provide two numerical arguments".red());
-                        continue;
-                    }
+                "p" | "print" => match Expression::parse(&args.join(" "), &self.symbols) {
+                    Ok(expression) => print_value(expression.evaluate(self)),
+                    Err(e) => println!("{}", format!("ERR: {}", e).red()),
+                },
+
+                "m" | "memory" => self.print_memory_range(args),
 
-                    match parse_number(args[0]) {
-                        Some(breakpoint) => {
-                            if self.breakpoints.contains(&breakpoint) {
+                "dis" | "disasm" => self.print_disassembly(&args),
+
+                "pal" | "palette" => match args.as_slice() {
+                    [] => self.print_palettes(),
+                    [name] => {
+                        if !self.select_palette(name) {
+                            println!("{}", "ERR: No palette with that name".red());
+                        }
+                    }
+                    _ => println!("{}", "ERR: Please provide a palette name or number".red()),
+                },
+
+                "save" => match self.state_path(&args) {
+                    Ok(path) => match self.save_state_file(&path) {
+                        Ok(()) => println!("{}", format!("Saved state to {}", path).green()),
+                        Err(e) => println!("{}", format!("ERR: {}", e).red()),
+                    },
+                    Err(e) => println!("{}", format!("ERR: {}", e).red()),
+                },
+
+                "load" => match self.state_path(&args) {
+                    Ok(path) => match self.load_state_file(&path) {
+                        Ok(()) => {
+                            println!("{}", format!("Loaded state from {}", path).green());
+                            println!("{}", self.format_instruction());
+                        }
+                        Err(e) => println!("{}", format!("ERR: {}", e).red()),
+                    },
+                    Err(e) => println!("{}", format!("ERR: {}", e).red()),
+                },
+
+                "b" | "break" => match args.as_slice() {
+                    [] => self.print_breakpoints(),
+                    [addr] => match parse_address(addr, &self.symbols) {
+                        Some(addr) => {
+                            if self.breakpoints.iter().any(|b| b.addr == addr) {
                                 println!("{}", "Removing breakpoint".green());
-                                self.breakpoints.remove(&breakpoint);
+                                self.breakpoints.retain(|b| b.addr != addr);
                             } else {
-                                self.breakpoints.insert(breakpoint);
+                                self.add_breakpoint(Breakpoint::new(addr));
                             }
                         }
                         _ => {
                             println!("{}", "ERR: Invalid invalid numbers passed to break".red());
                         }
-                    }
-                }
+                    },
+
+                    // Replaces any breakpoint already at the address
+                    _ => match parse_breakpoint(&args, &self.symbols) {
+                        Ok(breakpoint) => {
+                            self.breakpoints.retain(|b| b.addr != breakpoint.addr);
+                            self.add_breakpoint(breakpoint);
+                        }
+                        Err(e) => println!("{}", format!("ERR: {}", e).red()),
+                    },
+                },
+
+                "ub" | "unbreak" => match args.as_slice() {
+                    ["all"] => self.breakpoints.clear(),
+                    [index] => match index.parse::<usize>() {
+                        Ok(index) if index < self.breakpoints.len() => {
+                            self.breakpoints.remove(index);
+                        }
+                        _ => println!("{}", "ERR: No breakpoint with that number".red()),
+                    },
+                    _ => println!("{}", "ERR: Please provide a breakpoint number or all".red()),
+                },
 
                 "bm" | "breakmemory" => {
                     if args.len() != 1 {
-                        println!("{}", "ERR: Please provide two numerical arguments".red());
+                        println!("{}", "ERR: Please provide an address".red());
                         continue;
                     }
 
-                    match parse_number(args[0]) {
-                        Some(breakpoint) => {
-                            self.memory_breakpoints.insert(breakpoint);
-                        }
+                    match parse_address(args[0], &self.symbols) {
+                        Some(addr) => self.mmu.watchpoints.push(Watchpoint {
+                            start: addr.addr,
+                            end: addr.addr,
+                            bank: addr.bank,
+                            access: Access::Any,
+                            filter: ValueFilter::Any,
+                        }),
                         _ => {
                             println!("{}", "ERR: Invalid invalid numbers passed to mem".red());
                         }
                     }
                 }
 
+                "w" | "watch" => match args.as_slice() {
+                    [] => self.print_watchpoints(),
+                    _ => match parse_watchpoint(&args, &self.symbols) {
+                        Some(watchpoint) => self.mmu.watchpoints.push(watchpoint),
+                        None => println!(
+                            "{}",
+                            "ERR: Usage: watch r|w|rw start [end] [=value|changed]".red()
+                        ),
+                    },
+                },
+
+                "uw" | "unwatch" => match args.as_slice() {
+                    ["all"] => self.mmu.watchpoints.clear(),
+                    [index] => match index.parse::<usize>() {
+                        Ok(index) if index < self.mmu.watchpoints.len() => {
+                            self.mmu.watchpoints.remove(index);
+                        }
+                        _ => println!("{}", "ERR: No watchpoint with that number".red()),
+                    },
+                    _ => println!("{}", "ERR: Please provide a watchpoint number or all".red()),
+                },
+
+                "trace" => {
+                    if let Err(e) = self.trace_command(&args) {
+                        println!("{}", format!("ERR: {}", e).red());
+                    }
+                }
+
                 _ => {
                     println!("{}", "ERR: Invalid debugger command".red());
                 }
@@ -116,42 +339,40 @@ impl GameBoy {
     }
 
     pub fn format_instruction(&self) -> String {
-        let instruction_address = format!("{:#06X}", self.cpu.registers.pc);
+        let instruction_address = self.mmu.banked(self.cpu.registers.pc);
 
-        let opcode = self.mmu.read_byte(self.cpu.registers.pc);
-        let arg_1 = self.mmu.read_byte(self.cpu.registers.pc + 1);
-        let arg_2 = self.mmu.read_byte(self.cpu.registers.pc + 2);
+        let opcode = self.mmu.peek_byte(self.cpu.registers.pc);
+        let arg_1 = self.mmu.peek_byte(self.cpu.registers.pc + 1);
+        let arg_2 = self.mmu.peek_byte(self.cpu.registers.pc + 2);
 
         let (_, instruction_length, _) = parse(opcode, arg_1, arg_2);
         let instruction_bytes = (0..instruction_length)
-            .map(|o| {
-                format!(
-                    "{:02x}",
-                    self.mmu.read_byte(self.cpu.registers.pc + o)
-                )
-            })
+            .map(|o| format!("{:02x}", self.mmu.peek_byte(self.cpu.registers.pc + o)))
             .collect::<Vec<String>>()
             .join("");
 
+        let instruction = self.ins();
         format!(
-            "{} 0x{}: {}",
-            instruction_address,
+            "{} 0x{}: {}{}",
+            self.describe(instruction_address),
             instruction_bytes,
-            self.ins(),
+            instruction,
+            self.describe_target(instruction_address, &instruction),
         )
     }
 
     fn print_memory_range(&self, args: Vec<&str>) {
         const BYTES_PER_ROW: u16 = 16;
 
-        let parsed_args: Option<Vec<u16>> = args.iter()
-            .map(|s| parse_number(s))
-            .try_fold(Vec::new(), |mut acc, curr| {
-                curr.map(|n| {
-                    acc.push(n);
-                    acc
-                })
-            });
+        let parsed_args: Option<Vec<u16>> =
+            args.iter()
+                .map(|s| parse_number(s))
+                .try_fold(Vec::new(), |mut acc, curr| {
+                    curr.map(|n| {
+                        acc.push(n);
+                        acc
+                    })
+                });
 
         let (start, end) = match parsed_args {
             Some(nums) => match nums.as_slice() {
@@ -172,7 +393,7 @@ impl GameBoy {
                 print!("{:#06x}: ", start + offset);
             }
 
-            print!("{:02x}", self.mmu.read_byte(start + offset));
+            print!("{:02x}", self.mmu.peek_byte(start + offset));
 
             if offset % 2 == 1 {
                 print!(" ");
@@ -186,9 +407,171 @@ impl GameBoy {
         println!();
     }
 
+    /// Decode from an address, or the PC, for a number of instructions or up
+    /// to an end address. A plain decimal number is a count. Addresses in a
+    /// ROM bank that isn't mapped are read from the ROM.
+    fn print_disassembly(&self, args: &[&str]) {
+        const DEFAULT_COUNT: usize = 10;
+
+        let pc = self.mmu.banked(self.cpu.registers.pc);
+        let start = match args.first().map(|arg| parse_address(arg, &self.symbols)) {
+            None => pc,
+            Some(Some(addr)) if addr.bank.is_some() => addr,
+            Some(Some(addr)) => self.mmu.banked(addr.addr),
+            Some(None) => {
+                println!("{}", "ERR: Invalid address".red());
+                return;
+            }
+        };
+
+        let (count, end) = match args {
+            [] | [_] => (DEFAULT_COUNT, u16::MAX),
+            [_, count] if count.chars().all(|c| c.is_ascii_digit()) => match count.parse() {
+                Ok(count) => (count, u16::MAX),
+                Err(_) => {
+                    println!("{}", "ERR: Invalid count".red());
+                    return;
+                }
+            },
+            [_, end] => match parse_address(end, &self.symbols) {
+                Some(end) if end.addr >= start.addr => (usize::MAX, end.addr),
+                _ => {
+                    println!("{}", "ERR: Invalid end address".red());
+                    return;
+                }
+            },
+            _ => {
+                println!("{}", "ERR: Usage: disasm a [n|b]".red());
+                return;
+            }
+        };
+
+        let read = |addr: u16| {
+            self.mmu.read_banked(BankedAddress {
+                bank: start.bank,
+                addr,
+            })
+        };
+
+        let mut addr = start.addr as u32;
+        for _ in 0..count {
+            if addr > end as u32 {
+                break;
+            }
+
+            let at = BankedAddress {
+                bank: start.bank,
+                addr: addr as u16,
+            };
+            let line = Line::decode(at, read);
+            let bank = at.bank.unwrap_or(0);
+            let breakpoint = self
+                .breakpoints
+                .iter()
+                .any(|b| b.addr.matches(at.addr, bank));
+
+            let marker = if at == pc {
+                "=>".green()
+            } else if breakpoint {
+                " *".red()
+            } else {
+                "  ".normal()
+            };
+
+            if let Some(label) = self.symbols.label_at(line.addr) {
+                println!("{}:", label);
+            }
+            println!(
+                "{} {}{}",
+                marker,
+                line,
+                self.describe_target(line.addr, &line.instruction)
+            );
+
+            addr += line.length() as u32;
+        }
+    }
+
+    /// The file named by a save/load command: a slot number, a path, or
+    /// slot 1 when nothing is given.
+    fn state_path(&self, args: &[&str]) -> Result<String, String> {
+        match args {
+            [] => self.slot_path(1),
+            [arg] => match arg.parse::<usize>() {
+                Ok(slot) => self.slot_path(slot),
+                Err(_) => Ok(arg.to_string()),
+            },
+            _ => Err("Please provide a slot number or a file".to_string()),
+        }
+    }
+
+    fn add_breakpoint(&mut self, mut breakpoint: Breakpoint) {
+        breakpoint.label = self.symbols.label(breakpoint.addr);
+        self.breakpoints.push(breakpoint);
+    }
+
+    fn print_breakpoints(&self) {
+        if self.breakpoints.is_empty() {
+            println!("No breakpoints");
+        }
+
+        for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+            println!("{:2} {}", i, breakpoint);
+        }
+    }
+
+    fn print_watchpoints(&self) {
+        if self.mmu.watchpoints.is_empty() {
+            println!("No watchpoints");
+        }
+
+        for (i, watchpoint) in self.mmu.watchpoints.iter().enumerate() {
+            println!("{:2} {}", i, watchpoint);
+        }
+    }
+
+    fn print_palettes(&self) {
+        for (i, palette) in self.palettes.iter().enumerate() {
+            let marker = if i == self.palette_index() { "*" } else { " " };
+            println!("{} {:2} {}", marker.green(), i, palette.name);
+        }
+    }
+
     fn print_instructions(&self) {
         for (addr, instruction) in self.instruction_history.iter() {
-            println!("{:#06X} {}", addr, instruction)
+            println!(
+                "{} {}{}",
+                self.describe(*addr),
+                instruction,
+                self.describe_target(*addr, instruction)
+            )
+        }
+    }
+
+    /// Where each frame on the call stack is up to, innermost first, with the
+    /// function it's in and SP on entry to it.
+    fn print_backtrace(&self) {
+        let frames = self.cpu.call_stack.frames();
+        let mut location = self.mmu.banked(self.cpu.registers.pc);
+
+        for (i, frame) in frames.iter().rev().enumerate() {
+            println!(
+                "#{:<3}{}  in {} ({}, SP {:#06X})",
+                i,
+                self.describe(location),
+                self.describe(frame.entry),
+                frame.kind,
+                frame.sp
+            );
+            location = frame.caller;
+        }
+        println!("#{:<3}{}", frames.len(), self.describe(location));
+
+        if let Some(problem) = &self.cpu.call_stack.problem {
+            println!(
+                "{}",
+                format!("The call stack may be off: {}", problem).yellow()
+            );
         }
     }
 
@@ -207,27 +590,27 @@ impl GameBoy {
         println!(
             "VBlank:    {:5}     {}",
             colored_bool(self.mmu.ie & 0x1 > 0),
-            colored_bool(self.mmu.read_byte(0xFF0F) & 0x1 > 0)
+            colored_bool(self.mmu.peek_byte(0xFF0F) & 0x1 > 0)
         );
         println!(
             "LCD:       {:5}     {}",
             colored_bool(self.mmu.ie & 0x2 > 0),
-            colored_bool(self.mmu.read_byte(0xFF0F) & 0x2 > 0)
+            colored_bool(self.mmu.peek_byte(0xFF0F) & 0x2 > 0)
         );
         println!(
             "Timer:     {:5}     {}",
             colored_bool(self.mmu.ie & 0x4 > 0),
-            colored_bool(self.mmu.read_byte(0xFF0F) & 0x4 > 0)
+            colored_bool(self.mmu.peek_byte(0xFF0F) & 0x4 > 0)
         );
         println!(
             "Serial:    {:5}     {}",
             colored_bool(self.mmu.ie & 0x8 > 0),
-            colored_bool(self.mmu.read_byte(0xFF0F) & 0x8 > 0)
+            colored_bool(self.mmu.peek_byte(0xFF0F) & 0x8 > 0)
         );
         println!(
             "Joypad:    {:5}     {}",
             colored_bool(self.mmu.ie & 0x10 > 0),
-            colored_bool(self.mmu.read_byte(0xFF0F) & 0x10 > 0)
+            colored_bool(self.mmu.peek_byte(0xFF0F) & 0x10 > 0)
         );
 
         println!();
@@ -237,9 +620,26 @@ impl GameBoy {
         println!("============== COWBOY DEBUGGER ==============");
         println!("[s]tep | <Enter>          step an instruction");
         println!("[c]ontinue                leave debug session");
-        println!("[p]rint a b               dump gameboy memory");
+        println!("[n]ext                    step over calls");
+        println!("[fin]ish                  run until return");
+        println!("[u]ntil a                 run to an address");
+        println!("[fr]ame                   run to next vblank");
+        println!("back                      undo an instruction");
+        println!("[rc] reverse-continue     back to a breakpoint");
+        println!("[p]rint expr              evaluate expression");
+        println!("[m]emory a b              dump gameboy memory");
+        println!("[dis]asm [a] [n|b]        decode instructions");
         println!("[b]reak a                 breakpoint creation");
+        println!("[b]reak a [hit n] [if e]  conditional break");
+        println!("[u]n[b]reak n|all         remove a breakpoint");
         println!("[b]reak [m]emory a        break on mem access");
+        println!("[w]atch r|w|rw a [b] [=v|changed]");
+        println!("                          break on mem access");
+        println!("[u]n[w]atch n|all         remove a watchpoint");
+        println!("trace [on|off]            show or pause trace");
+        println!("trace start|stop a [hit n] [if e]|off");
+        println!("                          trace at breakpoints");
+        println!("trace range a b|off       only trace a range");
         println!("[d]ebug                   print gameboy state");
         println!("[f]lush                   flush ppu to screen");
         println!("[r]egisters               print cpu registers");
@@ -247,6 +647,13 @@ impl GameBoy {
         println!("[h]elp                    show this help info");
         println!("[ro]m                     display gameboy rom");
         println!("[ins]tructions            last cpu operations");
+        println!("[bt] backtrace            show the call stack");
+        println!("[pal]ette [name]          list or pick colours");
+        println!("save [slot|file]          save a state to disk");
+        println!("load [slot|file]          load a saved state");
+        println!();
+        println!("Addresses can be in a bank, e.g. 05:4123");
+        println!("or labels from a .sym file, e.g. Main.loop");
         println!("=============================================");
         println!();
     }
@@ -260,8 +667,100 @@ impl fmt::Debug for GameBoy {
             //.field("instruction", &self.ins())
             .field(
                 "instruction_raw",
-                &self.mmu.read_byte(self.cpu.registers.pc),
+                &self.mmu.peek_byte(self.cpu.registers.pc),
             )
             .finish()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{parse_address, parse_watchpoint};
+    use crate::gameboy::symbols::Symbols;
+    use crate::mmu::address::BankedAddress;
+    use crate::mmu::watchpoint::{Access, ValueFilter, Watchpoint};
+
+    #[test]
+    fn addresses() {
+        let symbols = Symbols::new();
+        assert_eq!(
+            parse_address("$4123", &symbols),
+            Some(BankedAddress::new(0x4123))
+        );
+        assert_eq!(
+            parse_address("0x4123", &symbols),
+            Some(BankedAddress::new(0x4123))
+        );
+        assert_eq!(
+            parse_address("100", &symbols),
+            Some(BankedAddress::new(100))
+        );
+        assert_eq!(parse_address("$10000", &symbols), None);
+        assert_eq!(parse_address("nowhere", &symbols), None);
+    }
+
+    #[test]
+    fn banked_addresses() {
+        let symbols = Symbols::new();
+        assert_eq!(
+            parse_address("05:4123", &symbols),
+            Some(BankedAddress::in_bank(5, 0x4123))
+        );
+        assert_eq!(
+            parse_address("1A:7fff", &symbols),
+            Some(BankedAddress::in_bank(0x1A, 0x7FFF))
+        );
+        assert_eq!(
+            parse_address("0:0150", &symbols),
+            Some(BankedAddress::in_bank(0, 0x150))
+        );
+        assert_eq!(parse_address("05:", &symbols), None);
+        assert_eq!(parse_address(":4123", &symbols), None);
+        assert_eq!(parse_address("05:$4123", &symbols), None);
+        assert_eq!(parse_address("05:12345", &symbols), None);
+    }
+
+    #[test]
+    fn labels() {
+        let symbols = Symbols::parse("01:4123 Routine\n00:0150 Main\n");
+        assert_eq!(
+            parse_address("Routine", &symbols),
+            Some(BankedAddress::in_bank(1, 0x4123))
+        );
+        assert_eq!(
+            parse_address("Main", &symbols),
+            Some(BankedAddress::in_bank(0, 0x150))
+        );
+        assert_eq!(parse_address("Elsewhere", &symbols), None);
+    }
+
+    #[test]
+    fn banked_watchpoints() {
+        let symbols = Symbols::new();
+        let watch = |args: &[&str]| parse_watchpoint(args, &symbols);
+
+        assert_eq!(
+            watch(&["w", "02:4000", "02:40FF", "changed"]),
+            Some(Watchpoint {
+                start: 0x4000,
+                end: 0x40FF,
+                bank: Some(2),
+                access: Access::Write,
+                filter: ValueFilter::Changes,
+            })
+        );
+        assert_eq!(
+            watch(&["r", "01:D000", "=$12"]),
+            Some(Watchpoint {
+                start: 0xD000,
+                end: 0xD000,
+                bank: Some(1),
+                access: Access::Read,
+                filter: ValueFilter::Equals(0x12),
+            })
+        );
+
+        // A range can't span banks
+        assert_eq!(watch(&["rw", "02:4000", "03:4000"]), None);
+    }
+}
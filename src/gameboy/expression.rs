@@ -0,0 +1,497 @@
+use std::fmt;
+
+use super::symbols::Symbols;
+use super::GameBoy;
+
+/// Something in the Game Boy an expression can look at.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Variable {
+    A,
+    F,
+    B,
+    C,
+    D,
+    E,
+    H,
+    L,
+    AF,
+    BC,
+    DE,
+    HL,
+    SP,
+    PC,
+    ZeroFlag,
+    SubtractFlag,
+    HalfCarryFlag,
+    CarryFlag,
+    Ime,
+
+    /// The ROM bank mapped at 0x4000
+    Bank,
+    Ly,
+    Cycles,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Variable> {
+        let variable = match name.to_lowercase().as_str() {
+            "a" => Variable::A,
+            "f" => Variable::F,
+            "b" => Variable::B,
+            "c" => Variable::C,
+            "d" => Variable::D,
+            "e" => Variable::E,
+            "h" => Variable::H,
+            "l" => Variable::L,
+            "af" => Variable::AF,
+            "bc" => Variable::BC,
+            "de" => Variable::DE,
+            "hl" => Variable::HL,
+            "sp" => Variable::SP,
+            "pc" => Variable::PC,
+            "zf" => Variable::ZeroFlag,
+            "nf" => Variable::SubtractFlag,
+            "hf" => Variable::HalfCarryFlag,
+            "cf" => Variable::CarryFlag,
+            "ime" => Variable::Ime,
+            "bank" => Variable::Bank,
+            "ly" => Variable::Ly,
+            "cycles" => Variable::Cycles,
+            _ => return None,
+        };
+        Some(variable)
+    }
+
+    fn value(self, gameboy: &GameBoy) -> i64 {
+        let registers = &gameboy.cpu.registers;
+        let pair = |high: u8, low: u8| (high as i64) << 8 | low as i64;
+
+        match self {
+            Variable::A => registers.a as i64,
+            Variable::F => registers.f.as_byte() as i64,
+            Variable::B => registers.b as i64,
+            Variable::C => registers.c as i64,
+            Variable::D => registers.d as i64,
+            Variable::E => registers.e as i64,
+            Variable::H => registers.h as i64,
+            Variable::L => registers.l as i64,
+            Variable::AF => pair(registers.a, registers.f.as_byte()),
+            Variable::BC => pair(registers.b, registers.c),
+            Variable::DE => pair(registers.d, registers.e),
+            Variable::HL => pair(registers.h, registers.l),
+            Variable::SP => registers.sp as i64,
+            Variable::PC => registers.pc as i64,
+            Variable::ZeroFlag => registers.f.zero as i64,
+            Variable::SubtractFlag => registers.f.subtract as i64,
+            Variable::HalfCarryFlag => registers.f.half_carry as i64,
+            Variable::CarryFlag => registers.f.carry as i64,
+            Variable::Ime => gameboy.cpu.ime as i64,
+            Variable::Bank => gameboy.mmu.cartridge.rom_bank() as i64,
+            Variable::Ly => gameboy.mmu.ppu.ly as i64,
+            Variable::Cycles => gameboy.cycles as i64,
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum UnaryOp {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    ShiftLeft,
+    ShiftRight,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+impl BinaryOp {
+    fn from_symbol(symbol: &str) -> Option<BinaryOp> {
+        let op = match symbol {
+            "||" => BinaryOp::Or,
+            "&&" => BinaryOp::And,
+            "|" => BinaryOp::BitOr,
+            "^" => BinaryOp::BitXor,
+            "&" => BinaryOp::BitAnd,
+            "==" => BinaryOp::Equal,
+            "!=" => BinaryOp::NotEqual,
+            "<" => BinaryOp::Less,
+            "<=" => BinaryOp::LessEqual,
+            ">" => BinaryOp::Greater,
+            ">=" => BinaryOp::GreaterEqual,
+            "<<" => BinaryOp::ShiftLeft,
+            ">>" => BinaryOp::ShiftRight,
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Subtract,
+            "*" => BinaryOp::Multiply,
+            "/" => BinaryOp::Divide,
+            "%" => BinaryOp::Remainder,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    // Same order as in C. Higher binds tighter.
+    fn precedence(self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Equal | BinaryOp::NotEqual => 6,
+            BinaryOp::Less | BinaryOp::LessEqual | BinaryOp::Greater | BinaryOp::GreaterEqual => 7,
+            BinaryOp::ShiftLeft | BinaryOp::ShiftRight => 8,
+            BinaryOp::Add | BinaryOp::Subtract => 9,
+            BinaryOp::Multiply | BinaryOp::Divide | BinaryOp::Remainder => 10,
+        }
+    }
+
+    fn apply(self, a: i64, b: i64) -> i64 {
+        match self {
+            BinaryOp::Or => (a != 0 || b != 0) as i64,
+            BinaryOp::And => (a != 0 && b != 0) as i64,
+            BinaryOp::BitOr => a | b,
+            BinaryOp::BitXor => a ^ b,
+            BinaryOp::BitAnd => a & b,
+            BinaryOp::Equal => (a == b) as i64,
+            BinaryOp::NotEqual => (a != b) as i64,
+            BinaryOp::Less => (a < b) as i64,
+            BinaryOp::LessEqual => (a <= b) as i64,
+            BinaryOp::Greater => (a > b) as i64,
+            BinaryOp::GreaterEqual => (a >= b) as i64,
+            BinaryOp::ShiftLeft => a.wrapping_shl(b as u32),
+            BinaryOp::ShiftRight => a.wrapping_shr(b as u32),
+            BinaryOp::Add => a.wrapping_add(b),
+            BinaryOp::Subtract => a.wrapping_sub(b),
+            BinaryOp::Multiply => a.wrapping_mul(b),
+            BinaryOp::Divide => a.checked_div(b).unwrap_or(0),
+            BinaryOp::Remainder => a.checked_rem(b).unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Number(i64),
+    Variable(Variable),
+
+    /// The byte at an address
+    Memory(Box<Node>),
+    Unary(UnaryOp, Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>),
+}
+
+impl Node {
+    fn evaluate(&self, gameboy: &GameBoy) -> i64 {
+        match self {
+            Node::Number(n) => *n,
+            Node::Variable(variable) => variable.value(gameboy),
+            Node::Memory(addr) => gameboy.mmu.peek_byte(addr.evaluate(gameboy) as u16) as i64,
+            Node::Unary(op, operand) => {
+                let value = operand.evaluate(gameboy);
+                match op {
+                    UnaryOp::Not => (value == 0) as i64,
+                    UnaryOp::Negate => value.wrapping_neg(),
+                    UnaryOp::Complement => !value,
+                }
+            }
+
+            // Only look at the right hand side when it makes a difference,
+            // so `[hl] == 0 || ...` doesn't read memory it doesn't need to
+            Node::Binary(BinaryOp::And, a, b) => {
+                (a.evaluate(gameboy) != 0 && b.evaluate(gameboy) != 0) as i64
+            }
+            Node::Binary(BinaryOp::Or, a, b) => {
+                (a.evaluate(gameboy) != 0 || b.evaluate(gameboy) != 0) as i64
+            }
+            Node::Binary(op, a, b) => op.apply(a.evaluate(gameboy), b.evaluate(gameboy)),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Debug)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Name(name) => write!(f, "{}", name),
+            Token::Symbol(symbol) => write!(f, "{}", symbol),
+        }
+    }
+}
+
+// Longest first, so `<=` isn't read as `<` then `=`
+const SYMBOLS: [&str; 24] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<<", ">>", "|", "^", "&", "<", ">", "+", "-", "*", "/",
+    "%", "!", "~", "(", ")", "[", "]",
+];
+
+/// Numbers are decimal, or hex with `$` or `0x` in front, or binary with
+/// `0b` in front.
+pub fn parse_number(s: &str) -> Option<i64> {
+    let lower = s.to_lowercase();
+    if let Some(hex) = lower.strip_prefix('$').or(lower.strip_prefix("0x")) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse::<i64>().ok()
+    }
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = source.trim_start();
+
+    while !rest.is_empty() {
+        let word_length = rest
+            .char_indices()
+            .skip(1)
+            .find(|(_, c)| !c.is_ascii_alphanumeric() && !"_.@#".contains(*c))
+            .map_or(rest.len(), |(i, _)| i);
+        let first = rest.chars().next().unwrap();
+
+        let length = if first.is_ascii_digit() || first == '$' {
+            let word = &rest[..word_length];
+            let number = parse_number(word).ok_or(format!("Invalid number {}", word))?;
+            tokens.push(Token::Number(number));
+            word_length
+        } else if first.is_ascii_alphabetic() || first == '_' {
+            tokens.push(Token::Name(rest[..word_length].to_string()));
+            word_length
+        } else {
+            let symbol = SYMBOLS
+                .iter()
+                .find(|symbol| rest.starts_with(*symbol))
+                .ok_or(format!("Unexpected {}", first))?;
+            tokens.push(Token::Symbol(symbol));
+            symbol.len()
+        };
+
+        rest = rest[length..].trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    position: usize,
+    symbols: &'a Symbols,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, symbol: &'static str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(s)) if s == symbol => Ok(()),
+            _ => Err(format!("Expected {}", symbol)),
+        }
+    }
+
+    /// Parse operators that bind at least as tightly as `precedence`.
+    fn binary(&mut self, precedence: u8) -> Result<Node, String> {
+        let mut node = self.unary()?;
+
+        while let Some(Token::Symbol(symbol)) = self.peek() {
+            let op = match BinaryOp::from_symbol(symbol) {
+                Some(op) if op.precedence() >= precedence => op,
+                _ => break,
+            };
+            self.position += 1;
+
+            let rhs = self.binary(op.precedence() + 1)?;
+            node = Node::Binary(op, Box::new(node), Box::new(rhs));
+        }
+
+        Ok(node)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let op = match self.peek() {
+            Some(Token::Symbol("!")) => UnaryOp::Not,
+            Some(Token::Symbol("-")) => UnaryOp::Negate,
+            Some(Token::Symbol("~")) => UnaryOp::Complement,
+            _ => return self.primary(),
+        };
+        self.position += 1;
+
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Node::Number(n)),
+            Some(Token::Name(name)) => match Variable::from_name(&name) {
+                Some(variable) => Ok(Node::Variable(variable)),
+                None => self
+                    .symbols
+                    .address(&name)
+                    .map(|addr| Node::Number(addr.addr as i64))
+                    .ok_or(format!("Unknown name {}", name)),
+            },
+            Some(Token::Symbol("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            }
+            Some(Token::Symbol("[")) => {
+                let node = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Memory(Box::new(node)))
+            }
+            Some(Token::Symbol(symbol)) => Err(format!("Unexpected {}", symbol)),
+            None => Err("Unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// An expression over the Game Boy's registers, flags and memory, used for
+/// breakpoint conditions and the debugger's `print`. Operators work like
+/// C's, comparisons give 1 or 0 and anything other than 0 counts as true.
+/// `[addr]` is the byte at `addr`, and labels stand for their address.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    source: String,
+    root: Node,
+}
+
+impl Expression {
+    pub fn parse(source: &str, symbols: &Symbols) -> Result<Expression, String> {
+        let mut parser = Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+            symbols,
+        };
+
+        let root = parser.binary(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {} after the expression", token));
+        }
+
+        Ok(Expression {
+            source: source.trim().to_string(),
+            root,
+        })
+    }
+
+    /// Division by 0 gives 0.
+    pub fn evaluate(&self, gameboy: &GameBoy) -> i64 {
+        self.root.evaluate(gameboy)
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::symbols::Symbols;
+    use super::Expression;
+    use crate::gameboy::model::Model;
+    use crate::gameboy::{Boot, GameBoy};
+
+    fn evaluate(source: &str) -> i64 {
+        let mut gameboy = GameBoy::new(vec![0; 0x8000], Model::Dmg, Boot::Skip);
+        gameboy.cpu.registers.a = 0x12;
+        gameboy.cpu.registers.h = 0xC0;
+        gameboy.cpu.registers.l = 0x10;
+        gameboy.mmu.write_byte(0xC010, 0x34);
+
+        Expression::parse(source, &Symbols::new())
+            .unwrap()
+            .evaluate(&gameboy)
+    }
+
+    fn parse_error(source: &str) -> String {
+        Expression::parse(source, &Symbols::new()).unwrap_err()
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(evaluate("42"), 42);
+        assert_eq!(evaluate("$ff"), 0xFF);
+        assert_eq!(evaluate("0xFF"), 0xFF);
+        assert_eq!(evaluate("0b101"), 5);
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(evaluate("1 + 2 * 3"), 7);
+        assert_eq!(evaluate("(1 + 2) * 3"), 9);
+        assert_eq!(evaluate("1 << 2 + 1"), 8);
+        assert_eq!(evaluate("6 & 3 == 3"), 0);
+        assert_eq!(evaluate("1 | 2 ^ 3 & 1"), 3);
+        assert_eq!(evaluate("1 < 2 == 1"), 1);
+        assert_eq!(evaluate("0 || 1 && 0"), 0);
+        assert_eq!(evaluate("-2 * 3"), -6);
+        assert_eq!(evaluate("!0 + 1"), 2);
+    }
+
+    #[test]
+    fn left_associative() {
+        assert_eq!(evaluate("10 - 3 - 2"), 5);
+        assert_eq!(evaluate("16 / 4 / 2"), 2);
+        assert_eq!(evaluate("1 << 2 << 3"), 32);
+    }
+
+    #[test]
+    fn registers_and_memory() {
+        assert_eq!(evaluate("a"), 0x12);
+        assert_eq!(evaluate("HL"), 0xC010);
+        assert_eq!(evaluate("[hl]"), 0x34);
+        assert_eq!(evaluate("[hl] == $34 && a != 0"), 1);
+        assert_eq!(evaluate("[$C000 + $10] + 1"), 0x35);
+    }
+
+    #[test]
+    fn division_by_zero_is_zero() {
+        assert_eq!(evaluate("5 / 0"), 0);
+        assert_eq!(evaluate("5 % 0"), 0);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(parse_error("1 +"), "Unexpected end of expression");
+        assert_eq!(parse_error("(1 + 2"), "Expected )");
+        assert_eq!(parse_error("1 2"), "Unexpected 2 after the expression");
+        assert_eq!(parse_error("nowhere"), "Unknown name nowhere");
+        assert_eq!(parse_error("$zz"), "Invalid number $zz");
+    }
+}
@@ -1,42 +1,173 @@
+pub mod breakpoint;
 mod debugger;
+pub mod expression;
+pub mod model;
+mod reverse;
+pub mod rewind;
+mod save_state;
+mod stepping;
+pub mod symbols;
+pub mod trace;
 
-use std::collections::HashSet;
+use colored::*;
 
 use crate::cpu::CPU;
-use crate::debugger::is_gameboy_doctor;
+use crate::debugger::enable_debug;
 use crate::instructions::{parse, Instruction};
+use crate::mmu::address::BankedAddress;
+use crate::mmu::ppu::dmg_palette::DmgPalette;
+use crate::mmu::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::mmu::sgb::{SGB_SCREEN_HEIGHT, SGB_SCREEN_WIDTH};
 use crate::mmu::MMU;
+use breakpoint::Breakpoint;
+use model::Model;
+use reverse::Recording;
+use rewind::Rewind;
 use std::collections::VecDeque;
+use stepping::RunUntil;
+use symbols::Symbols;
+use trace::Trace;
+
+/// How the Game Boy starts up.
+pub enum Boot {
+    /// Run the built in DMG boot ROM. Other models have the registers they
+    /// would have been left with patched in once it finishes, and colour
+    /// games start straight from the cartridge.
+    BuiltIn,
+
+    /// Run a boot ROM dumped from real hardware.
+    Rom(Vec<u8>),
+
+    /// Start from the cartridge as if the model's boot ROM had run.
+    Skip,
+}
 
 pub struct GameBoy {
     // debugger
-    pub breakpoints: HashSet<u16>,
-    memory_breakpoints: HashSet<u16>,
-    instruction_history: VecDeque<(u16, Instruction)>,
+    pub breakpoints: Vec<Breakpoint>,
+    instruction_history: VecDeque<(BankedAddress, Instruction)>,
+    recording: Recording,
+    run_until: Option<RunUntil>,
+    pub symbols: Symbols,
+
+    // display
+    pub palettes: Vec<DmgPalette>,
+    palette_index: usize,
 
     // state
+    pub model: Model,
     pub mmu: MMU,
     pub cpu: CPU,
+
+    // CPU cycles run since starting up
+    pub cycles: u64,
+
+    // Set while the built in DMG boot ROM runs on a different model
+    patch_boot_registers: bool,
+
+    // Numbered save state slots are kept next to the ROM
+    pub rom_path: Option<String>,
+
+    pub rewind: Rewind,
+
+    // Instructions are written here as they're run
+    pub trace: Option<Trace>,
 }
 
 impl GameBoy {
-    pub fn new(rom_data: Vec<u8>) -> GameBoy {
+    pub fn new(rom_data: Vec<u8>, model: Model, boot: Boot) -> GameBoy {
+        let (boot_rom, skip_boot) = match boot {
+            Boot::BuiltIn => (None, false),
+            Boot::Rom(boot_rom) => (Some(boot_rom), false),
+            Boot::Skip => (None, true),
+        };
+        let built_in_boot_rom = !skip_boot && boot_rom.is_none();
+
+        let mut mmu = MMU::new(rom_data, model, boot_rom);
+
+        // Colour games can't run the DMG boot ROM
+        let skip_boot = skip_boot || !mmu.boot_rom_enabled();
+
+        let cpu = if skip_boot {
+            mmu.skip_boot(model);
+            CPU::after_boot(model, &mmu.cartridge.header)
+        } else {
+            CPU::new()
+        };
+
         GameBoy {
-            mmu: MMU::new(rom_data),
-            cpu: CPU::new(),
+            model,
+            mmu,
+            cpu,
+            cycles: 0,
 
-            breakpoints: HashSet::with_capacity(10),
-            memory_breakpoints: HashSet::with_capacity(10),
+            breakpoints: Vec::new(),
             instruction_history: VecDeque::with_capacity(10000),
+            recording: Recording::new(),
+            run_until: None,
+            symbols: Symbols::new(),
+
+            palettes: DmgPalette::presets(),
+            palette_index: 0,
+
+            patch_boot_registers: built_in_boot_rom && !skip_boot && model != Model::Dmg,
+            rom_path: None,
+            rewind: Rewind::default(),
+            trace: None,
         }
     }
 
-    pub fn step(&mut self) {
-        if is_gameboy_doctor() {
-            self.print_gameboy_doctor();
+    /// Make user defined palettes available after the built in ones.
+    pub fn add_palettes(&mut self, palettes: Vec<DmgPalette>) {
+        self.palettes.extend(palettes);
+    }
+
+    pub fn palette_index(&self) -> usize {
+        self.palette_index
+    }
+
+    /// Switch to the palette with the given name or index. Returns false if
+    /// there is no such palette.
+    pub fn select_palette(&mut self, name: &str) -> bool {
+        let index = match name.parse::<usize>() {
+            Ok(index) if index < self.palettes.len() => Some(index),
+            _ => self.palettes.iter().position(|p| p.name == name),
+        };
+
+        match index {
+            Some(index) => {
+                self.palette_index = index;
+                self.mmu.ppu.set_dmg_palette(self.palettes[index].clone());
+                true
+            }
+            None => false,
         }
+    }
+
+    pub fn next_palette(&mut self) {
+        let index = (self.palette_index + 1) % self.palettes.len();
+        self.select_palette(&index.to_string());
+    }
+
+    /// Width and height of the picture returned by `frame`.
+    pub fn screen_size(&self) -> (usize, usize) {
+        match self.mmu.sgb {
+            Some(_) => (SGB_SCREEN_WIDTH, SGB_SCREEN_HEIGHT),
+            None => (SCREEN_WIDTH, SCREEN_HEIGHT),
+        }
+    }
 
-        if self.breakpoints.contains(&self.cpu.registers.pc) {
+    /// The last complete frame. On the SGB this includes the border.
+    pub fn frame(&mut self) -> Vec<u32> {
+        match &mut self.mmu.sgb {
+            Some(sgb) => sgb.render(self.mmu.ppu.shades()).to_vec(),
+            None => self.mmu.ppu.frame_buffer.clone(),
+        }
+    }
+
+    pub fn step(&mut self) {
+        if self.check_breakpoints() {
+            self.run_until = None;
             self.debugger_cli();
         }
 
@@ -44,38 +175,59 @@ impl GameBoy {
             self.instruction_history.pop_front();
         }
         self.instruction_history
-            .push_back((self.cpu.registers.pc, self.ins()));
+            .push_back((self.mmu.banked(self.cpu.registers.pc), self.ins()));
+
+        self.record_checkpoint();
 
-        let _cycles = self.cpu.step(&mut self.mmu);
+        // Forget anything the debugger read in between instructions
+        let pc = self.mmu.banked(self.cpu.registers.pc);
+        let ime = self.cpu.ime;
+        self.mmu.take_watch_hit();
+        let traced = self.begin_trace_line();
+        self.execute();
+        if traced {
+            self.end_trace_line();
+        }
+
+        if let Some(hit) = self.mmu.take_watch_hit() {
+            println!(
+                "{}",
+                format!(
+                    "Watchpoint: {}{} by instruction at {}",
+                    hit,
+                    self.label_suffix(hit.addr),
+                    self.describe(pc)
+                )
+                .yellow()
+            );
+            self.run_until = None;
+            self.debugger_cli();
+        } else if self.run_until_reached(ime) {
+            enable_debug();
+        }
+    }
+
+    /// Run a single instruction. This is all the reverse debugger replays,
+    /// so it must only depend on the state and the keys pressed.
+    fn execute(&mut self) {
+        self.cycles += self.cpu.step(&mut self.mmu) as u64;
+
+        // Games tell models apart by the registers the boot ROM leaves, so
+        // give them the ones their model's boot ROM would have
+        if self.patch_boot_registers && !self.mmu.boot_rom_enabled() {
+            self.patch_boot_registers = false;
+            self.cpu = CPU::after_boot(self.model, &self.mmu.cartridge.header);
+        }
+
+        self.recording.instructions += 1;
     }
 
     pub fn ins(&self) -> Instruction {
-        let opcode = self.mmu.read_byte(self.cpu.registers.pc);
-        let arg_1 = self.mmu.read_byte(self.cpu.registers.pc + 1);
-        let arg_2 = self.mmu.read_byte(self.cpu.registers.pc + 2);
+        let opcode = self.mmu.peek_byte(self.cpu.registers.pc);
+        let arg_1 = self.mmu.peek_byte(self.cpu.registers.pc + 1);
+        let arg_2 = self.mmu.peek_byte(self.cpu.registers.pc + 2);
         let (ins, _, _) = parse(opcode, arg_1, arg_2);
 
         ins
     }
-
-    fn print_gameboy_doctor(&self) {
-        println!(
-            "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} \
-                PCMEM:{:02X},{:02X},{:02X},{:02X}",
-            self.cpu.registers.a,
-            u8::from(self.cpu.registers.f),
-            self.cpu.registers.b,
-            self.cpu.registers.c,
-            self.cpu.registers.d,
-            self.cpu.registers.e,
-            self.cpu.registers.h,
-            self.cpu.registers.l,
-            self.cpu.registers.sp,
-            self.cpu.registers.pc,
-            self.mmu.read_byte(self.cpu.registers.pc),
-            self.mmu.read_byte(self.cpu.registers.pc + 1),
-            self.mmu.read_byte(self.cpu.registers.pc + 2),
-            self.mmu.read_byte(self.cpu.registers.pc + 3),
-        );
-    }
 }
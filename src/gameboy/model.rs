@@ -0,0 +1,55 @@
+use clap::ValueEnum;
+
+use crate::cartridge::header::CartridgeHeader;
+
+/// The Game Boy being emulated.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Model {
+    /// The earliest DMG boot ROM, only found in some Japanese units.
+    Dmg0,
+    Dmg,
+    /// Game Boy Pocket
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    /// Game Boy Advance, running Game Boy games on its CGB hardware.
+    Agb,
+}
+
+impl Model {
+    /// The model a game was made for, going by its header.
+    pub fn auto(header: &CartridgeHeader) -> Model {
+        if header.supports_cgb() {
+            Model::Cgb
+        } else if header.supports_sgb() {
+            Model::Sgb
+        } else {
+            Model::Dmg
+        }
+    }
+
+    /// The system counter when the boot ROM jumps to 0x100. Its upper byte
+    /// is what the game reads from DIV.
+    pub fn post_boot_counter(self) -> u16 {
+        match self {
+            Model::Dmg0 => 0x1830,
+            Model::Dmg | Model::Mgb => 0xABCC,
+
+            // The SGB boot ROM waits on the SNES and the CGB one spends a
+            // different amount of time on each game, so these are only
+            // typical values
+            Model::Sgb | Model::Sgb2 => 0xD85C,
+            Model::Cgb | Model::Agb => 0x1EA0,
+        }
+    }
+
+    /// Whether the model has the CGB's colour hardware.
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+
+use minifb::Key;
+
+use super::GameBoy;
+use crate::mmu::joypad::MAX_PLAYERS;
+
+// Instructions between checkpoints. Going back replays everything since the
+// checkpoint before, so this bounds how long a step back takes.
+const CHECKPOINT_INTERVAL: u64 = 50_000;
+
+// Each one is a full save state, so this keeps the last few seconds
+const MAX_CHECKPOINTS: usize = 100;
+
+type HeldKeys = ([u8; MAX_PLAYERS], [u8; MAX_PLAYERS]);
+
+struct Checkpoint {
+    instruction: u64,
+    state: Vec<u8>,
+
+    // Save states leave out the keys being held, but replaying needs them
+    keys: HeldKeys,
+}
+
+/// Everything needed to get back to an earlier instruction: save states
+/// taken every so often, and the keys pressed since. The emulator is
+/// deterministic, so replaying from a checkpoint with the same keys ends up
+/// in exactly the same state. The MBC3 clock is the exception, as it follows
+/// the host's time.
+pub struct Recording {
+    // Instructions executed so far
+    pub(super) instructions: u64,
+    checkpoints: VecDeque<Checkpoint>,
+
+    // Key presses and releases, and the instruction they came in before
+    inputs: VecDeque<(u64, Key, bool)>,
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording {
+            instructions: 0,
+            checkpoints: VecDeque::with_capacity(MAX_CHECKPOINTS),
+            inputs: VecDeque::new(),
+        }
+    }
+
+    /// Forget everything recorded, for when the state is replaced wholesale.
+    pub fn clear(&mut self) {
+        self.checkpoints.clear();
+        self.inputs.clear();
+    }
+
+    fn checkpoint_due(&self) -> bool {
+        self.checkpoints
+            .back()
+            .is_none_or(|c| self.instructions - c.instruction >= CHECKPOINT_INTERVAL)
+    }
+
+    fn oldest(&self) -> Option<u64> {
+        self.checkpoints.front().map(|c| c.instruction)
+    }
+
+    /// Drop everything after the given instruction.
+    fn truncate(&mut self, instruction: u64) {
+        while self
+            .checkpoints
+            .back()
+            .is_some_and(|c| c.instruction > instruction)
+        {
+            self.checkpoints.pop_back();
+        }
+
+        while self.inputs.back().is_some_and(|i| i.0 > instruction) {
+            self.inputs.pop_back();
+        }
+    }
+}
+
+impl GameBoy {
+    pub fn key_down(&mut self, key: Key) {
+        self.record_key(key, true);
+    }
+
+    pub fn key_up(&mut self, key: Key) {
+        self.record_key(key, false);
+    }
+
+    fn record_key(&mut self, key: Key, pressed: bool) {
+        self.recording
+            .inputs
+            .push_back((self.recording.instructions, key, pressed));
+        self.press_key(key, pressed);
+    }
+
+    fn press_key(&mut self, key: Key, pressed: bool) {
+        if pressed {
+            self.mmu.joypad.handle_key_down(key);
+        } else {
+            self.mmu.joypad.handle_key_up(key);
+        }
+    }
+
+    /// Called before every instruction.
+    pub(super) fn record_checkpoint(&mut self) {
+        if !self.recording.checkpoint_due() {
+            return;
+        }
+
+        if self.recording.checkpoints.len() == MAX_CHECKPOINTS {
+            self.recording.checkpoints.pop_front();
+            let oldest = self.recording.oldest().unwrap();
+            while self.recording.inputs.front().is_some_and(|i| i.0 < oldest) {
+                self.recording.inputs.pop_front();
+            }
+        }
+
+        self.recording.checkpoints.push_back(Checkpoint {
+            instruction: self.recording.instructions,
+            state: self.save_state(),
+            keys: self.mmu.joypad.held_keys(),
+        });
+    }
+
+    /// Undo the last instruction.
+    pub fn reverse_step(&mut self) -> Result<(), String> {
+        let target = self.recording.instructions.wrapping_sub(1);
+        match self.recording.oldest() {
+            Some(oldest) if oldest <= target && target < self.recording.instructions => {
+                self.go_to(target);
+                self.forget_instructions(1);
+                Ok(())
+            }
+            _ => Err("Nothing is recorded before this instruction".to_string()),
+        }
+    }
+
+    /// Go back to the last time a breakpoint was hit. If there isn't one
+    /// this stops at the start of the recording and returns false.
+    pub fn reverse_continue(&mut self) -> Result<bool, String> {
+        let Some(oldest) = self.recording.oldest() else {
+            return Err("Nothing has been recorded yet".to_string());
+        };
+
+        // Search one checkpoint's worth of instructions at a time, newest
+        // first
+        let current = self.recording.instructions;
+        let mut end = current;
+        while end > oldest {
+            let index = self.checkpoint_before(end - 1);
+            let start = self.recording.checkpoints[index].instruction;
+            self.restore_checkpoint(index);
+
+            let mut hit = None;
+            while self.recording.instructions < end {
+                if self.at_breakpoint() {
+                    hit = Some(self.recording.instructions);
+                }
+                self.replay_instruction(start);
+            }
+
+            if let Some(hit) = hit {
+                self.go_to(hit);
+                self.forget_instructions(current - hit);
+                return Ok(true);
+            }
+            end = start;
+        }
+
+        self.go_to(oldest);
+        self.forget_instructions(current - oldest);
+        Ok(false)
+    }
+
+    /// Drop undone instructions from the instruction history.
+    fn forget_instructions(&mut self, count: u64) {
+        for _ in 0..count {
+            if self.instruction_history.pop_back().is_none() {
+                break;
+            }
+        }
+    }
+
+    /// Get to the given instruction by replaying from the checkpoint before
+    /// it. Anything recorded after it is forgotten, as running on from here
+    /// may play out differently.
+    fn go_to(&mut self, target: u64) {
+        let index = self.checkpoint_before(target);
+        let start = self.recording.checkpoints[index].instruction;
+        self.restore_checkpoint(index);
+        while self.recording.instructions < target {
+            self.replay_instruction(start);
+        }
+        self.replay_inputs(start);
+
+        self.recording.truncate(target);
+    }
+
+    fn checkpoint_before(&self, instruction: u64) -> usize {
+        self.recording
+            .checkpoints
+            .iter()
+            .rposition(|c| c.instruction <= instruction)
+            .expect("There is a checkpoint before every recorded instruction")
+    }
+
+    fn restore_checkpoint(&mut self, index: usize) {
+        let checkpoint = &self.recording.checkpoints[index];
+        let (instruction, keys) = (checkpoint.instruction, checkpoint.keys);
+        let state = std::mem::take(&mut self.recording.checkpoints[index].state);
+
+        self.load_state(&state)
+            .expect("Checkpoints are made by this Game Boy");
+        self.recording.checkpoints[index].state = state;
+
+        self.mmu.joypad.set_held_keys(keys);
+        self.recording.instructions = instruction;
+    }
+
+    /// Run the next instruction the way it originally ran, with the keys
+    /// that were pressed before it. Keys pressed right at the checkpoint are
+    /// already part of it.
+    fn replay_instruction(&mut self, checkpoint: u64) {
+        self.replay_inputs(checkpoint);
+        self.execute();
+    }
+
+    fn replay_inputs(&mut self, checkpoint: u64) {
+        let instruction = self.recording.instructions;
+        if instruction == checkpoint {
+            return;
+        }
+
+        let inputs = &self.recording.inputs;
+        let first = inputs.partition_point(|i| i.0 < instruction);
+        let keys: Vec<(Key, bool)> = inputs
+            .range(first..)
+            .take_while(|i| i.0 == instruction)
+            .map(|i| (i.1, i.2))
+            .collect();
+
+        for (key, pressed) in keys {
+            self.press_key(key, pressed);
+        }
+    }
+}
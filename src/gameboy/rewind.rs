@@ -0,0 +1,239 @@
+use std::collections::VecDeque;
+
+use super::GameBoy;
+
+/// Recent snapshots of the Game Boy to rewind through. Only the newest is
+/// kept whole. Each older one is stored as the difference from the one after
+/// it, which is mostly zeros and run length encodes down to very little.
+/// The oldest snapshots are dropped to stay within the memory budget.
+pub struct Rewind {
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+
+    // Bytes used by all of the snapshots
+    used: usize,
+    budget: usize,
+
+    // Frames between snapshots, and frames since the last one
+    interval: u32,
+    frames: u32,
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(0, 1)
+    }
+}
+
+impl Rewind {
+    /// A budget of 0 turns rewinding off.
+    pub fn new(budget: usize, interval: u32) -> Rewind {
+        Rewind {
+            latest: None,
+            deltas: VecDeque::new(),
+            used: 0,
+            budget,
+            interval: interval.max(1),
+            frames: 0,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.budget > 0
+    }
+
+    /// Count a frame. Returns true when it is time for another snapshot.
+    fn frame_due(&mut self) -> bool {
+        self.frames += 1;
+        if !self.is_enabled() || self.frames < self.interval {
+            return false;
+        }
+
+        self.frames = 0;
+        true
+    }
+
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        self.used += snapshot.len();
+
+        if let Some(previous) = self.latest.replace(snapshot) {
+            self.used -= previous.len();
+            let delta = encode_delta(self.latest.as_ref().unwrap(), &previous);
+            self.used += delta.len();
+            self.deltas.push_back(delta);
+        }
+
+        while self.used > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.used -= delta.len(),
+                None => break,
+            }
+        }
+    }
+
+    /// Take the newest snapshot, leaving the one before it as the newest.
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        self.used -= latest.len();
+
+        if let Some(delta) = self.deltas.pop_back() {
+            self.used -= delta.len();
+            let previous = decode_delta(&latest, &delta);
+            self.used += previous.len();
+            self.latest = Some(previous);
+        }
+
+        self.frames = 0;
+        Some(latest)
+    }
+}
+
+/// Encode `target` as its difference from `base`: the length of `target`,
+/// then runs of a count of unchanged bytes followed by a count of changed
+/// bytes and their XOR with `base`.
+fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let xor: Vec<u8> = target
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ base.get(i).unwrap_or(&0))
+        .collect();
+
+    let mut delta = Vec::new();
+    delta.extend_from_slice(&(target.len() as u32).to_le_bytes());
+
+    let mut i = 0;
+    while i < xor.len() {
+        let unchanged = xor[i..].iter().take_while(|byte| **byte == 0).count();
+        i += unchanged;
+        let changed = xor[i..].iter().take_while(|byte| **byte != 0).count();
+
+        delta.extend_from_slice(&(unchanged as u32).to_le_bytes());
+        delta.extend_from_slice(&(changed as u32).to_le_bytes());
+        delta.extend_from_slice(&xor[i..i + changed]);
+        i += changed;
+    }
+
+    delta
+}
+
+fn decode_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let read = |at: usize| u32::from_le_bytes(delta[at..at + 4].try_into().unwrap()) as usize;
+
+    let length = read(0);
+    let mut target: Vec<u8> = (0..length).map(|i| *base.get(i).unwrap_or(&0)).collect();
+
+    let (mut position, mut i) = (4, 0);
+    while position < delta.len() {
+        i += read(position);
+        let changed = read(position + 4);
+        position += 8;
+
+        for (byte, xor) in target[i..i + changed]
+            .iter_mut()
+            .zip(&delta[position..position + changed])
+        {
+            *byte ^= xor;
+        }
+        i += changed;
+        position += changed;
+    }
+
+    target
+}
+
+impl GameBoy {
+    /// Called after every frame to keep the rewind buffer topped up.
+    pub fn capture_rewind(&mut self) {
+        if self.rewind.frame_due() {
+            let snapshot = self.rewind_snapshot();
+            self.rewind.push(snapshot);
+        }
+    }
+
+    /// Go back to the last snapshot in the rewind buffer, frame and all.
+    /// Returns false when there is nothing further back.
+    pub fn step_back(&mut self) -> bool {
+        match self.rewind.pop() {
+            Some(snapshot) => {
+                self.restore_rewind_snapshot(&snapshot);
+                self.recording.clear();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A save state followed by the frame on screen, so rewinding can show
+    /// it straight away.
+    fn rewind_snapshot(&self) -> Vec<u8> {
+        let mut snapshot = self.save_state();
+        for pixel in self.mmu.ppu.frame_buffer.iter() {
+            snapshot.extend_from_slice(&pixel.to_le_bytes());
+        }
+        snapshot
+    }
+
+    fn restore_rewind_snapshot(&mut self, snapshot: &[u8]) {
+        let frame_length = self.mmu.ppu.frame_buffer.len() * 4;
+        let (state, frame) = snapshot.split_at(snapshot.len() - frame_length);
+
+        self.load_state(state)
+            .expect("Rewind snapshots are made by this Game Boy");
+        for (pixel, bytes) in self
+            .mmu
+            .ppu
+            .frame_buffer
+            .iter_mut()
+            .zip(frame.chunks_exact(4))
+        {
+            *pixel = u32::from_le_bytes(bytes.try_into().unwrap());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_delta, encode_delta};
+
+    fn round_trip(base: &[u8], target: &[u8]) -> Vec<u8> {
+        let delta = encode_delta(base, target);
+        assert_eq!(decode_delta(base, &delta), target);
+        delta
+    }
+
+    #[test]
+    fn unchanged_state_is_one_run() {
+        let state = [1, 2, 3, 4, 5];
+        assert_eq!(
+            round_trip(&state, &state),
+            [5, 0, 0, 0, 5, 0, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn changes_round_trip() {
+        let base = [0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77];
+        round_trip(&base, &[0xFF, 0x11, 0x22, 0x00, 0x00, 0x55, 0x66, 0x78]);
+        round_trip(&base, &[0; 8]);
+        round_trip(&base, &[0xAA; 8]);
+        round_trip(&[], &[]);
+    }
+
+    #[test]
+    fn length_changes_round_trip() {
+        let base = [1, 2, 3, 4];
+        round_trip(&base, &[1, 2, 3, 4, 0, 0, 9]);
+        round_trip(&base, &[1, 2]);
+        round_trip(&[], &[5, 0, 6]);
+    }
+
+    #[test]
+    fn deltas_are_small_for_small_changes() {
+        let base = vec![0x5A; 0x10000];
+        let mut target = base.clone();
+        target[0x1234] = 0;
+        target[0x8000] ^= 0xFF;
+
+        assert!(round_trip(&base, &target).len() < 40);
+    }
+}
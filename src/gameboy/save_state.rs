@@ -0,0 +1,189 @@
+use std::fs;
+use std::path::Path;
+
+use super::GameBoy;
+use crate::state::{SaveState, StateReader, StateWriter};
+
+const MAGIC: &[u8; 8] = b"COWBOYSS";
+
+// Bump whenever anything is added to or moved around in the state. States
+// from other versions are refused rather than loaded wrongly.
+const VERSION: u16 = 4;
+
+impl GameBoy {
+    /// Snapshot everything needed to carry on from exactly this point. The
+    /// state starts with a header naming the format version, the ROM and the
+    /// model it was made with.
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = StateWriter::new();
+        state.bytes(MAGIC);
+        state.u16(VERSION);
+        state.u32(self.mmu.cartridge.checksum);
+        state.u8(self.model as u8);
+
+        self.cpu.save(&mut state);
+        self.mmu.save(&mut state);
+        state.u64(self.cycles);
+        state.bool(self.patch_boot_registers);
+        state.into_bytes()
+    }
+
+    /// Restore a state made by `save_state`. If the state can't be loaded
+    /// the Game Boy carries on as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let backup = self.save_state();
+
+        match self.restore(data) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.restore(&backup)
+                    .expect("Couldn't restore the state from before loading");
+                Err(e)
+            }
+        }
+    }
+
+    fn restore(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut state = StateReader::new(data);
+
+        let mut magic = [0; MAGIC.len()];
+        if state.bytes(&mut magic).is_err() || magic != *MAGIC {
+            return Err("Not a save state".to_string());
+        }
+
+        let version = state.u16()?;
+        if version != VERSION {
+            return Err(format!(
+                "Save state is version {}, only version {} is supported",
+                version, VERSION
+            ));
+        }
+
+        if state.u32()? != self.mmu.cartridge.checksum {
+            return Err("Save state was made with a different ROM".to_string());
+        }
+
+        if state.u8()? != self.model as u8 {
+            return Err(format!(
+                "Save state was made on a different model, this is {:?}",
+                self.model
+            ));
+        }
+
+        self.cpu.load(&mut state)?;
+        self.mmu.load(&mut state)?;
+        self.cycles = state.u64()?;
+        self.patch_boot_registers = state.bool()?;
+
+        if !state.is_empty() {
+            return Err("Save state is longer than expected".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn save_state_file(&self, path: &str) -> Result<(), String> {
+        fs::write(path, self.save_state()).map_err(|e| format!("Couldn't write {}: {}", path, e))
+    }
+
+    pub fn load_state_file(&mut self, path: &str) -> Result<(), String> {
+        let data = fs::read(path).map_err(|e| format!("Couldn't read {}: {}", path, e))?;
+        self.load_state(&data)?;
+
+        // The reverse debugger can't go back past a state that didn't come
+        // from running
+        self.recording.clear();
+        Ok(())
+    }
+
+    /// The file a numbered save state slot is kept in, next to the ROM.
+    pub fn slot_path(&self, slot: usize) -> Result<String, String> {
+        let rom_path = self
+            .rom_path
+            .as_ref()
+            .ok_or("Save state slots need the path of the ROM")?;
+
+        Ok(Path::new(rom_path)
+            .with_extension(format!("ss{}", slot))
+            .to_string_lossy()
+            .to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::gameboy::model::Model;
+    use crate::gameboy::{Boot, GameBoy};
+
+    fn tetris() -> GameBoy {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms/tetris.gb");
+        GameBoy::new(std::fs::read(path).unwrap(), Model::Dmg, Boot::Skip)
+    }
+
+    fn run(gameboy: &mut GameBoy, instructions: usize) {
+        for _ in 0..instructions {
+            gameboy.step();
+        }
+    }
+
+    #[test]
+    fn state_carries_on_the_same() {
+        let mut gameboy = tetris();
+        run(&mut gameboy, 50_000);
+        let state = gameboy.save_state();
+
+        run(&mut gameboy, 50_000);
+        let expected = gameboy.save_state();
+
+        gameboy.load_state(&state).unwrap();
+        assert_eq!(gameboy.save_state(), state);
+        run(&mut gameboy, 50_000);
+        assert_eq!(gameboy.save_state(), expected);
+    }
+
+    // Refused states must leave the Game Boy as it was
+    fn assert_refused(gameboy: &mut GameBoy, state: &[u8], error: &str) {
+        let before = gameboy.save_state();
+        assert!(gameboy.load_state(state).unwrap_err().contains(error));
+        assert_eq!(gameboy.save_state(), before);
+    }
+
+    #[test]
+    fn refuses_a_bad_magic() {
+        let mut gameboy = tetris();
+        let mut state = gameboy.save_state();
+        state[0] = b'X';
+        run(&mut gameboy, 1000);
+
+        assert_refused(&mut gameboy, &state, "Not a save state");
+        assert_refused(&mut gameboy, &[], "Not a save state");
+    }
+
+    #[test]
+    fn refuses_another_version() {
+        let mut gameboy = tetris();
+        let mut state = gameboy.save_state();
+        state[8] ^= 0xFF;
+        run(&mut gameboy, 1000);
+
+        assert_refused(&mut gameboy, &state, "only version");
+    }
+
+    #[test]
+    fn refuses_another_rom() {
+        let state = tetris().save_state();
+        let mut gameboy = GameBoy::new(vec![0; 0x8000], Model::Dmg, Boot::Skip);
+
+        assert_refused(&mut gameboy, &state, "different ROM");
+    }
+
+    #[test]
+    fn refuses_a_truncated_state() {
+        let mut gameboy = tetris();
+        let state = gameboy.save_state();
+        run(&mut gameboy, 1000);
+
+        assert_refused(&mut gameboy, &state[..state.len() - 1], "");
+    }
+}
@@ -0,0 +1,184 @@
+use super::GameBoy;
+use crate::instructions::Instruction;
+use crate::mmu::address::BankedAddress;
+
+// CPU cycles in a frame at normal speed
+const FRAME_CYCLES: u64 = 70224;
+
+/// Where to stop when the debugger lets the Game Boy run on. Breakpoints
+/// and watchpoints still stop it first.
+pub enum RunUntil {
+    /// After the next instruction, unless it calls a subroutine or an
+    /// interrupt comes in, in which case after they return. The stack
+    /// pointer is from before the instruction.
+    Next {
+        sp: u16,
+    },
+
+    /// Until the stack pointer is back up to this, after returning from the
+    /// calls and interrupts being stepped over.
+    Return {
+        sp: u16,
+    },
+
+    /// Until a return leaves the stack pointer above this, which is where it
+    /// was inside the function being finished.
+    Finish {
+        sp: u16,
+    },
+
+    Address(BankedAddress),
+
+    /// Until the start of the next VBlank. Holds LY as of the last
+    /// instruction, and the cycle count when it started running. There's
+    /// no VBlank while the LCD is off, so then it stops after a frame.
+    VBlank {
+        ly: u8,
+        since: u64,
+    },
+}
+
+fn is_call(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::CallImm16(_) | Instruction::CallCondImm16(_, _) | Instruction::RstTgt3(_)
+    )
+}
+
+fn is_return(instruction: &Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Ret | Instruction::RetCond(_) | Instruction::Reti
+    )
+}
+
+impl GameBoy {
+    /// Let the Game Boy run until it gets to `run_until`.
+    pub(super) fn run_until(&mut self, run_until: RunUntil) {
+        self.run_until = Some(run_until);
+    }
+
+    pub(super) fn step_over(&mut self) {
+        self.run_until(RunUntil::Next {
+            sp: self.cpu.registers.sp,
+        });
+    }
+
+    pub(super) fn step_out(&mut self) {
+        self.run_until(RunUntil::Finish {
+            sp: self.cpu.registers.sp,
+        });
+    }
+
+    pub(super) fn run_to_vblank(&mut self) {
+        self.run_until(RunUntil::VBlank {
+            ly: self.mmu.ppu.ly,
+            since: self.cycles,
+        });
+    }
+
+    /// Called after every instruction, with IME from before it. Returns true
+    /// once it's time to stop.
+    pub(super) fn run_until_reached(&mut self, ime: bool) -> bool {
+        let Some(run_until) = &mut self.run_until else {
+            return false;
+        };
+        let registers = &self.cpu.registers;
+        let Some((_, instruction)) = self.instruction_history.back() else {
+            return false;
+        };
+
+        // Servicing an interrupt clears IME. EI only takes effect after the
+        // next instruction, and RETI sets it straight away.
+        let interrupted = match instruction {
+            Instruction::Di | Instruction::Ei => false,
+            Instruction::Reti => !self.cpu.ime,
+            _ => ime && !self.cpu.ime,
+        };
+
+        // The stack pointer the instruction left, before an interrupt pushed
+        // onto it
+        let sp = registers.sp.wrapping_add(if interrupted { 2 } else { 0 });
+
+        let reached = match *run_until {
+            RunUntil::Next { sp: before } => {
+                let called = is_call(instruction) && sp == before.wrapping_sub(2);
+                let frames = called as u16 + interrupted as u16;
+                if frames > 0 {
+                    *run_until = RunUntil::Return {
+                        sp: registers.sp.wrapping_add(2 * frames),
+                    };
+                }
+                frames == 0
+            }
+            RunUntil::Return { sp: target } => registers.sp >= target,
+            RunUntil::Finish { sp: inside } => {
+                let returned = is_return(instruction) && sp > inside;
+                if returned && interrupted {
+                    *run_until = RunUntil::Return { sp };
+                    false
+                } else {
+                    returned
+                }
+            }
+            RunUntil::Address(addr) => addr.matches(registers.pc, self.mmu.bank(registers.pc)),
+            RunUntil::VBlank { ly, since } => {
+                *run_until = RunUntil::VBlank {
+                    ly: self.mmu.ppu.ly,
+                    since,
+                };
+
+                // The CPU gets through twice as many cycles a frame in
+                // double speed mode
+                let frame_cycles = FRAME_CYCLES << self.mmu.double_speed as u8;
+                let lcd_off = !self.mmu.ppu.lcd_enabled();
+                (ly != 144 && self.mmu.ppu.ly == 144)
+                    || (lcd_off && self.cycles - since >= frame_cycles)
+            }
+        };
+
+        if reached {
+            self.run_until = None;
+        }
+        reached
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::FRAME_CYCLES;
+    use crate::gameboy::model::Model;
+    use crate::gameboy::{Boot, GameBoy};
+
+    // Steps until the debugger would stop, returning the cycles it took
+    fn run_to_vblank(gameboy: &mut GameBoy) -> u64 {
+        let start = gameboy.cycles;
+        gameboy.run_to_vblank();
+        while gameboy.run_until.is_some() {
+            assert!(gameboy.cycles - start <= 2 * FRAME_CYCLES);
+            gameboy.step();
+        }
+        gameboy.cycles - start
+    }
+
+    #[test]
+    fn frame_stops_at_vblank() {
+        let mut gameboy = GameBoy::new(vec![0; 0x8000], Model::Dmg, Boot::Skip);
+        run_to_vblank(&mut gameboy);
+        assert_eq!(gameboy.mmu.ppu.ly, 144);
+
+        // A whole frame later
+        let cycles = run_to_vblank(&mut gameboy);
+        assert_eq!(gameboy.mmu.ppu.ly, 144);
+        assert!(cycles.abs_diff(FRAME_CYCLES) < 8);
+    }
+
+    #[test]
+    fn frame_stops_after_a_frame_with_the_lcd_off() {
+        let mut gameboy = GameBoy::new(vec![0; 0x8000], Model::Dmg, Boot::Skip);
+        gameboy.mmu.write_byte(0xFF40, 0x00);
+
+        let cycles = run_to_vblank(&mut gameboy);
+        assert!((FRAME_CYCLES..FRAME_CYCLES + 8).contains(&cycles));
+    }
+}
@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+use super::GameBoy;
+use crate::instructions::Instruction;
+use crate::mmu::address::{region_start, BankedAddress};
+
+/// Labels from a symbol file, as written by RGBDS or read by no$gmb. Each
+/// line is `BB:AAAA Label` with the bank and address in hex, and anything
+/// after a `;` is a comment.
+#[derive(Default, Debug)]
+pub struct Symbols {
+    // The first label given for each address, by bank then address
+    labels: BTreeMap<(u16, u16), String>,
+    addresses: HashMap<String, BankedAddress>,
+}
+
+fn parse_line(line: &str) -> Option<(BankedAddress, &str)> {
+    let line = line.split(';').next()?;
+    let (addr, label) = line.trim().split_once(char::is_whitespace)?;
+    let (bank, addr) = addr.split_once(':')?;
+
+    let addr = BankedAddress::in_bank(
+        u16::from_str_radix(bank, 16).ok()?,
+        u16::from_str_radix(addr, 16).ok()?,
+    );
+    Some((addr, label.trim()))
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols {
+            labels: BTreeMap::new(),
+            addresses: HashMap::new(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Symbols, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Ok(Symbols::parse(&text))
+    }
+
+    /// Lines that aren't labels, like no$gmb's section headers, are skipped.
+    pub fn parse(text: &str) -> Symbols {
+        let mut symbols = Symbols::new();
+
+        for (addr, label) in text.lines().filter_map(parse_line) {
+            let bank = addr.bank.unwrap_or(0);
+            symbols
+                .labels
+                .entry((bank, addr.addr))
+                .or_insert_with(|| label.to_string());
+            symbols.addresses.insert(label.to_string(), addr);
+        }
+
+        symbols
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.addresses.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.addresses.len()
+    }
+
+    pub fn address(&self, label: &str) -> Option<BankedAddress> {
+        self.addresses.get(label).copied()
+    }
+
+    /// Every address with a label, and the first label given for it.
+    pub fn labels(&self) -> impl Iterator<Item = (BankedAddress, &str)> {
+        self.labels
+            .iter()
+            .map(|(&(bank, addr), label)| (BankedAddress::in_bank(bank, addr), label.as_str()))
+    }
+
+    /// The label exactly at an address.
+    pub fn label_at(&self, addr: BankedAddress) -> Option<&str> {
+        let bank = addr.bank.unwrap_or(0);
+        self.labels.get(&(bank, addr.addr)).map(String::as_str)
+    }
+
+    /// The label at an address, or the closest one before it in the same
+    /// part of memory as `Label+offset`. Addresses without a bank are looked
+    /// up in bank 0.
+    pub fn label(&self, addr: BankedAddress) -> Option<String> {
+        let bank = addr.bank.unwrap_or(0);
+        let start = region_start(addr.addr);
+
+        let (&(_, label_addr), label) = self
+            .labels
+            .range((bank, start)..=(bank, addr.addr))
+            .next_back()?;
+
+        match addr.addr - label_addr {
+            0 => Some(label.clone()),
+            offset => Some(format!("{}+{}", label, offset)),
+        }
+    }
+}
+
+impl GameBoy {
+    /// An address followed by its label, like `00:0153 <Main+3>`.
+    pub(super) fn describe(&self, addr: BankedAddress) -> String {
+        format!("{}{}", addr, self.label_suffix(addr))
+    }
+
+    /// The label for an address in angle brackets, starting with a space, or
+    /// nothing if there isn't one.
+    pub(super) fn label_suffix(&self, addr: BankedAddress) -> String {
+        match self.symbols.label(addr) {
+            Some(label) => format!(" <{}>", label),
+            None => String::new(),
+        }
+    }
+
+    /// The label suffix for whatever the instruction at `at` jumps to, calls
+    /// or refers to. Targets in the same part of memory are taken to be in
+    /// the same bank.
+    pub(super) fn describe_target(&self, at: BankedAddress, instruction: &Instruction) -> String {
+        let Some(target) = instruction.target(at.addr) else {
+            return String::new();
+        };
+
+        let target = match at.bank {
+            Some(bank) if region_start(target) == region_start(at.addr) => {
+                BankedAddress::in_bank(bank, target)
+            }
+            _ => self.mmu.banked(target),
+        };
+
+        self.label_suffix(target)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Symbols;
+    use crate::mmu::address::BankedAddress;
+
+    const SYM: &str = "\
+; File generated by rgblink
+[labels]
+00:0150 Main
+00:0150 Start ; a second name
+00:0200 Main.loop
+01:4000 BankedRoutine
+00:C000 wBuffer
+not a label
+00:zzzz Broken
+";
+
+    #[test]
+    fn parses_labels() {
+        let symbols = Symbols::parse(SYM);
+        assert_eq!(symbols.len(), 5);
+        assert_eq!(
+            symbols.address("Main"),
+            Some(BankedAddress::in_bank(0, 0x150))
+        );
+        assert_eq!(
+            symbols.address("Start"),
+            Some(BankedAddress::in_bank(0, 0x150))
+        );
+        assert_eq!(
+            symbols.address("Main.loop"),
+            Some(BankedAddress::in_bank(0, 0x200))
+        );
+        assert_eq!(
+            symbols.address("BankedRoutine"),
+            Some(BankedAddress::in_bank(1, 0x4000))
+        );
+        assert_eq!(symbols.address("Broken"), None);
+        assert_eq!(symbols.address("a"), None);
+    }
+
+    #[test]
+    fn first_label_names_the_address() {
+        let symbols = Symbols::parse(SYM);
+        assert_eq!(
+            symbols.label_at(BankedAddress::in_bank(0, 0x150)),
+            Some("Main")
+        );
+        assert_eq!(symbols.label_at(BankedAddress::in_bank(0, 0x151)), None);
+    }
+
+    #[test]
+    fn labels_with_offsets() {
+        let symbols = Symbols::parse(SYM);
+        let label = |bank, addr| symbols.label(BankedAddress::in_bank(bank, addr));
+
+        assert_eq!(label(0, 0x150), Some("Main".to_string()));
+        assert_eq!(label(0, 0x153), Some("Main+3".to_string()));
+        assert_eq!(label(0, 0x210), Some("Main.loop+16".to_string()));
+        assert_eq!(label(1, 0x4001), Some("BankedRoutine+1".to_string()));
+        assert_eq!(label(0, 0xC004), Some("wBuffer+4".to_string()));
+        assert_eq!(label(0, 0x100), None);
+
+        // Labels don't reach into other banks or parts of memory
+        assert_eq!(label(2, 0x4001), None);
+        assert_eq!(label(0, 0x4001), None);
+        assert_eq!(
+            symbols.label(BankedAddress::new(0x153)),
+            Some("Main+3".to_string())
+        );
+    }
+}
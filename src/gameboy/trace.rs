@@ -0,0 +1,415 @@
+use std::fmt::{self, Write as _};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use clap::ValueEnum;
+use colored::*;
+
+use super::breakpoint::Breakpoint;
+use super::debugger::{parse_address, parse_breakpoint};
+use super::GameBoy;
+use crate::disassembler::Line;
+use crate::instructions::r16::R16;
+use crate::mmu::address::BankedAddress;
+
+// Files are written out a megabyte at a time
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+// How many full files are kept when the trace is rotated, as `trace.log.1`
+// for the newest up to `trace.log.3`
+const ROTATED_FILES: usize = 3;
+
+/// How each instruction is written to the trace.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum TraceFormat {
+    /// The registers and the bytes at PC, as gameboy-doctor compares them.
+    #[default]
+    Doctor,
+
+    /// The address, bytes and instruction, then the registers, laid out
+    /// like BGB's debugger.
+    Bgb,
+
+    /// A JSON object per line with the registers, cycles, bank, instruction
+    /// and the memory it read and wrote.
+    Json,
+}
+
+/// Writes the instructions run to a file or stdout, one per line. Tracing
+/// can be turned on and off at breakpoints, and limited to a range of
+/// addresses. Files are started again once they get too big, keeping a few
+/// of the last ones.
+pub struct Trace {
+    pub format: TraceFormat,
+    path: Option<String>,
+    writer: BufWriter<Box<dyn Write>>,
+    line: String,
+
+    // Bytes written to the current file, and how big it can get
+    size: u64,
+    max_size: Option<u64>,
+
+    // Whether instructions are being written now
+    pub on: bool,
+
+    // Turn tracing on and off when these are hit
+    pub start: Option<Breakpoint>,
+    pub stop: Option<Breakpoint>,
+
+    // Only instructions from the first address to the second are written.
+    // The bank of the first is used for both.
+    pub range: Option<(BankedAddress, u16)>,
+}
+
+impl Trace {
+    /// Trace to a file. A `max_size` of 0 lets it grow forever.
+    pub fn to_file(path: &str, format: TraceFormat, max_size: u64) -> Result<Trace, String> {
+        let file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut trace = Trace::new(Box::new(file), format);
+        trace.path = Some(path.to_string());
+        trace.max_size = (max_size > 0).then_some(max_size);
+        Ok(trace)
+    }
+
+    pub fn to_stdout(format: TraceFormat) -> Trace {
+        Trace::new(Box::new(io::stdout()), format)
+    }
+
+    fn new(writer: Box<dyn Write>, format: TraceFormat) -> Trace {
+        Trace {
+            format,
+            path: None,
+            writer: BufWriter::with_capacity(BUFFER_SIZE, writer),
+            line: String::new(),
+            size: 0,
+            max_size: None,
+            on: true,
+            start: None,
+            stop: None,
+            range: None,
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn in_range(&self, at: BankedAddress) -> bool {
+        match self.range {
+            Some((start, end)) => {
+                (start.addr..=end).contains(&at.addr)
+                    && start.bank.is_none_or(|b| at.bank == Some(b))
+            }
+            None => true,
+        }
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        self.line.push('\n');
+        self.writer.write_all(self.line.as_bytes())?;
+        self.size += self.line.len() as u64;
+
+        if self.max_size.is_some_and(|max_size| self.size >= max_size) {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+
+    /// Move the full file to `.1` and the older ones along, then start the
+    /// file again.
+    fn rotate(&mut self) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        self.writer.flush()?;
+        for i in (1..ROTATED_FILES).rev() {
+            let older = format!("{}.{}", path, i);
+            if Path::new(&older).exists() {
+                fs::rename(&older, format!("{}.{}", path, i + 1))?;
+            }
+        }
+        fs::rename(path, format!("{}.1", path))?;
+
+        self.writer = BufWriter::with_capacity(BUFFER_SIZE, Box::new(File::create(path)?));
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let format = match self.format {
+            TraceFormat::Doctor => "doctor",
+            TraceFormat::Bgb => "bgb",
+            TraceFormat::Json => "json",
+        };
+        let path = self.path.as_deref().unwrap_or("stdout");
+        let on = if self.on { "on" } else { "off" };
+        write!(f, "Tracing to {} as {} ({})", path, format, on)?;
+
+        if let Some(start) = &self.start {
+            write!(f, "\nstart at {}", start)?;
+        }
+        if let Some(stop) = &self.stop {
+            write!(f, "\nstop at  {}", stop)?;
+        }
+        if let Some((start, end)) = self.range {
+            let end = BankedAddress { addr: end, ..start };
+            write!(f, "\nonly     {} to {}", start, end)?;
+        }
+        Ok(())
+    }
+}
+
+/// A memory region's name and bank as BGB shows them, like `ROM1` or `WRA0`.
+fn bgb_region(at: BankedAddress) -> String {
+    let bank = at.bank.unwrap_or(0);
+    match at.addr {
+        0x0000..=0x3FFF => "ROM0".to_string(),
+        0x4000..=0x7FFF => format!("ROM{:X}", bank),
+        0x8000..=0x9FFF => format!("VRA{}", bank),
+        0xA000..=0xBFFF => format!("SRA{:X}", bank),
+        0xC000..=0xCFFF => "WRA0".to_string(),
+        0xD000..=0xDFFF => format!("WRA{}", bank),
+        0xE000..=0xFDFF => "ECH0".to_string(),
+        0xFE00..=0xFEFF => "OAM".to_string(),
+        0xFF00..=0xFF7F => "I/O".to_string(),
+        _ => "HRAM".to_string(),
+    }
+}
+
+/// Text without the colours the terminal shows it in.
+fn plain(text: &str) -> String {
+    let mut plain = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| *c == 'm');
+        } else {
+            plain.push(c);
+        }
+    }
+
+    plain
+}
+
+impl GameBoy {
+    /// Start the line for the instruction about to run, if it's being
+    /// traced. Returns whether it is, for `end_trace_line` to finish it once
+    /// the instruction has run.
+    pub(super) fn begin_trace_line(&mut self) -> bool {
+        let Some(mut trace) = self.trace.take() else {
+            return false;
+        };
+
+        if trace
+            .start
+            .as_mut()
+            .is_some_and(|b| self.breakpoint_matches(b) && b.hit())
+        {
+            trace.on = true;
+        }
+        if trace
+            .stop
+            .as_mut()
+            .is_some_and(|b| self.breakpoint_matches(b) && b.hit())
+        {
+            trace.on = false;
+        }
+
+        let at = self.mmu.banked(self.cpu.registers.pc);
+        let traced = trace.on && trace.in_range(at);
+        if traced {
+            trace.line.clear();
+            self.format_trace_line(&mut trace.line, trace.format, at);
+            self.mmu.record_accesses = trace.format == TraceFormat::Json;
+        }
+
+        self.trace = Some(trace);
+        traced
+    }
+
+    fn format_trace_line(&self, line: &mut String, format: TraceFormat, at: BankedAddress) {
+        let registers = &self.cpu.registers;
+        let f = u8::from(registers.f);
+        let pc = registers.pc;
+
+        if format == TraceFormat::Doctor {
+            write!(
+                line,
+                "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} \
+                    PCMEM:{:02X},{:02X},{:02X},{:02X}",
+                registers.a,
+                f,
+                registers.b,
+                registers.c,
+                registers.d,
+                registers.e,
+                registers.h,
+                registers.l,
+                registers.sp,
+                pc,
+                self.mmu.peek_byte(pc),
+                self.mmu.peek_byte(pc.wrapping_add(1)),
+                self.mmu.peek_byte(pc.wrapping_add(2)),
+                self.mmu.peek_byte(pc.wrapping_add(3)),
+            )
+            .unwrap();
+            return;
+        }
+
+        let decoded = Line::decode(at, |addr| self.mmu.peek_byte(addr));
+        let instruction = plain(&decoded.instruction.to_string());
+
+        if format == TraceFormat::Bgb {
+            let bytes = decoded
+                .bytes
+                .iter()
+                .map(|b| format!("{:02X}", b))
+                .collect::<Vec<String>>()
+                .join(" ");
+
+            write!(
+                line,
+                "{}:{:04X} {:<9} {:<24} AF={:02X}{:02X} BC={:04X} DE={:04X} HL={:04X} SP={:04X} cy={}",
+                bgb_region(at),
+                pc,
+                bytes,
+                instruction,
+                registers.a,
+                f,
+                registers.get_r16(R16::BC),
+                registers.get_r16(R16::DE),
+                registers.get_r16(R16::HL),
+                registers.sp,
+                self.cycles,
+            )
+            .unwrap();
+            return;
+        }
+
+        // The accesses are added once the instruction has run
+        write!(
+            line,
+            "{{\"cycles\":{},\"bank\":{},\"pc\":{},\"bytes\":{:?},\"instruction\":{},\
+                \"a\":{},\"f\":{},\"b\":{},\"c\":{},\"d\":{},\"e\":{},\"h\":{},\"l\":{},\
+                \"sp\":{},\"ime\":{},\"accesses\":[",
+            self.cycles,
+            at.bank.unwrap_or(0),
+            pc,
+            decoded.bytes,
+            serde_json::Value::from(instruction),
+            registers.a,
+            f,
+            registers.b,
+            registers.c,
+            registers.d,
+            registers.e,
+            registers.h,
+            registers.l,
+            registers.sp,
+            self.cpu.ime,
+        )
+        .unwrap();
+    }
+
+    /// Finish the line `begin_trace_line` started and write it out.
+    pub(super) fn end_trace_line(&mut self) {
+        let Some(trace) = &mut self.trace else {
+            return;
+        };
+
+        if trace.format == TraceFormat::Json {
+            self.mmu.record_accesses = false;
+            for (i, access) in self.mmu.take_accesses().iter().enumerate() {
+                let separator = if i == 0 { "" } else { "," };
+                write!(
+                    trace.line,
+                    "{}{{\"addr\":{},\"value\":{},\"write\":{}}}",
+                    separator, access.addr, access.value, access.write
+                )
+                .unwrap();
+            }
+            trace.line.push_str("]}");
+        }
+
+        if let Err(e) = trace.write_line() {
+            eprintln!("{}", format!("Stopped tracing: {}", e).red());
+            self.trace = None;
+        }
+    }
+
+    /// Write out whatever of the trace is still buffered.
+    pub fn flush_trace(&mut self) {
+        if let Some(Err(e)) = self.trace.as_mut().map(Trace::flush) {
+            eprintln!("{}", format!("Stopped tracing: {}", e).red());
+            self.trace = None;
+        }
+    }
+
+    /// Only trace from when a breakpoint is hit, given like one for the
+    /// debugger's break command. Tracing stops until then.
+    pub fn trace_from(&mut self, breakpoint: &str) -> Result<(), String> {
+        let args: Vec<&str> = breakpoint.split_whitespace().collect();
+        let breakpoint = parse_breakpoint(&args, &self.symbols)?;
+        let trace = self.trace.as_mut().ok_or(NO_TRACE)?;
+        trace.start = Some(breakpoint);
+        trace.on = false;
+        Ok(())
+    }
+
+    /// Stop tracing when a breakpoint is hit.
+    pub fn trace_until(&mut self, breakpoint: &str) -> Result<(), String> {
+        let args: Vec<&str> = breakpoint.split_whitespace().collect();
+        let breakpoint = parse_breakpoint(&args, &self.symbols)?;
+        self.trace.as_mut().ok_or(NO_TRACE)?.stop = Some(breakpoint);
+        Ok(())
+    }
+
+    /// Only trace instructions from `start` to `end`.
+    pub fn trace_range(&mut self, start: &str, end: &str) -> Result<(), String> {
+        let start = parse_address(start, &self.symbols).ok_or("Invalid start address")?;
+        let end = parse_address(end, &self.symbols).ok_or("Invalid end address")?;
+        if end.addr < start.addr || end.bank.is_some_and(|bank| start.bank != Some(bank)) {
+            return Err("The range has to end after it starts, in the same bank".to_string());
+        }
+
+        self.trace.as_mut().ok_or(NO_TRACE)?.range = Some((start, end.addr));
+        Ok(())
+    }
+
+    /// The debugger's trace command.
+    pub(super) fn trace_command(&mut self, args: &[&str]) -> Result<(), String> {
+        let trace = self.trace.as_mut().ok_or(NO_TRACE)?;
+
+        match args {
+            [] => println!("{}", trace),
+            ["on"] => trace.on = true,
+            ["off"] => trace.on = false,
+            ["start", "off"] => trace.start = None,
+            ["stop", "off"] => trace.stop = None,
+            ["range", "off"] => trace.range = None,
+            ["start", breakpoint @ ..] if !breakpoint.is_empty() => {
+                self.trace_from(&breakpoint.join(" "))?
+            }
+            ["stop", breakpoint @ ..] if !breakpoint.is_empty() => {
+                self.trace_until(&breakpoint.join(" "))?
+            }
+            ["range", start, end] => self.trace_range(start, end)?,
+            _ => {
+                return Err(
+                    "Usage: trace [on|off], trace start|stop a [hit n] [if e]|off, \
+                        trace range a b|off"
+                        .to_string(),
+                )
+            }
+        }
+
+        Ok(())
+    }
+}
+
+const NO_TRACE: &str = "There's no trace. Start the emulator with --trace to write one";
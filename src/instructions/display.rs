@@ -23,6 +23,10 @@ fn split_camel_case(s: &str) -> Vec<String> {
 
 impl fmt::Display for Instruction {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Instruction::ILLEGAL = self {
+            return write!(f, "illegal");
+        }
+
         // Create a temporary buffer to capture the default debug output
         let mut buffer = String::new();
 
@@ -65,7 +69,7 @@ impl fmt::Display for Instruction {
                 "r16mem" => args.pop().unwrap().to_string().blue().to_string(),
                 "imm8mem" => format!("[{}]", args.pop().unwrap()).blue().to_string(),
                 "imm16mem" => format!("[{}]", args.pop().unwrap()).blue().to_string(),
-                "cmem" => format!("[{}]", args.pop().unwrap()).blue().to_string(),
+                "cmem" => "[c]".blue().to_string(),
                 "tgt3" => format!("[{}]", args.pop().unwrap()).blue().to_string(),
 
                 "b3" => args.pop().unwrap().blue().to_string(),
@@ -317,6 +317,32 @@ pub enum Instruction {
     ILLEGAL,
 }
 
+impl Instruction {
+    /// The address the instruction at `at` jumps to, calls or reads or
+    /// writes directly, if it has one.
+    pub fn target(&self, at: u16) -> Option<u16> {
+        match *self {
+            Instruction::CallImm16(addr)
+            | Instruction::CallCondImm16(_, addr)
+            | Instruction::JpImm16(addr)
+            | Instruction::JpCondImm16(_, addr)
+            | Instruction::LdAImm16mem(addr)
+            | Instruction::LdImm16memA(addr)
+            | Instruction::LdImm16memSp(addr) => Some(addr),
+            Instruction::LdhAImm8mem(addr) | Instruction::LdhImm8memA(addr) => {
+                Some(0xFF00 | addr as u16)
+            }
+
+            // Relative to the end of the two byte instruction
+            Instruction::JrImm8(offset) | Instruction::JrCondImm8(_, offset) => {
+                Some(at.wrapping_add(2).wrapping_add_signed(offset as i16))
+            }
+            Instruction::RstTgt3(target) => Some(target as u16),
+            _ => None,
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use serde_json::Value;
@@ -335,9 +361,7 @@ mod test {
 
         let error_message = format!(
             "{} (opcode: {:#04X} {:#04X})",
-            json["mnemonic"],
-            opcode,
-            imm8
+            json["mnemonic"], opcode, imm8
         );
 
         assert_eq!(expected_bytes, actual_bytes, "{}", error_message,);
@@ -388,9 +412,8 @@ mod test {
     use crate::instructions::{Cond, Instruction, R16mem, R16, R8};
 
     fn get_instruction(opcode: u8, imm8: u8, arg2: u8) -> Instruction {
-        match parse(opcode, imm8, arg2) {
-            (ins, _, _) => ins,
-        }
+        let (ins, _, _) = parse(opcode, imm8, arg2);
+        ins
     }
 
     // Block 0
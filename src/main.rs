@@ -1,45 +1,210 @@
 pub mod cartridge;
 pub mod cpu;
 pub mod debugger;
+pub mod disassembler;
 pub mod gameboy;
 pub mod instructions;
 pub mod mmu;
 mod renderer;
+pub mod state;
 
 use cartridge::header::CartridgeHeader;
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::*;
 use debugger::{enable_debug, enable_gameboy_doctor, is_debug_enabled};
-use std::fs::File;
-use std::io::Read;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::Path;
 use std::process::exit;
 use std::sync::atomic::AtomicBool;
 use std::sync::mpsc;
 use std::sync::mpsc::{Receiver, Sender};
 use std::thread;
+use std::time::{Duration, Instant};
 
-use gameboy::GameBoy;
+use disassembler::code_map::CodeMap;
+use gameboy::model::Model;
+use gameboy::rewind::Rewind;
+use gameboy::symbols::Symbols;
+use gameboy::trace::{Trace, TraceFormat};
+use gameboy::{Boot, GameBoy};
 use minifb::Key;
-use renderer::window_loop;
+use mmu::bootrom::{CGB_BOOT_ROM_SIZE, DMG_BOOT_ROM_SIZE};
+use mmu::code_data_log::CodeDataLog;
+use mmu::ppu::colour_correction::ColourCorrection;
+use mmu::ppu::dmg_palette::DmgPalette;
+use renderer::{window_loop, Frame, Input};
 
 pub static DEBUG_MODE: AtomicBool = AtomicBool::new(false);
 
+const TARGET_FPS: f64 = 60.0;
+
+// Frames between saves of the code/data log, so not much is lost when the
+// emulator is stopped with Ctrl+C
+const CDL_SAVE_INTERVAL: u64 = 600;
+
 /// Simple program to greet a person
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Whether to enable the doctor or not.
     #[arg(short, long, default_value_t = false)]
     doctor: bool,
 
+    /// The Game Boy to emulate: auto, dmg0, dmg, mgb, sgb, sgb2, cgb or agb.
+    /// auto picks one from the cartridge header.
+    //
+    // Written out in full so clap hands "auto" to the parser as a value
+    #[arg(long, default_value = "auto", value_parser = parse_model)]
+    model: std::option::Option<Model>,
+
+    /// Skip the boot ROM and start the game with the hardware set up the way
+    /// the model's boot ROM leaves it.
+    #[arg(long, default_value_t = false)]
+    skip_boot: bool,
+
+    /// Run a boot ROM from a file instead of the built in DMG one. Takes a
+    /// 256 byte DMG/MGB/SGB boot ROM or a 2304 byte CGB one.
+    #[arg(long)]
+    boot_rom: Option<String>,
+
+    /// How Game Boy Color colours are adjusted for the screen.
+    #[arg(long, value_enum, default_value_t = ColourCorrection::Lcd)]
+    colour_correction: ColourCorrection,
+
+    /// JSON file of extra DMG palettes.
+    #[arg(long)]
+    palettes: Option<String>,
+
+    /// Name of the DMG palette to start with. Press P to cycle through them.
+    #[arg(long)]
+    palette: Option<String>,
+
+    /// Memory set aside for rewinding, in MiB. Hold R to rewind. 0 turns
+    /// rewinding off.
+    #[arg(long, default_value_t = 32)]
+    rewind_buffer: usize,
+
+    /// Frames between rewind snapshots. Rewinding plays back this many
+    /// times faster than the game runs.
+    #[arg(long, default_value_t = 2)]
+    rewind_interval: u32,
+
+    /// RGBDS or no$gmb symbol file with labels for the debugger. Defaults to
+    /// a .sym file next to the ROM.
+    #[arg(long)]
+    symbols: Option<String>,
+
+    /// Log which bytes of the ROM are run as code and which are read as
+    /// data to a .cdl file next to the ROM, adding to what's there already.
+    /// The asm command uses it to tell code from data.
+    #[arg(long, default_value_t = false)]
+    cdl: bool,
+
+    /// Write each instruction run to a file. --doctor without this prints
+    /// them in gameboy-doctor's format.
+    #[arg(long)]
+    trace: Option<String>,
+
+    /// How instructions are written to the trace.
+    #[arg(long, value_enum, default_value_t = TraceFormat::Doctor)]
+    trace_format: TraceFormat,
+
+    /// Only start tracing at a breakpoint, written like one for the
+    /// debugger's break command, e.g. "01:4000 hit 3".
+    #[arg(long)]
+    trace_start: Option<String>,
+
+    /// Stop tracing at a breakpoint.
+    #[arg(long)]
+    trace_stop: Option<String>,
+
+    /// Only trace instructions between two addresses, e.g. 01:4000-01:4100.
+    #[arg(long)]
+    trace_range: Option<String>,
+
+    /// Size the trace can grow to in MiB before it is moved to .1 and
+    /// started again, keeping the last few. 0 lets it grow forever.
+    #[arg(long, default_value_t = 256)]
+    trace_max_size: u64,
+
     rom_path: Option<String>,
 }
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Write a listing of one bank of a ROM, decoded as code.
+    Disasm {
+        rom_path: String,
+
+        /// The ROM bank to list, in decimal or hex with $ or 0x in front.
+        #[arg(long, default_value = "0", value_parser = parse_bank)]
+        bank: u16,
+
+        /// RGBDS or no$gmb symbol file with labels. Defaults to a .sym file
+        /// next to the ROM.
+        #[arg(long)]
+        symbols: Option<String>,
+
+        /// Where to write the listing, instead of stdout.
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+
+    /// Write RGBDS source that assembles back into the ROM. Code is found
+    /// by following jumps and calls from the entry point and the RST and
+    /// interrupt vectors, and everything else is left as data.
+    Asm {
+        rom_path: String,
+
+        /// RGBDS or no$gmb symbol file with labels. Defaults to a .sym file
+        /// next to the ROM.
+        #[arg(long)]
+        symbols: Option<String>,
+
+        /// Code/data log from playing with --cdl. Everything it saw run is
+        /// disassembled. Defaults to a .cdl file next to the ROM.
+        #[arg(long)]
+        cdl: Option<String>,
+
+        /// The directory to write the source to.
+        #[arg(short, long, default_value = ".")]
+        output: String,
+    },
+}
+
 fn main() {
     let args = Args::parse();
 
-    let rom_path = match args.rom_path {
-        Some(path) => path,
+    if let Some(command) = &args.command {
+        let result = match command {
+            Command::Disasm {
+                rom_path,
+                bank,
+                symbols,
+                output,
+            } => disassemble(rom_path, *bank, symbols, output),
+            Command::Asm {
+                rom_path,
+                symbols,
+                cdl,
+                output,
+            } => write_source(rom_path, symbols, cdl, output),
+        };
+
+        if let Err(e) = result {
+            eprintln!("{}", e.red());
+            exit(-1);
+        }
+        return;
+    }
+
+    let rom_path = match &args.rom_path {
+        Some(path) => path.clone(),
         _ => "roms/super-mario-land.gb".to_string(),
     };
 
@@ -47,17 +212,237 @@ fn main() {
         enable_gameboy_doctor();
     }
 
-    let (tx, rx) = mpsc::channel::<Vec<u32>>();
-    let (tx_key, rx_key) = mpsc::channel::<(bool, Key)>();
+    let (tx, rx) = mpsc::channel::<Frame>();
+    let (tx_key, rx_key) = mpsc::channel::<Input>();
     let rom = read_file_to_bytes(rom_path.as_str()).unwrap();
-    let game_title = CartridgeHeader::new(&rom).unwrap().title();
+    let header = CartridgeHeader::new(&rom).unwrap();
+    let game_title = header.title();
+    let boot = boot_mode(&args);
+    let model = select_model(&args, &header, &boot);
 
-    let _ = thread::spawn(move || emulator_loop(rom, tx, rx_key));
+    let emulator =
+        thread::spawn(move || emulator_loop(rom, rom_path, model, boot, args, tx, rx_key));
     window_loop(rx, tx_key, &game_title);
+
+    // Give the emulator a chance to save before exiting, unless it's waiting
+    // in the debugger
+    if !is_debug_enabled() {
+        let _ = emulator.join();
+    }
+}
+
+fn parse_model(value: &str) -> Result<Option<Model>, String> {
+    match value {
+        "auto" => Ok(None),
+        _ => Model::from_str(value, true).map(Some),
+    }
+}
+
+fn parse_bank(value: &str) -> Result<u16, String> {
+    gameboy::expression::parse_number(value)
+        .and_then(|bank| u16::try_from(bank).ok())
+        .ok_or(format!("{} isn't a bank number", value))
+}
+
+fn boot_mode(args: &Args) -> Boot {
+    if let Some(path) = &args.boot_rom {
+        let boot_rom = read_file_to_bytes(path).unwrap();
+        if ![DMG_BOOT_ROM_SIZE, CGB_BOOT_ROM_SIZE].contains(&boot_rom.len()) {
+            eprintln!(
+                "{}",
+                format!("{} isn't a boot ROM, it is {} bytes", path, boot_rom.len()).red()
+            );
+            exit(-1);
+        }
+        return Boot::Rom(boot_rom);
+    }
+
+    // The doctor expects to start with the registers the boot ROM leaves
+    if args.skip_boot || args.doctor {
+        Boot::Skip
+    } else {
+        Boot::BuiltIn
+    }
 }
 
-fn emulator_loop(rom: Vec<u8>, tx: Sender<Vec<u32>>, rx: Receiver<(bool, Key)>) {
-    let mut gameboy = GameBoy::new(rom);
+fn select_model(args: &Args, header: &CartridgeHeader, boot: &Boot) -> Model {
+    match (args.model, boot) {
+        (Some(model), _) => model,
+        (None, Boot::Rom(boot_rom)) if boot_rom.len() == CGB_BOOT_ROM_SIZE => Model::Cgb,
+
+        // gameboy-doctor's logs are from a DMG, even for colour games
+        (None, _) if args.doctor => Model::Dmg,
+        (None, _) => Model::auto(header),
+    }
+}
+
+/// The file given for an option, or one with the extension next to the ROM
+/// if there is one.
+fn path_beside_rom(given: &Option<String>, rom_path: &str, extension: &str) -> Option<String> {
+    match given {
+        Some(path) => Some(path.clone()),
+        None => {
+            let path = Path::new(rom_path).with_extension(extension);
+            path.exists().then(|| path.to_string_lossy().to_string())
+        }
+    }
+}
+
+fn load_symbols(args: &Args, rom_path: &str) -> Symbols {
+    let Some(path) = path_beside_rom(&args.symbols, rom_path, "sym") else {
+        return Symbols::new();
+    };
+
+    match Symbols::load(&path) {
+        Ok(symbols) => {
+            eprintln!("Loaded {} symbols from {}", symbols.len(), path);
+            symbols
+        }
+        Err(e) => {
+            eprintln!("{}", format!("Couldn't load symbols: {}", e).red());
+            Symbols::new()
+        }
+    }
+}
+
+fn read_symbols(symbols: &Option<String>, rom_path: &str) -> Result<Symbols, String> {
+    match path_beside_rom(symbols, rom_path, "sym") {
+        Some(path) => Symbols::load(&path).map_err(|e| format!("Couldn't load symbols: {}", e)),
+        None => Ok(Symbols::new()),
+    }
+}
+
+fn disassemble(
+    rom_path: &str,
+    bank: u16,
+    symbols: &Option<String>,
+    output: &Option<String>,
+) -> Result<(), String> {
+    let rom = read_file_to_bytes(rom_path).map_err(|e| format!("{}: {}", rom_path, e))?;
+    let symbols = read_symbols(symbols, rom_path)?;
+
+    // Instructions are coloured for the terminal
+    colored::control::set_override(false);
+    let listing = disassembler::bank_listing(&rom, bank, &symbols)?;
+
+    match output {
+        Some(path) => File::create(path).and_then(|mut file| file.write_all(listing.as_bytes())),
+        None => std::io::stdout().write_all(listing.as_bytes()),
+    }
+    .map_err(|e| format!("Couldn't write the listing: {}", e))
+}
+
+fn write_source(
+    rom_path: &str,
+    symbols: &Option<String>,
+    cdl: &Option<String>,
+    output: &str,
+) -> Result<(), String> {
+    let rom = read_file_to_bytes(rom_path).map_err(|e| format!("{}: {}", rom_path, e))?;
+    let symbols = read_symbols(symbols, rom_path)?;
+    let log = match path_beside_rom(cdl, rom_path, "cdl") {
+        Some(path) => Some(
+            CodeDataLog::load(&path, rom.len())
+                .map_err(|e| format!("Couldn't load the code/data log: {}", e))?,
+        ),
+        None => None,
+    };
+    let name = Path::new(rom_path)
+        .file_stem()
+        .map_or("game".to_string(), |stem| {
+            stem.to_string_lossy().to_string()
+        });
+
+    let map = CodeMap::analyse(&rom, log.as_ref());
+    let files = disassembler::rgbds::rgbds_source(&rom, &map, &symbols, &name);
+
+    fs::create_dir_all(output).map_err(|e| format!("{}: {}", output, e))?;
+    for (file, source) in files.iter() {
+        let path = Path::new(output).join(file);
+        fs::write(&path, source).map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+
+    println!(
+        "Wrote {}.asm and {} banks to {}",
+        name,
+        files.len() - 1,
+        output
+    );
+    Ok(())
+}
+
+fn start_trace(gameboy: &mut GameBoy, args: &Args) -> Result<(), String> {
+    gameboy.trace = match &args.trace {
+        Some(path) => Some(Trace::to_file(
+            path,
+            args.trace_format,
+            args.trace_max_size * 1024 * 1024,
+        )?),
+        None if args.doctor => Some(Trace::to_stdout(TraceFormat::Doctor)),
+        None => return Ok(()),
+    };
+
+    if let Some(breakpoint) = &args.trace_start {
+        gameboy.trace_from(breakpoint)?;
+    }
+    if let Some(breakpoint) = &args.trace_stop {
+        gameboy.trace_until(breakpoint)?;
+    }
+    if let Some(range) = &args.trace_range {
+        let (start, end) = range
+            .split_once('-')
+            .ok_or("The range should be written start-end")?;
+        gameboy.trace_range(start, end)?;
+    }
+
+    Ok(())
+}
+
+fn emulator_loop(
+    rom: Vec<u8>,
+    rom_path: String,
+    model: Model,
+    boot: Boot,
+    args: Args,
+    tx: Sender<Frame>,
+    rx: Receiver<Input>,
+) {
+    let rom_size = rom.len();
+    let mut gameboy = GameBoy::new(rom, model, boot);
+    gameboy.symbols = load_symbols(&args, &rom_path);
+
+    let cdl_path = args
+        .cdl
+        .then(|| Path::new(&rom_path).with_extension("cdl"))
+        .map(|path| path.to_string_lossy().to_string());
+    if let Some(path) = &cdl_path {
+        gameboy.mmu.code_data_log = load_code_data_log(path, rom_size);
+    }
+
+    gameboy.rom_path = Some(rom_path);
+    gameboy.rewind = Rewind::new(args.rewind_buffer * 1024 * 1024, args.rewind_interval);
+    gameboy
+        .mmu
+        .ppu
+        .set_colour_correction(args.colour_correction);
+
+    if let Some(path) = &args.palettes {
+        match DmgPalette::load(path) {
+            Ok(palettes) => gameboy.add_palettes(palettes),
+            Err(e) => eprintln!("{}", format!("Couldn't load palettes: {}", e).red()),
+        }
+    }
+
+    if let Some(name) = &args.palette {
+        if !gameboy.select_palette(name) {
+            eprintln!("{}", format!("Unknown palette {}", name).red());
+        }
+    }
+
+    if let Err(e) = start_trace(&mut gameboy, &args) {
+        eprintln!("{}", format!("Couldn't start tracing: {}", e).red());
+        gameboy.trace = None;
+    }
 
     ctrlc::set_handler(move || {
         if is_debug_enabled() {
@@ -72,34 +457,169 @@ fn emulator_loop(rom: Vec<u8>, tx: Sender<Vec<u32>>, rx: Receiver<(bool, Key)>)
     })
     .expect("Error setting Ctrl-C handler");
 
+    let frame_duration = Duration::from_secs_f64(1.0 / TARGET_FPS);
+    let mut last_frame_time = Instant::now();
+    let mut rewinding = false;
+    let mut frames: u64 = 0;
+
     loop {
         // Enter debug mode if Ctrl-C received
         if is_debug_enabled() {
             gameboy.debugger_cli()
         }
 
-        // Step forward
-        gameboy.step();
+        // Step forward, or back a snapshot at a time while rewinding
+        let frame_available = if rewinding {
+            gameboy.step_back();
+            true
+        } else {
+            gameboy.step();
+            gameboy.mmu.ppu.get_and_reset_frame_available()
+        };
 
         // Render window
-        if gameboy.mmu.ppu.get_and_reset_frame_available() {
-            let _ = tx.send(gameboy.mmu.ppu.frame_buffer.clone());
+        if frame_available {
+            if !rewinding {
+                gameboy.capture_rewind();
+            }
+
+            frames += 1;
+            if frames.is_multiple_of(CDL_SAVE_INTERVAL) {
+                save_code_data_log(&gameboy, &cdl_path);
+            }
+
+            let (width, height) = gameboy.screen_size();
+            let _ = tx.send(Frame {
+                buffer: gameboy.frame(),
+                width,
+                height,
+            });
+
+            // Sleep off whatever is left of this frame
+            let elapsed = last_frame_time.elapsed();
+            if elapsed < frame_duration {
+                thread::sleep(frame_duration - elapsed);
+            }
+            last_frame_time = Instant::now();
         }
 
         // Handle joypad input
         loop {
             match rx.try_recv() {
-                Ok((true, key)) => gameboy.mmu.joypad.handle_key_down(key),
-                Ok((false, key)) => gameboy.mmu.joypad.handle_key_up(key),
+                Ok(Input::KeyDown(Key::P)) => {
+                    gameboy.next_palette();
+                    let palette = &gameboy.palettes[gameboy.palette_index()];
+                    eprintln!("Palette: {}", palette.name);
+                }
+                Ok(Input::KeyDown(key)) => gameboy.key_down(key),
+                Ok(Input::KeyUp(key)) => gameboy.key_up(key),
+                Ok(Input::Rewind(held)) => rewinding = held && gameboy.rewind.is_enabled(),
+                Ok(Input::Quit) => {
+                    save_code_data_log(&gameboy, &cdl_path);
+                    gameboy.flush_trace();
+                    return;
+                }
+                Ok(Input::SaveState(slot)) => {
+                    let result = gameboy
+                        .slot_path(slot)
+                        .and_then(|path| gameboy.save_state_file(&path));
+                    match result {
+                        Ok(()) => eprintln!("Saved state to slot {}", slot),
+                        Err(e) => eprintln!("{}", e.red()),
+                    }
+                }
+                Ok(Input::LoadState(slot)) => {
+                    let result = gameboy
+                        .slot_path(slot)
+                        .and_then(|path| gameboy.load_state_file(&path));
+                    match result {
+                        Ok(()) => eprintln!("Loaded state from slot {}", slot),
+                        Err(e) => eprintln!("{}", e.red()),
+                    }
+                }
                 _ => break,
             }
         }
     }
 }
 
+fn load_code_data_log(path: &str, rom_size: usize) -> Option<CodeDataLog> {
+    match CodeDataLog::load(path, rom_size) {
+        Ok(log) => {
+            let (code, data) = log.coverage();
+            eprintln!(
+                "Logging code and data to {}, with {} bytes of code and {} of data so far",
+                path, code, data
+            );
+            Some(log)
+        }
+        Err(e) => {
+            eprintln!(
+                "{}",
+                format!("Couldn't load the code/data log: {}", e).red()
+            );
+            None
+        }
+    }
+}
+
+fn save_code_data_log(gameboy: &GameBoy, path: &Option<String>) {
+    if let (Some(log), Some(path)) = (&gameboy.mmu.code_data_log, path) {
+        if let Err(e) = log.save(path) {
+            eprintln!(
+                "{}",
+                format!("Couldn't save the code/data log: {}", e).red()
+            );
+        }
+    }
+}
+
 fn read_file_to_bytes(filename: &str) -> Result<Vec<u8>, std::io::Error> {
     let mut file = File::open(filename)?;
     let mut buffer = Vec::new();
     file.read_to_end(&mut buffer)?;
     Ok(buffer)
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{boot_mode, select_model, Args};
+    use crate::cartridge::header::CartridgeHeader;
+    use crate::cpu::CPU;
+    use crate::gameboy::model::Model;
+    use clap::Parser;
+
+    #[test]
+    fn doctor_starts_with_the_dmg_registers() {
+        // cpu_instrs is flagged as working on the CGB too
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms/test_roms/cpu_instrs.gb");
+        let rom = std::fs::read(&path).unwrap();
+        let header = CartridgeHeader::new(&rom).unwrap();
+        assert!(header.supports_cgb());
+
+        let args = Args::parse_from(["cowboy", "--doctor", path.to_str().unwrap()]);
+        let model = select_model(&args, &header, &boot_mode(&args));
+        assert_eq!(model, Model::Dmg);
+
+        let registers = CPU::after_boot(model, &header).registers;
+        assert_eq!(registers.a, 0x01);
+        assert_eq!(registers.f.as_byte(), 0xB0);
+        assert_eq!((registers.b, registers.c), (0x00, 0x13));
+        assert_eq!((registers.d, registers.e), (0x00, 0xD8));
+        assert_eq!((registers.h, registers.l), (0x01, 0x4D));
+        assert_eq!(registers.sp, 0xFFFE);
+        assert_eq!(registers.pc, 0x0100);
+    }
+
+    #[test]
+    fn colour_games_run_on_a_cgb_without_the_doctor() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms/test_roms/cpu_instrs.gb");
+        let rom = std::fs::read(&path).unwrap();
+        let header = CartridgeHeader::new(&rom).unwrap();
+
+        let args = Args::parse_from(["cowboy", path.to_str().unwrap()]);
+        assert_eq!(select_model(&args, &header, &boot_mode(&args)), Model::Cgb);
+    }
+}
@@ -0,0 +1,73 @@
+use std::fmt;
+
+/// An address in a particular bank, written `05:4123` like in symbol files.
+/// Without a bank it stands for the address in whichever bank is mapped.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct BankedAddress {
+    pub bank: Option<u16>,
+    pub addr: u16,
+}
+
+impl BankedAddress {
+    /// The address in any bank.
+    pub fn new(addr: u16) -> BankedAddress {
+        BankedAddress { bank: None, addr }
+    }
+
+    pub fn in_bank(bank: u16, addr: u16) -> BankedAddress {
+        BankedAddress {
+            bank: Some(bank),
+            addr,
+        }
+    }
+
+    /// Whether this is `addr` while `bank` is mapped there.
+    pub fn matches(&self, addr: u16, bank: u16) -> bool {
+        self.addr == addr && self.bank.is_none_or(|b| b == bank)
+    }
+}
+
+/// Where the part of the memory map holding `addr` starts. Each part is
+/// banked separately.
+pub fn region_start(addr: u16) -> u16 {
+    match addr {
+        0x0000..=0x3FFF => 0x0000,
+        0x4000..=0x7FFF => 0x4000,
+        0x8000..=0x9FFF => 0x8000,
+        0xA000..=0xBFFF => 0xA000,
+        0xC000..=0xCFFF => 0xC000,
+        0xD000..=0xDFFF => 0xD000,
+        0xE000..=0xFF7F => 0xE000,
+        0xFF80..=0xFFFF => 0xFF80,
+    }
+}
+
+impl fmt::Display for BankedAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.bank {
+            Some(bank) => write!(f, "{:02X}:{:04X}", bank, self.addr),
+            None => write!(f, "{:#06X}", self.addr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::BankedAddress;
+
+    #[test]
+    fn matches_in_its_bank() {
+        let addr = BankedAddress::in_bank(3, 0x4123);
+        assert!(addr.matches(0x4123, 3));
+        assert!(!addr.matches(0x4123, 4));
+        assert!(!addr.matches(0x4124, 3));
+    }
+
+    #[test]
+    fn matches_in_any_bank() {
+        let addr = BankedAddress::new(0x4123);
+        assert!(addr.matches(0x4123, 3));
+        assert!(addr.matches(0x4123, 4));
+        assert!(!addr.matches(0x4124, 3));
+    }
+}
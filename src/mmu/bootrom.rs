@@ -16,3 +16,35 @@ pub const BOOT_ROM: [u8; 256] = [
     0x21, 0x04, 0x01, 0x11, 0xa8, 0x00, 0x1a, 0x13, 0xbe, 0x20, 0xfe, 0x23, 0x7d, 0xfe, 0x34, 0x20,
     0xf5, 0x06, 0x19, 0x78, 0x86, 0x23, 0x05, 0x20, 0xfb, 0x86, 0x20, 0xfe, 0x3e, 0x01, 0xe0, 0x50,
 ];
+
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+
+// The CGB boot ROM is mapped at 0x000-0x0FF and 0x200-0x8FF, leaving the
+// cartridge header visible in between
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+
+/// The sound registers as every boot ROM leaves them, after playing the
+/// chime.
+pub const POST_BOOT_SOUND: [(u16, u8); 21] = [
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (0xFF14, 0xBF),
+    (0xFF16, 0x3F),
+    (0xFF17, 0x00),
+    (0xFF18, 0xFF),
+    (0xFF19, 0xBF),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF1E, 0xBF),
+    (0xFF20, 0xFF),
+    (0xFF21, 0x00),
+    (0xFF22, 0x00),
+    (0xFF23, 0xBF),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+    (0xFF26, 0xF1),
+];
@@ -0,0 +1,165 @@
+use std::cell::Cell;
+use std::fs;
+use std::path::Path;
+
+/// How each byte of the ROM has been used while playing, for telling code
+/// from data when disassembling. Reads happen through `&MMU`, so the flags
+/// are kept in Cells.
+///
+/// Saved as a `.cdl` file with one byte of flags for each byte of ROM.
+/// Loading an existing log and saving it again adds to what's already there,
+/// so it builds up over several sessions.
+pub struct CodeDataLog {
+    flags: Vec<Cell<u8>>,
+}
+
+impl CodeDataLog {
+    /// Fetched as the first byte of an instruction
+    pub const OPCODE: u8 = 0x01;
+
+    /// Fetched as part of an instruction after its first byte
+    pub const OPERAND: u8 = 0x02;
+
+    /// Read by an instruction
+    pub const DATA: u8 = 0x04;
+
+    /// Copied by OAM or VRAM DMA
+    pub const DMA: u8 = 0x08;
+
+    pub fn new(rom_size: usize) -> CodeDataLog {
+        CodeDataLog {
+            flags: vec![Cell::new(0); rom_size],
+        }
+    }
+
+    /// Load a log for a ROM of `rom_size` bytes. A log that doesn't exist
+    /// yet is empty.
+    pub fn load(path: &str, rom_size: usize) -> Result<CodeDataLog, String> {
+        if !Path::new(path).exists() {
+            return Ok(CodeDataLog::new(rom_size));
+        }
+
+        let bytes = fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        if bytes.len() != rom_size {
+            return Err(format!(
+                "{} is for a {} byte ROM, not this {} byte one",
+                path,
+                bytes.len(),
+                rom_size
+            ));
+        }
+
+        Ok(CodeDataLog {
+            flags: bytes.into_iter().map(Cell::new).collect(),
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let bytes: Vec<u8> = self.flags.iter().map(Cell::get).collect();
+        fs::write(path, bytes).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+    }
+
+    /// The flags for a ROM offset, or none if it's past the end.
+    pub fn flags(&self, offset: usize) -> u8 {
+        self.flags.get(offset).map_or(0, Cell::get)
+    }
+
+    pub fn mark(&self, offset: usize, flag: u8) {
+        if let Some(flags) = self.flags.get(offset) {
+            flags.set(flags.get() | flag);
+        }
+    }
+
+    /// Whether the byte at `offset` has been run as part of an instruction.
+    pub fn is_code(&self, offset: usize) -> bool {
+        self.flags(offset) & (CodeDataLog::OPCODE | CodeDataLog::OPERAND) != 0
+    }
+
+    /// Whether the byte at `offset` has been read or copied, but never run.
+    pub fn is_data(&self, offset: usize) -> bool {
+        !self.is_code(offset) && self.flags(offset) & (CodeDataLog::DATA | CodeDataLog::DMA) != 0
+    }
+
+    /// How many bytes have been run and how many only read, for reporting.
+    pub fn coverage(&self) -> (usize, usize) {
+        let code = (0..self.len()).filter(|&o| self.is_code(o)).count();
+        let data = (0..self.len()).filter(|&o| self.is_data(o)).count();
+        (code, data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::CodeDataLog;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir()
+            .join(format!("cowboy-{}-{}.cdl", std::process::id(), name))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    #[test]
+    fn sessions_add_to_the_log() {
+        let path = temp_path("merge");
+
+        let log = CodeDataLog::load(&path, 0x8000).unwrap();
+        assert_eq!(log.coverage(), (0, 0));
+        log.mark(0x150, CodeDataLog::OPCODE);
+        log.mark(0x151, CodeDataLog::OPERAND);
+        log.mark(0x4000, CodeDataLog::DATA);
+        log.save(&path).unwrap();
+
+        let log = CodeDataLog::load(&path, 0x8000).unwrap();
+        log.mark(0x150, CodeDataLog::DATA);
+        log.mark(0x4000, CodeDataLog::DMA);
+        log.mark(0x4001, CodeDataLog::DATA);
+        log.save(&path).unwrap();
+
+        let log = CodeDataLog::load(&path, 0x8000).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(log.flags(0x150), CodeDataLog::OPCODE | CodeDataLog::DATA);
+        assert_eq!(log.flags(0x151), CodeDataLog::OPERAND);
+        assert_eq!(log.flags(0x4000), CodeDataLog::DATA | CodeDataLog::DMA);
+        assert_eq!(log.flags(0x4001), CodeDataLog::DATA);
+        assert_eq!(log.coverage(), (2, 2));
+    }
+
+    #[test]
+    fn refuses_a_log_for_another_size_of_rom() {
+        let path = temp_path("size");
+        CodeDataLog::new(0x8000).save(&path).unwrap();
+
+        let error = CodeDataLog::load(&path, 0x10000).err().unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(error.contains("32768 byte ROM"));
+    }
+
+    #[test]
+    fn code_wins_over_data() {
+        let log = CodeDataLog::new(0x10);
+        log.mark(0, CodeDataLog::OPCODE | CodeDataLog::DATA);
+        log.mark(1, CodeDataLog::DATA);
+        log.mark(2, CodeDataLog::DMA);
+
+        assert!(log.is_code(0) && !log.is_data(0));
+        assert!(!log.is_code(1) && log.is_data(1));
+        assert!(!log.is_code(2) && log.is_data(2));
+        assert!(!log.is_code(3) && !log.is_data(3));
+
+        // Past the end of the ROM
+        log.mark(0x10, CodeDataLog::OPCODE);
+        assert_eq!(log.flags(0x10), 0);
+    }
+}
@@ -0,0 +1,151 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+pub const OAM_DMA_LENGTH: u8 = 0xA0;
+
+/// OAM DMA controller. Once started by a write to 0xFF46 it copies one byte
+/// per M-cycle from `source` into OAM. While a transfer is running the CPU can
+/// only reliably reach HRAM and the IO registers - everything else on the bus
+/// sees whatever byte the DMA is currently moving.
+#[derive(Clone)]
+pub struct OamDma {
+    register: u8,
+    source: u16,
+    progress: u8,
+    active: bool,
+    current_byte: u8,
+
+    // A newly requested transfer starts after a one M-cycle delay. An
+    // in-flight transfer keeps running (and keeps OAM locked) until then.
+    pending: Option<u16>,
+}
+
+impl Default for OamDma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OamDma {
+    pub fn new() -> OamDma {
+        OamDma {
+            register: 0xFF,
+            source: 0,
+            progress: 0,
+            active: false,
+            current_byte: 0xFF,
+            pending: None,
+        }
+    }
+
+    pub fn start(&mut self, value: u8) {
+        self.register = value;
+        self.pending = Some((value as u16) << 8);
+    }
+
+    pub fn register(&self) -> u8 {
+        self.register
+    }
+
+    /// The address the transfer is currently reading from.
+    pub fn source(&self) -> u16 {
+        self.source + self.progress as u16
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// The byte currently on the bus. This is what the CPU reads when it tries
+    /// to access memory that the DMA is using.
+    pub fn current_byte(&self) -> u8 {
+        self.current_byte
+    }
+
+    /// Advance the controller by a single M-cycle. Returns the (source address,
+    /// OAM offset) pair that should be copied during this cycle, if any.
+    pub fn step(&mut self) -> Option<(u16, u8)> {
+        let transfer = if self.active {
+            let offset = self.progress;
+            self.progress += 1;
+            if self.progress == OAM_DMA_LENGTH {
+                self.active = false;
+            }
+
+            Some((self.source + offset as u16, offset))
+        } else {
+            None
+        };
+
+        if let Some(source) = self.pending.take() {
+            self.source = source;
+            self.progress = 0;
+            self.active = true;
+        }
+
+        transfer
+    }
+
+    pub fn set_current_byte(&mut self, value: u8) {
+        self.current_byte = value;
+    }
+}
+
+impl SaveState for OamDma {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.register);
+        state.u16(self.source);
+        state.u8(self.progress);
+        state.bool(self.active);
+        state.u8(self.current_byte);
+        state.bool(self.pending.is_some());
+        state.u16(self.pending.unwrap_or(0));
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.register = state.u8()?;
+        self.source = state.u16()?;
+        self.progress = state.u8()?;
+        self.active = state.bool()?;
+        self.current_byte = state.u8()?;
+        let pending = state.bool()?;
+        let source = state.u16()?;
+        self.pending = pending.then_some(source);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OamDma, OAM_DMA_LENGTH};
+
+    #[test]
+    fn copies_a_byte_a_cycle_after_a_delay() {
+        let mut dma = OamDma::new();
+        dma.start(0xC1);
+        assert!(!dma.is_active());
+
+        assert_eq!(dma.step(), None);
+        assert!(dma.is_active());
+
+        for offset in 0..OAM_DMA_LENGTH {
+            assert!(dma.is_active());
+            assert_eq!(dma.step(), Some((0xC100 + offset as u16, offset)));
+        }
+
+        assert!(!dma.is_active());
+        assert_eq!(dma.step(), None);
+        assert_eq!(dma.register(), 0xC1);
+    }
+
+    #[test]
+    fn restarting_keeps_going_until_the_new_transfer_starts() {
+        let mut dma = OamDma::new();
+        dma.start(0xC1);
+        dma.step();
+        dma.step();
+
+        dma.start(0xD0);
+        assert_eq!(dma.step(), Some((0xC101, 1)));
+        assert_eq!(dma.step(), Some((0xD000, 0)));
+    }
+}
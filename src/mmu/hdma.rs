@@ -0,0 +1,119 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+pub const HDMA_BLOCK_LENGTH: u16 = 0x10;
+
+/// CGB VRAM DMA controller (HDMA1-5). Copies data into VRAM in blocks of 16
+/// bytes, either all at once (general purpose DMA) or one block at the start
+/// of every HBlank. The CPU is halted while a block is being copied.
+#[derive(Clone)]
+pub struct VramDma {
+    source: u16,
+    destination: u16,
+
+    // Number of blocks left to copy, minus one, as reported by HDMA5
+    remaining: u8,
+    hblank_active: bool,
+}
+
+impl Default for VramDma {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a write to HDMA5 asked for.
+pub enum VramDmaRequest {
+    /// Copy this many blocks straight away.
+    GeneralPurpose(u8),
+    HBlank,
+    Cancelled,
+}
+
+impl VramDma {
+    pub fn new() -> VramDma {
+        VramDma {
+            source: 0,
+            destination: 0,
+            remaining: 0x7F,
+            hblank_active: false,
+        }
+    }
+
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        match addr {
+            // The address registers are write only
+            0xFF51..=0xFF54 => 0xFF,
+
+            // Bit 7 is clear while an HBlank transfer is still running
+            0xFF55 => ((!self.hblank_active as u8) << 7) | self.remaining,
+
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn write_byte(&mut self, addr: u16, value: u8) -> Option<VramDmaRequest> {
+        match addr {
+            0xFF51 => self.source = (self.source & 0x00FF) | ((value as u16) << 8),
+            0xFF52 => self.source = (self.source & 0xFF00) | (value as u16 & 0xF0),
+            0xFF53 => self.destination = (self.destination & 0x00FF) | ((value as u16 & 0x1F) << 8),
+            0xFF54 => self.destination = (self.destination & 0xFF00) | (value as u16 & 0xF0),
+            0xFF55 => {
+                // Writing with bit 7 clear stops an HBlank transfer part way
+                if self.hblank_active && value & 0x80 == 0 {
+                    self.hblank_active = false;
+                    return Some(VramDmaRequest::Cancelled);
+                }
+
+                self.remaining = value & 0x7F;
+                if value & 0x80 == 0x80 {
+                    self.hblank_active = true;
+                    return Some(VramDmaRequest::HBlank);
+                }
+
+                return Some(VramDmaRequest::GeneralPurpose(self.remaining + 1));
+            }
+
+            _ => unreachable!(),
+        }
+
+        None
+    }
+
+    pub fn is_hblank_active(&self) -> bool {
+        self.hblank_active
+    }
+
+    /// Advance past the next block. Returns the source address and the VRAM
+    /// address it should be copied to.
+    pub fn next_block(&mut self) -> (u16, u16) {
+        let block = (self.source, 0x8000 | (self.destination & 0x1FF0));
+
+        self.source = self.source.wrapping_add(HDMA_BLOCK_LENGTH);
+        self.destination = self.destination.wrapping_add(HDMA_BLOCK_LENGTH);
+
+        // HDMA5 counts down through 0x7F once the last block is copied
+        self.remaining = self.remaining.wrapping_sub(1) & 0x7F;
+        if self.remaining == 0x7F {
+            self.hblank_active = false;
+        }
+
+        block
+    }
+}
+
+impl SaveState for VramDma {
+    fn save(&self, state: &mut StateWriter) {
+        state.u16(self.source);
+        state.u16(self.destination);
+        state.u8(self.remaining);
+        state.bool(self.hblank_active);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.source = state.u16()?;
+        self.destination = state.u16()?;
+        self.remaining = state.u8()?;
+        self.hblank_active = state.bool()?;
+        Ok(())
+    }
+}
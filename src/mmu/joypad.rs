@@ -1,10 +1,18 @@
+use crate::state::{SaveState, StateReader, StateWriter};
 use minifb::Key;
 
+// The SGB can read up to four joypads
+pub const MAX_PLAYERS: usize = 4;
+
 pub struct Joypad {
     pub joypad_irq: bool,
     joypad: u8,
-    ssba: u8,
-    dulr: u8,
+    ssba: [u8; MAX_PLAYERS],
+    dulr: [u8; MAX_PLAYERS],
+
+    // Multiplayer on the SGB. Releasing P15 moves on to the next joypad.
+    players: usize,
+    player: usize,
 }
 
 impl Default for Joypad {
@@ -18,63 +26,115 @@ impl Joypad {
         Joypad {
             joypad_irq: false,
             joypad: 0x0,
-            dulr: 0xF,
-            ssba: 0xF,
+            dulr: [0xF; MAX_PLAYERS],
+            ssba: [0xF; MAX_PLAYERS],
+            players: 1,
+            player: 0,
         }
     }
 
-    pub fn handle_key_down(&mut self, key: Key) {
-        let original_irq = self.joypad_irq;
-        self.joypad_irq = true;
-        match key {
-            Key::Right => self.dulr &= !0x1,
-            Key::Left => self.dulr &= !0x2,
-            Key::Up => self.dulr &= !0x4,
-            Key::Down => self.dulr &= !0x8,
-
-            Key::S => self.ssba &= !0x1,
-            Key::A => self.ssba &= !0x2,
-            Key::Space => self.ssba &= !0x4,
-            Key::Enter => self.ssba &= !0x8,
-
-            _ => self.joypad_irq = original_irq,
+    /// The keyboard controls the first joypad, and the second once a game
+    /// asks for more than one.
+    fn key_bit(key: Key) -> Option<(usize, bool, u8)> {
+        Some(match key {
+            Key::Right => (0, true, 0x1),
+            Key::Left => (0, true, 0x2),
+            Key::Up => (0, true, 0x4),
+            Key::Down => (0, true, 0x8),
+
+            Key::S => (0, false, 0x1),
+            Key::A => (0, false, 0x2),
+            Key::Space => (0, false, 0x4),
+            Key::Enter => (0, false, 0x8),
+
+            Key::L => (1, true, 0x1),
+            Key::J => (1, true, 0x2),
+            Key::I => (1, true, 0x4),
+            Key::K => (1, true, 0x8),
+
+            Key::M => (1, false, 0x1),
+            Key::N => (1, false, 0x2),
+            Key::RightShift => (1, false, 0x4),
+            Key::Backspace => (1, false, 0x8),
+
+            _ => return None,
+        })
+    }
+
+    fn set_key(&mut self, key: Key, pressed: bool) {
+        let Some((player, direction, bit)) = Self::key_bit(key) else {
+            return;
+        };
+
+        let lines = if direction {
+            &mut self.dulr[player]
+        } else {
+            &mut self.ssba[player]
+        };
+
+        if pressed {
+            *lines &= !bit;
+        } else {
+            *lines |= bit;
         }
+        self.joypad_irq = true;
+    }
+
+    pub fn handle_key_down(&mut self, key: Key) {
+        self.set_key(key, true);
     }
 
     pub fn handle_key_up(&mut self, key: Key) {
-        let original_irq = self.joypad_irq;
-        self.joypad_irq = true;
+        self.set_key(key, false);
+    }
+
+    /// Set how many joypads the SGB reads from: 1, 2 or 4.
+    pub fn set_players(&mut self, players: usize) {
+        self.players = players.clamp(1, MAX_PLAYERS);
+        self.player = 0;
+    }
 
-        match key {
-            Key::Right => self.dulr |= 0x1,
-            Key::Left => self.dulr |= 0x2,
-            Key::Up => self.dulr |= 0x4,
-            Key::Down => self.dulr |= 0x8,
+    /// The keys held on every joypad, as direction and button lines.
+    pub fn held_keys(&self) -> ([u8; MAX_PLAYERS], [u8; MAX_PLAYERS]) {
+        (self.dulr, self.ssba)
+    }
 
-            Key::S => self.ssba |= 0x1,
-            Key::A => self.ssba |= 0x2,
-            Key::Space => self.ssba |= 0x4,
-            Key::Enter => self.ssba |= 0x8,
+    /// Put back keys returned by `held_keys`.
+    pub fn set_held_keys(&mut self, (dulr, ssba): ([u8; MAX_PLAYERS], [u8; MAX_PLAYERS])) {
+        self.dulr = dulr;
+        self.ssba = ssba;
+    }
 
-            _ => self.joypad_irq = original_irq,
-        }
+    /// The directions currently held, one bit each for right, left, up and
+    /// down.
+    pub fn pressed_directions(&self) -> u8 {
+        !self.dulr[0] & 0xF
+    }
+
+    /// The buttons currently held, one bit each for A, B, select and start.
+    pub fn pressed_buttons(&self) -> u8 {
+        !self.ssba[0] & 0xF
     }
 
     pub fn read_byte(&self, addr: u16) -> u8 {
+        let (dulr, ssba) = (self.dulr[self.player], self.ssba[self.player]);
+
         match addr {
             // Joy pad
             0xFF00 => {
                 match (self.joypad >> 4) & 0x3 {
                     // Return both
-                    0x0 => 0xC0 | (self.dulr & self.ssba),
+                    0x0 => 0xC0 | (dulr & ssba),
 
                     // Return select
-                    0x1 => 0xD0 | self.ssba,
+                    0x1 => 0xD0 | ssba,
 
                     // Return dpad
-                    0x2 => 0xE0 | self.dulr,
+                    0x2 => 0xE0 | dulr,
 
-                    // Return neither
+                    // Return neither. In multiplayer the low bits identify
+                    // the joypad being read.
+                    0x3 if self.players > 1 => 0xF0 | (0xF - self.player as u8),
                     0x3 => 0xFF,
 
                     _ => unreachable!(),
@@ -87,8 +147,32 @@ impl Joypad {
 
     pub fn write_byte(&mut self, addr: u16, byte: u8) {
         match addr {
-            0xFF00 => self.joypad = byte,
+            0xFF00 => {
+                if self.players > 1 && self.joypad & 0x20 == 0 && byte & 0x20 == 0x20 {
+                    self.player = (self.player + 1) % self.players;
+                }
+                self.joypad = byte;
+            }
             _ => unreachable!(),
         }
     }
 }
+
+// The keys held down belong to the player rather than the game, so they are
+// left as they are
+impl SaveState for Joypad {
+    fn save(&self, state: &mut StateWriter) {
+        state.bool(self.joypad_irq);
+        state.u8(self.joypad);
+        state.u8(self.players as u8);
+        state.u8(self.player as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.joypad_irq = state.bool()?;
+        self.joypad = state.u8()?;
+        self.players = (state.u8()? as usize).clamp(1, MAX_PLAYERS);
+        self.player = state.u8()? as usize % self.players;
+        Ok(())
+    }
+}
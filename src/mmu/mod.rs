@@ -1,52 +1,452 @@
+pub mod address;
 pub mod bootrom;
+pub mod code_data_log;
+pub mod dma;
+pub mod hdma;
 pub mod joypad;
 pub mod ppu;
+pub mod sgb;
 pub mod timer;
-
-use crate::{cartridge::Cartridge, debugger::enable_debug};
-use bootrom::BOOT_ROM;
+pub mod watchpoint;
+
+use crate::{
+    cartridge::{rom_offset, Cartridge},
+    debugger::enable_debug,
+    gameboy::model::Model,
+    state::{SaveState, StateReader, StateWriter},
+};
+use address::BankedAddress;
+use bootrom::{BOOT_ROM, CGB_BOOT_ROM_SIZE, POST_BOOT_SOUND};
+use code_data_log::CodeDataLog;
 use colored::Colorize;
+use dma::OamDma;
+use hdma::{VramDma, VramDmaRequest, HDMA_BLOCK_LENGTH};
 use joypad::Joypad;
 use ppu::PPU;
+use sgb::Sgb;
+use std::cell::{Cell, RefCell};
 use timer::Timer;
+use watchpoint::{WatchHit, Watchpoint};
+
+/// A read or write by an instruction.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct MemoryAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub write: bool,
+}
+
+#[derive(PartialEq, Eq)]
+enum Bus {
+    External,
+    Video,
+    WorkingRam,
+    Internal,
+}
+
+// HRAM's place in `ram`
+const HRAM: std::ops::Range<usize> = 0x7F80..0x7FFF;
+
+const WRAM_BANK_SIZE: usize = 0x1000;
+const WRAM_BANKS: usize = 8;
+
+// Copying one 16 byte block of VRAM DMA takes 8 M-cycles at normal speed
+const HDMA_BLOCK_M_CYCLES: u32 = 8;
 
 pub struct MMU {
+    pub model: Model,
+    boot_rom: Vec<u8>,
     boot_rom_enabled: bool,
+    pub cgb_mode: bool,
     pub cartridge: Cartridge,
     pub ram: [u8; 0xFFFF],
     pub joypad: Joypad,
     pub ppu: PPU,
     pub timer: Timer,
+    pub dma: OamDma,
+    pub hdma: VramDma,
     pub ie: u8,
+
+    // Present when the game is running on a Super Game Boy
+    pub sgb: Option<Sgb>,
+
+    // Working RAM. Bank 0 is always at 0xC000, 0xD000 shows bank 1 on the
+    // DMG and any of banks 1-7 in CGB mode.
+    wram: Vec<u8>,
+    wram_bank: usize,
+
+    // CGB double speed mode (KEY1). The CPU, timer and OAM DMA run twice as
+    // fast while the PPU keeps its normal pace.
+    pub double_speed: bool,
+    speed_switch_armed: bool,
+
+    // M-cycles the CPU is halted for by a general purpose VRAM DMA
+    hdma_stall: u32,
+
+    // CGB object priority mode (OPRI), and the undocumented registers at
+    // 0xFF72-0xFF75. Nothing uses them, they just hold what was written.
+    opri: u8,
+    undocumented: [u8; 4],
+
+    // Sound registers and wave RAM. There's no sound yet, so they just hold
+    // whatever was written.
+    sound: [u8; 0x30],
+
+    // Set by the debugger. Reads can set off a watchpoint too, so the first
+    // access to do so is kept in a Cell until the debugger picks it up.
+    pub watchpoints: Vec<Watchpoint>,
+    watch_hit: Cell<Option<WatchHit>>,
+
+    // Set when logging how the ROM is used
+    pub code_data_log: Option<CodeDataLog>,
+
+    // Reads and writes are kept for the trace while this is set
+    pub record_accesses: bool,
+    accesses: RefCell<Vec<MemoryAccess>>,
 }
 
 impl MMU {
-    pub fn new(rom: Vec<u8>) -> MMU {
-        MMU {
-            cartridge: Cartridge::new(rom),
-            boot_rom_enabled: true,
+    /// `boot_rom` replaces the built in DMG boot ROM. Colour hardware runs
+    /// colour games in CGB mode and DMG games in compatibility mode. A CGB
+    /// boot ROM starts every game in CGB mode and leaves it to the boot ROM
+    /// to switch DMG games over.
+    pub fn new(rom: Vec<u8>, model: Model, boot_rom: Option<Vec<u8>>) -> MMU {
+        let cartridge = Cartridge::new(rom);
+        let boot_rom = boot_rom.unwrap_or_else(|| BOOT_ROM.to_vec());
+        let cgb_boot_rom = boot_rom.len() == CGB_BOOT_ROM_SIZE;
+        let cgb_mode = model.is_cgb() && (cartridge.header.supports_cgb() || cgb_boot_rom);
+
+        let mut mmu = MMU {
+            model,
+            cartridge,
+
+            // Colour games can only be booted by a CGB boot ROM
+            boot_rom_enabled: !cgb_mode || cgb_boot_rom,
+            boot_rom,
+            cgb_mode,
             joypad: Joypad::new(),
             ram: [0x0; 0xFFFF],
-            ppu: PPU::new(),
+            ppu: PPU::new(model, cgb_mode),
             ie: 0,
+            sgb: model.is_sgb().then(Sgb::new),
 
             timer: Timer::new(),
+            dma: OamDma::new(),
+            hdma: VramDma::new(),
+
+            wram: vec![0; WRAM_BANK_SIZE * WRAM_BANKS],
+            wram_bank: 1,
+            double_speed: false,
+            speed_switch_armed: false,
+            hdma_stall: 0,
+            opri: 0,
+            undocumented: [0; 4],
+            sound: [0; 0x30],
+
+            watchpoints: Vec::new(),
+            watch_hit: Cell::new(None),
+            code_data_log: None,
+            record_accesses: false,
+            accesses: RefCell::new(Vec::new()),
+        };
+
+        if model.is_cgb() && !cgb_mode {
+            mmu.ppu.enable_dmg_compatibility(&mmu.cartridge.header);
         }
+
+        mmu
     }
 
-    pub fn read_byte(&self, addr: u16) -> u8 {
+    pub fn boot_rom_enabled(&self) -> bool {
+        self.boot_rom_enabled
+    }
+
+    /// Start from the cartridge with the hardware set up the way the given
+    /// model's boot ROM leaves it.
+    pub fn skip_boot(&mut self, model: Model) {
+        self.boot_rom_enabled = false;
+        self.timer.set_counter(model.post_boot_counter());
+
+        for (addr, value) in POST_BOOT_SOUND {
+            self.write_byte(addr, value);
+        }
+
+        // The SGB reports its sound as off
+        if model.is_sgb() {
+            self.write_byte(0xFF26, 0xF0);
+        }
+
+        self.ppu.skip_boot();
+
+        // The boot ROM finishes during VBlank and leaves the interrupt
+        // pending
+        self.ppu.vblank_irq = true;
+    }
+
+    /// Advance the hardware that runs alongside the CPU by `cycles` T-cycles.
+    /// VRAM DMA transfers started along the way halt the CPU, so they add
+    /// more cycles on top.
+    pub fn do_cycles(&mut self, cycles: u8) {
+        let mut m_cycles = cycles as u32 / 4 + std::mem::take(&mut self.hdma_stall);
+
+        while m_cycles > 0 {
+            m_cycles -= 1;
+            self.do_m_cycle();
+
+            if self.ppu.take_hblank_started() && self.hdma.is_hblank_active() {
+                self.copy_hdma_block();
+                m_cycles += self.hdma_block_m_cycles();
+            }
+        }
+    }
+
+    fn do_m_cycle(&mut self) {
+        self.do_dma_cycle();
+
+        // In double speed mode an M-cycle only lasts two dots
+        let dots = if self.double_speed { 2 } else { 4 };
+        self.ppu.do_cycle(dots);
+        self.timer.do_cycles(4);
+    }
+
+    fn hdma_block_m_cycles(&self) -> u32 {
+        if self.double_speed {
+            HDMA_BLOCK_M_CYCLES * 2
+        } else {
+            HDMA_BLOCK_M_CYCLES
+        }
+    }
+
+    fn copy_hdma_block(&mut self) {
+        let (source, destination) = self.hdma.next_block();
+        for i in 0..HDMA_BLOCK_LENGTH {
+            self.log_rom(source.wrapping_add(i), CodeDataLog::DMA);
+            let value = self.read_hdma_source(source.wrapping_add(i));
+            self.ppu.write_vram(destination + i, value);
+        }
+    }
+
+    /// VRAM DMA can copy from the ROM, cartridge RAM and working RAM. The
+    /// source is whatever the game wrote, and anywhere else reads as 0xFF.
+    fn read_hdma_source(&self, addr: u16) -> u8 {
         match addr {
-            // Boot rom or regular rom
-            0x0..=0xFF => {
-                if self.boot_rom_enabled {
-                    BOOT_ROM[addr as usize]
-                } else {
-                    self.cartridge.read_byte(addr)
+            0x0000..=0x7FFF | 0xA000..=0xDFFF => self.read_mapped_byte(addr),
+            _ => 0xFF,
+        }
+    }
+
+    fn write_hdma(&mut self, addr: u16, value: u8) {
+        match self.hdma.write_byte(addr, value) {
+            Some(VramDmaRequest::GeneralPurpose(blocks)) => {
+                for _ in 0..blocks {
+                    self.copy_hdma_block();
                 }
+                self.hdma_stall += blocks as u32 * self.hdma_block_m_cycles();
             }
 
+            // HBlank transfers are copied as the PPU reaches each HBlank
+            Some(VramDmaRequest::HBlank) | Some(VramDmaRequest::Cancelled) | None => (),
+        }
+    }
+
+    /// Called by STOP. Switches between normal and double speed if KEY1 was
+    /// armed beforehand.
+    pub fn switch_speed(&mut self) {
+        if !self.speed_switch_armed {
+            return;
+        }
+
+        self.speed_switch_armed = false;
+        self.double_speed = !self.double_speed;
+
+        // The switch resets the divider
+        self.timer.write_byte(0xFF04, 0);
+    }
+
+    fn wram_index(&self, addr: u16) -> usize {
+        match addr {
+            0xC000..=0xCFFF => (addr - 0xC000) as usize,
+            _ => self.wram_bank * WRAM_BANK_SIZE + (addr - 0xD000) as usize,
+        }
+    }
+
+    fn do_dma_cycle(&mut self) {
+        if let Some((source_addr, offset)) = self.dma.step() {
+            // Sources above 0xDFFF hit the echo of working RAM
+            let source_addr = match source_addr {
+                0xE000..=0xFFFF => source_addr - 0x2000,
+                _ => source_addr,
+            };
+
+            self.log_rom(source_addr, CodeDataLog::DMA);
+            let value = self.read_mapped_byte(source_addr);
+            self.dma.set_current_byte(value);
+            self.ppu.write_oam(offset, value);
+        }
+    }
+
+    /// The bus an address is reached through. OAM DMA only gets in the way of
+    /// accesses on the same bus as the memory it is copying from.
+    fn bus(&self, addr: u16) -> Bus {
+        match addr {
+            0x8000..=0x9FFF => Bus::Video,
+            0xFE00..=0xFFFF => Bus::Internal,
+
+            // The CGB has a separate bus for working RAM
+            0xC000..=0xFDFF if self.model.is_cgb() => Bus::WorkingRam,
+            _ => Bus::External,
+        }
+    }
+
+    fn dma_conflict(&self, addr: u16) -> bool {
+        self.dma.is_active() && self.bus(addr) == self.bus(self.dma.source())
+    }
+
+    /// The bank currently mapped at an address. Memory that isn't banked is
+    /// in bank 0.
+    pub fn bank(&self, addr: u16) -> u16 {
+        match addr {
+            0x4000..=0x7FFF => self.cartridge.rom_bank(),
+            0x8000..=0x9FFF => self.ppu.vram_bank() as u16,
+            0xA000..=0xBFFF => self.cartridge.ram_bank(),
+            0xD000..=0xDFFF => self.wram_bank as u16,
+            _ => 0,
+        }
+    }
+
+    /// An address along with the bank currently mapped there.
+    pub fn banked(&self, addr: u16) -> BankedAddress {
+        BankedAddress::in_bank(self.bank(addr), addr)
+    }
+
+    /// Read from a ROM bank whether or not it's mapped. Anything else is
+    /// read from whatever is mapped now.
+    pub fn read_banked(&self, addr: BankedAddress) -> u8 {
+        match (addr.bank, addr.addr) {
+            (Some(bank), 0x4000..=0x7FFF) if bank != self.cartridge.rom_bank() => {
+                let rom = self.cartridge.rom();
+                rom.get(rom_offset(bank, addr.addr))
+                    .copied()
+                    .unwrap_or(0xFF)
+            }
+            _ => self.peek_byte(addr.addr),
+        }
+    }
+
+    /// The first access to set off a watchpoint since this was last called.
+    pub fn take_watch_hit(&self) -> Option<WatchHit> {
+        self.watch_hit.take()
+    }
+
+    fn check_watchpoints(&self, hit: WatchHit) {
+        if self.watch_hit.get().is_none() && self.watchpoints.iter().any(|w| w.matches(&hit)) {
+            self.watch_hit.set(Some(hit));
+        }
+    }
+
+    /// Note how a byte of ROM was used in the code/data log, if there is
+    /// one. Anything else is left out.
+    fn log_rom(&self, addr: u16, flag: u8) {
+        let Some(log) = &self.code_data_log else {
+            return;
+        };
+
+        if addr < 0x8000 && !self.boot_rom_mapped(addr) {
+            log.mark(rom_offset(self.bank(addr), addr), flag);
+        }
+    }
+
+    /// Log the instruction at `pc` as code. The CPU fetches instructions
+    /// with `fetch_byte`, which doesn't know how long they are.
+    pub fn log_instruction(&self, pc: u16, length: u16) {
+        if self.code_data_log.is_some() {
+            self.log_rom(pc, CodeDataLog::OPCODE);
+            for offset in 1..length {
+                self.log_rom(pc.wrapping_add(offset), CodeDataLog::OPERAND);
+            }
+        }
+    }
+
+    /// The reads and writes recorded since this was last called.
+    pub fn take_accesses(&self) -> Vec<MemoryAccess> {
+        self.accesses.take()
+    }
+
+    fn record_access(&self, addr: u16, value: u8, write: bool) {
+        self.accesses
+            .borrow_mut()
+            .push(MemoryAccess { addr, value, write });
+    }
+
+    /// A read by an instruction.
+    pub fn read_byte(&self, addr: u16) -> u8 {
+        self.log_rom(addr, CodeDataLog::DATA);
+        let value = self.fetch_byte(addr);
+
+        if self.record_accesses {
+            self.record_access(addr, value, false);
+        }
+        value
+    }
+
+    /// A read of part of an instruction. Watchpoints see these, but they
+    /// aren't logged as data.
+    pub fn fetch_byte(&self, addr: u16) -> u8 {
+        let value = self.read_bus(addr);
+
+        if !self.watchpoints.is_empty() {
+            self.check_watchpoints(WatchHit {
+                addr: self.banked(addr),
+                write: false,
+                old: value,
+                new: value,
+            });
+        }
+
+        value
+    }
+
+    /// Read without setting off watchpoints or logging it, for looking at
+    /// memory from the debugger.
+    pub fn peek_byte(&self, addr: u16) -> u8 {
+        self.read_bus(addr)
+    }
+
+    fn read_bus(&self, addr: u16) -> u8 {
+        if self.dma.is_active() {
+            // OAM is owned by the DMA for the duration of the transfer
+            if let 0xFE00..=0xFEFF = addr {
+                return 0xFF;
+            }
+
+            // Anything sharing a bus with the DMA sees the byte being
+            // transferred
+            if self.dma_conflict(addr) {
+                return self.dma.current_byte();
+            }
+        }
+
+        self.read_mapped_byte(addr)
+    }
+
+    /// Whether the boot ROM is mapped over the cartridge at an address. The
+    /// CGB one continues after the cartridge header.
+    fn boot_rom_mapped(&self, addr: u16) -> bool {
+        self.boot_rom_enabled
+            && match addr {
+                0x0..=0xFF => true,
+                0x200..=0x8FF => self.boot_rom.len() == CGB_BOOT_ROM_SIZE,
+                _ => false,
+            }
+    }
+
+    fn read_mapped_byte(&self, addr: u16) -> u8 {
+        match addr {
+            // Boot rom
+            0x0..=0x8FF if self.boot_rom_mapped(addr) => self.boot_rom[addr as usize],
+
             // ROM
-            0x100..=0x7FFF => self.cartridge.read_byte(addr),
+            0x0..=0x7FFF => self.cartridge.read_byte(addr),
 
             // VRAM
             0x8000..=0x9FFF => self.ppu.get_byte(addr),
@@ -55,7 +455,7 @@ impl MMU {
             0xA000..=0xBFFF => self.cartridge.read_byte(addr),
 
             // Working RAM
-            0xC000..=0xDFFF => *self.ram.get((addr - 0x8000) as usize).unwrap_or(&0),
+            0xC000..=0xDFFF => self.wram[self.wram_index(addr)],
 
             // Echo RAM
             0xE000..=0xFDFF => {
@@ -67,9 +467,40 @@ impl MMU {
             // Joypad
             0xFF00 => self.joypad.read_byte(addr),
 
+            // Sound
+            0xFF10..=0xFF3F => self.sound[(addr - 0xFF10) as usize],
+
             // Interrupt registers
             0xFF04..=0xFF07 => self.timer.read_byte(addr),
 
+            // DMA transfer
+            0xFF46 => self.dma.register(),
+
+            0xFF4C => 0xFF,
+
+            // CGB infrared port. Nothing is ever received.
+            0xFF56 => 0xFF,
+
+            // CGB speed switch, VRAM DMA and WRAM bank
+            0xFF4D if self.cgb_mode => {
+                0x7E | ((self.double_speed as u8) << 7) | self.speed_switch_armed as u8
+            }
+            0xFF51..=0xFF55 if self.cgb_mode => self.hdma.read_byte(addr),
+            0xFF70 if self.cgb_mode => 0xF8 | self.wram_bank as u8,
+            0xFF4D | 0xFF51..=0xFF55 | 0xFF70 => 0xFF,
+
+            // CGB object priority mode and undocumented registers. The PCM
+            // amplitudes read as silence since there's no sound yet.
+            0xFF6C if self.cgb_mode => 0xFE | self.opri,
+            0xFF72 | 0xFF73 if self.model.is_cgb() => self.undocumented[(addr - 0xFF72) as usize],
+            0xFF74 if self.cgb_mode => self.undocumented[2],
+            0xFF75 if self.model.is_cgb() => 0x8F | self.undocumented[3],
+            0xFF76 | 0xFF77 if self.model.is_cgb() => 0x00,
+
+            // Nothing there, or not usable
+            0xFF03 | 0xFF08..=0xFF0E | 0xFF4E | 0xFF57..=0xFF67 | 0xFF6C..=0xFF7F => 0xFF,
+            0xFEA0..=0xFEFF => 0xFF,
+
             // Boot rom enabled
             0xFF50 => self.boot_rom_enabled as u8,
 
@@ -93,6 +524,25 @@ impl MMU {
     }
 
     pub fn write_byte(&mut self, addr: u16, value: u8) {
+        if self.record_accesses {
+            self.record_access(addr, value, true);
+        }
+
+        // Only look up the value being overwritten when someone is watching
+        if self.watchpoints.iter().any(|w| w.contains(addr)) {
+            self.check_watchpoints(WatchHit {
+                addr: self.banked(addr),
+                write: true,
+                old: self.read_mapped_byte(addr),
+                new: value,
+            });
+        }
+
+        // Writes that collide with an active DMA transfer are lost
+        if self.dma.is_active() && (self.dma_conflict(addr) || (0xFE00..=0xFEFF).contains(&addr)) {
+            return;
+        }
+
         match addr {
             // ROM bank - ignore
             0x0..=0x7FFF => self.cartridge.write_byte(addr, value),
@@ -104,14 +554,59 @@ impl MMU {
             0xA000..=0xBFFF => self.cartridge.write_byte(addr, value),
 
             // Working RAM
-            0xC000..=0xDFFF => self.ram[addr as usize - 0x8000] = value,
+            0xC000..=0xDFFF => {
+                let index = self.wram_index(addr);
+                self.wram[index] = value;
+            }
 
-            // Joypad
-            0xFF00 => self.joypad.write_byte(addr, value),
+            // Joypad. The SGB listens in for command packets, but only from
+            // games that say they support it.
+            0xFF00 => {
+                self.joypad.write_byte(addr, value);
+
+                let sgb_game = self.cartridge.header.supports_sgb();
+                if let Some(sgb) = self.sgb.as_mut().filter(|_| sgb_game) {
+                    if let Some(players) = sgb.write_joypad(value, &self.ppu) {
+                        self.joypad.set_players(players);
+                    }
+                }
+            }
 
             // Serial transfer - currently unsupported
             0xFF01..=0xFF02 => (),
 
+            // Sound
+            0xFF10..=0xFF3F => self.sound[(addr - 0xFF10) as usize] = value,
+
+            // KEY0. The CGB boot ROM switches DMG games to compatibility mode.
+            0xFF4C => {
+                if self.boot_rom_enabled && self.cgb_mode && value & 0x4 == 0x4 {
+                    self.cgb_mode = false;
+                    self.ppu.switch_to_dmg_compatibility();
+                }
+            }
+
+            // CGB infrared port - currently unsupported
+            0xFF56 => (),
+
+            // CGB speed switch, VRAM DMA and WRAM bank. Bank 0 can't be
+            // mapped at 0xD000, selecting it gives bank 1.
+            0xFF4D if self.cgb_mode => self.speed_switch_armed = value & 0x1 == 0x1,
+            0xFF51..=0xFF55 if self.cgb_mode => self.write_hdma(addr, value),
+            0xFF70 if self.cgb_mode => self.wram_bank = (value as usize & 0x7).max(1),
+            0xFF4D | 0xFF51..=0xFF55 | 0xFF70 => (),
+
+            // CGB object priority mode and undocumented registers
+            0xFF6C if self.cgb_mode => self.opri = value & 0x1,
+            0xFF72 | 0xFF73 if self.model.is_cgb() => {
+                self.undocumented[(addr - 0xFF72) as usize] = value
+            }
+            0xFF74 if self.cgb_mode => self.undocumented[2] = value,
+            0xFF75 if self.model.is_cgb() => self.undocumented[3] = value & 0x70,
+
+            // Read only, or nothing there
+            0xFF03 | 0xFF08..=0xFF0E | 0xFF4E | 0xFF57..=0xFF67 | 0xFF6C..=0xFF7F => (),
+
             // Timer
             0xFF04..=0xFF07 => self.timer.write_byte(addr, value),
 
@@ -124,15 +619,21 @@ impl MMU {
             }
 
             // DMA transfer
-            0xFF46 => {
-                let source_addr = (value as u16) << 8;
-                for offset in 0x0..=0x9F {
-                    self.write_byte(0xFE00 + offset, self.read_byte(source_addr + offset))
-                }
-            }
+            0xFF46 => self.dma.start(value),
 
             // Enable boot rom
-            0xFF50 => self.boot_rom_enabled = value == 0,
+            0xFF50 => {
+                // The CGB boot ROM checks for a palette button combination
+                // just before it hands over. A real CGB boot ROM has already
+                // done this itself.
+                if self.boot_rom_enabled && value != 0 && self.boot_rom.len() != CGB_BOOT_ROM_SIZE {
+                    let dpad = self.joypad.pressed_directions();
+                    let buttons = self.joypad.pressed_buttons();
+                    self.ppu.select_compatibility_palettes(dpad, buttons);
+                }
+
+                self.boot_rom_enabled = value == 0;
+            }
 
             // Not usable. Ignore writes...
             0xFEA0..=0xFEFF => (),
@@ -152,3 +653,188 @@ impl MMU {
         }
     }
 }
+
+impl SaveState for MMU {
+    fn save(&self, state: &mut StateWriter) {
+        state.bool(self.boot_rom_enabled);
+        state.bool(self.cgb_mode);
+        state.bytes(&self.ram[HRAM]);
+        state.bytes(&self.wram);
+        state.u8(self.wram_bank as u8);
+        state.bytes(&self.sound);
+        state.u8(self.ie);
+        state.bool(self.double_speed);
+        state.bool(self.speed_switch_armed);
+        state.u32(self.hdma_stall);
+        state.u8(self.opri);
+        state.bytes(&self.undocumented);
+
+        self.cartridge.save(state);
+        self.joypad.save(state);
+        self.ppu.save(state);
+        self.timer.save(state);
+        self.dma.save(state);
+        self.hdma.save(state);
+        if let Some(sgb) = &self.sgb {
+            sgb.save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.boot_rom_enabled = state.bool()?;
+        self.cgb_mode = state.bool()?;
+        state.bytes(&mut self.ram[HRAM])?;
+        state.bytes(&mut self.wram)?;
+        self.wram_bank = (state.u8()? as usize & 0x7).max(1);
+        state.bytes(&mut self.sound)?;
+        self.ie = state.u8()?;
+        self.double_speed = state.bool()?;
+        self.speed_switch_armed = state.bool()?;
+        self.hdma_stall = state.u32()?;
+        self.opri = state.u8()?;
+        state.bytes(&mut self.undocumented)?;
+
+        self.cartridge.load(state)?;
+        self.joypad.load(state)?;
+        self.ppu.load(state)?;
+        self.timer.load(state)?;
+        self.dma.load(state)?;
+        self.hdma.load(state)?;
+        if let Some(sgb) = &mut self.sgb {
+            sgb.load(state)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MMU;
+    use crate::gameboy::model::Model;
+
+    fn cgb_mmu() -> MMU {
+        let mut rom = vec![0; 0x8000];
+        rom[0x143] = 0x80;
+        MMU::new(rom, Model::Cgb, None)
+    }
+
+    // Start a transfer from 0xC000 and let it copy `bytes` bytes
+    fn start_dma(mmu: &mut MMU, bytes: u8) {
+        for i in 0..0xA0 {
+            mmu.write_byte(0xC000 + i, i as u8 ^ 0x55);
+        }
+        mmu.write_byte(0xFF46, 0xC0);
+
+        // One M-cycle before it starts
+        for _ in 0..=bytes {
+            mmu.do_cycles(4);
+        }
+    }
+
+    #[test]
+    fn oam_dma_copies_to_oam() {
+        let mut mmu = MMU::new(vec![0; 0x8000], Model::Dmg, None);
+        mmu.write_byte(0xFF40, 0x00);
+
+        start_dma(&mut mmu, 0xA0);
+        assert!(!mmu.dma.is_active());
+        for i in 0..0xA0 {
+            assert_eq!(mmu.read_byte(0xFE00 + i), i as u8 ^ 0x55);
+        }
+    }
+
+    #[test]
+    fn oam_dma_conflicts_on_the_same_bus() {
+        let mut mmu = MMU::new(vec![0; 0x8000], Model::Dmg, None);
+        mmu.write_byte(0xFF40, 0x00);
+        mmu.write_byte(0xFF80, 0x12);
+        start_dma(&mut mmu, 3);
+
+        // The last byte copied shows up across the external bus
+        assert_eq!(mmu.read_byte(0xC0FF), 0x02 ^ 0x55);
+        assert_eq!(mmu.read_byte(0x0150), 0x02 ^ 0x55);
+
+        // OAM is locked, and HRAM and VRAM are on other buses
+        assert_eq!(mmu.read_byte(0xFE00), 0xFF);
+        assert_eq!(mmu.read_byte(0xFF80), 0x12);
+        assert_eq!(mmu.read_byte(0x8000), 0x00);
+    }
+
+    #[test]
+    fn cgb_working_ram_has_its_own_bus() {
+        let mut mmu = cgb_mmu();
+        start_dma(&mut mmu, 3);
+
+        assert_eq!(mmu.read_byte(0xC0FF), 0x02 ^ 0x55);
+        assert_eq!(mmu.read_byte(0x0150), 0x00);
+    }
+
+    #[test]
+    fn cgb_registers_hold_what_was_written() {
+        let mut mmu = cgb_mmu();
+        assert!(mmu.cgb_mode);
+
+        mmu.write_byte(0xFF6C, 0x01);
+        assert_eq!(mmu.read_byte(0xFF6C), 0xFF);
+        mmu.write_byte(0xFF6C, 0x00);
+        assert_eq!(mmu.read_byte(0xFF6C), 0xFE);
+
+        for (addr, value) in [(0xFF72, 0x12), (0xFF73, 0x34), (0xFF74, 0x56)] {
+            mmu.write_byte(addr, value);
+            assert_eq!(mmu.read_byte(addr), value);
+        }
+
+        mmu.write_byte(0xFF75, 0xFF);
+        assert_eq!(mmu.read_byte(0xFF75), 0xFF);
+        mmu.write_byte(0xFF75, 0x00);
+        assert_eq!(mmu.read_byte(0xFF75), 0x8F);
+
+        mmu.write_byte(0xFF76, 0x12);
+        assert_eq!(mmu.read_byte(0xFF76), 0x00);
+    }
+
+    #[test]
+    fn unmapped_io_is_open_bus() {
+        for model in [Model::Dmg, Model::Cgb] {
+            let mut mmu = MMU::new(vec![0; 0x8000], model, None);
+
+            for addr in [
+                0xFF03, 0xFF08, 0xFF4E, 0xFF57, 0xFF6C, 0xFF71, 0xFF78, 0xFF7F,
+            ] {
+                mmu.write_byte(addr, 0x00);
+                assert_eq!(mmu.read_byte(addr), 0xFF, "{:#06X}", addr);
+            }
+        }
+    }
+
+    // Copy one block to 0x8000 with a general purpose VRAM DMA
+    fn vram_dma(mmu: &mut MMU, source: u16) -> Vec<u8> {
+        mmu.write_byte(0xFF51, (source >> 8) as u8);
+        mmu.write_byte(0xFF52, source as u8);
+        mmu.write_byte(0xFF53, 0x00);
+        mmu.write_byte(0xFF54, 0x00);
+        mmu.write_byte(0xFF55, 0x00);
+        (0x8000..0x8010).map(|addr| mmu.read_byte(addr)).collect()
+    }
+
+    #[test]
+    fn vram_dma_copies_working_ram() {
+        let mut mmu = cgb_mmu();
+        mmu.write_byte(0xFF40, 0x00);
+        for i in 0..0x10 {
+            mmu.write_byte(0xC000 + i, i as u8);
+        }
+
+        assert_eq!(vram_dma(&mut mmu, 0xC000), (0..0x10).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn vram_dma_from_elsewhere_reads_0xff() {
+        let mut mmu = cgb_mmu();
+        mmu.write_byte(0xFF40, 0x00);
+
+        for source in [0x8000, 0x9FF0, 0xE000, 0xFDF0, 0xFE00, 0xFF00, 0xFFF0] {
+            assert_eq!(vram_dma(&mut mmu, source), [0xFF; 0x10], "{:#06X}", source);
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use clap::ValueEnum;
+
+/// How 15-bit CGB colours are turned into the colours shown on a modern
+/// display.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum)]
+pub enum ColourCorrection {
+    /// Scale each channel straight up to 8 bits. Bright and oversaturated.
+    Raw,
+
+    /// Mimic the CGB's LCD, which bleeds the channels into each other and
+    /// has a darker response than a modern monitor.
+    #[default]
+    Lcd,
+
+    /// Mimic a backlit GBA SP, which is brighter with less colour bleed.
+    Gba,
+}
+
+// Each row gives how much of the source red, green and blue ends up in the
+// output channel
+type Matrix = [[f32; 3]; 3];
+
+const LCD_MATRIX: Matrix = [
+    [0.8125, 0.125, 0.0625],
+    [0.0, 0.75, 0.25],
+    [0.1875, 0.125, 0.6875],
+];
+
+const GBA_MATRIX: Matrix = [[0.86, 0.10, 0.04], [0.03, 0.88, 0.09], [0.02, 0.08, 0.90]];
+
+const DISPLAY_GAMMA: f32 = 2.2;
+
+impl ColourCorrection {
+    /// Lookup table from every 15-bit colour to its 32-bit frame buffer value.
+    pub fn table(self) -> Vec<u32> {
+        (0..0x8000).map(|colour| self.convert(colour)).collect()
+    }
+
+    fn convert(self, colour: u16) -> u32 {
+        let channels = [colour & 0x1F, (colour >> 5) & 0x1F, (colour >> 10) & 0x1F];
+
+        let [r, g, b] = match self {
+            Self::Raw => channels.map(|c| ((c << 3) | (c >> 2)) as u8),
+            Self::Lcd => mix(channels, &LCD_MATRIX, 2.5),
+            Self::Gba => mix(channels, &GBA_MATRIX, 2.0),
+        };
+
+        0xFF000000 | ((r as u32) << 16) | ((g as u32) << 8) | b as u32
+    }
+}
+
+/// Mix the channels in linear light. `screen_gamma` is the response of the
+/// screen being imitated - higher values give darker midtones.
+fn mix(channels: [u16; 3], matrix: &Matrix, screen_gamma: f32) -> [u8; 3] {
+    let linear = channels.map(|c| (c as f32 / 31.0).powf(screen_gamma));
+
+    matrix.map(|row| {
+        let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+        (value.clamp(0.0, 1.0).powf(1.0 / DISPLAY_GAMMA) * 255.0).round() as u8
+    })
+}
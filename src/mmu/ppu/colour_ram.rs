@@ -0,0 +1,78 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+const COLOUR_RAM_SIZE: usize = 64;
+
+/// CGB palette memory. Holds eight palettes of four colours, each stored as a
+/// little endian 15-bit BGR value. It is only reachable through an index
+/// register (BCPS/OCPS) and a data register (BCPD/OCPD).
+#[derive(Clone)]
+pub(super) struct ColourRam {
+    data: [u8; COLOUR_RAM_SIZE],
+    index: u8,
+    auto_increment: bool,
+}
+
+impl ColourRam {
+    pub fn new() -> ColourRam {
+        ColourRam {
+            // Palettes start out white
+            data: [0xFF; COLOUR_RAM_SIZE],
+            index: 0,
+            auto_increment: false,
+        }
+    }
+
+    pub fn read_index(&self) -> u8 {
+        0x40 | ((self.auto_increment as u8) << 7) | self.index
+    }
+
+    pub fn write_index(&mut self, value: u8) {
+        self.index = value & 0x3F;
+        self.auto_increment = value & 0x80 == 0x80;
+    }
+
+    pub fn read_data(&self) -> u8 {
+        self.data[self.index as usize]
+    }
+
+    /// Write to the data register. Writes are dropped while the PPU is
+    /// drawing but the index still advances.
+    pub fn write_data(&mut self, value: u8, accessible: bool) {
+        if accessible {
+            self.data[self.index as usize] = value;
+        }
+
+        if self.auto_increment {
+            self.index = (self.index + 1) & 0x3F;
+        }
+    }
+
+    /// Overwrite a whole palette, the way the boot ROM sets up DMG games.
+    pub fn set_palette(&mut self, palette: u8, colours: [u16; 4]) {
+        for (id, colour) in colours.iter().enumerate() {
+            let offset = (palette as usize & 0x7) * 8 + id * 2;
+            self.data[offset..offset + 2].copy_from_slice(&colour.to_le_bytes());
+        }
+    }
+
+    /// The 15-bit colour of entry `id` in the given palette.
+    pub fn colour(&self, palette: u8, id: u8) -> u16 {
+        let offset = (palette as usize & 0x7) * 8 + (id as usize & 0x3) * 2;
+        u16::from_le_bytes([self.data[offset], self.data[offset + 1]])
+    }
+}
+
+impl SaveState for ColourRam {
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.data);
+        state.u8(self.index);
+        state.bool(self.auto_increment);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.data)?;
+        self.index = state.u8()? & 0x3F;
+        self.auto_increment = state.bool()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,125 @@
+use crate::cartridge::header::CartridgeHeader;
+
+/// The colours the CGB boot ROM gives a DMG game: one palette for the
+/// background and one for each of OBP0 and OBP1.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) struct CompatibilityPalettes {
+    pub bg: [u16; 4],
+    pub obj0: [u16; 4],
+    pub obj1: [u16; 4],
+}
+
+/// Convert a 24-bit RGB colour to the CGB's 15-bit format.
+const fn rgb(colour: u32) -> u16 {
+    let r = ((colour >> 19) & 0x1F) as u16;
+    let g = ((colour >> 11) & 0x1F) as u16;
+    let b = ((colour >> 3) & 0x1F) as u16;
+    r | (g << 5) | (b << 10)
+}
+
+const fn palette(colours: [u32; 4]) -> [u16; 4] {
+    [
+        rgb(colours[0]),
+        rgb(colours[1]),
+        rgb(colours[2]),
+        rgb(colours[3]),
+    ]
+}
+
+const fn single(colours: [u32; 4]) -> CompatibilityPalettes {
+    CompatibilityPalettes {
+        bg: palette(colours),
+        obj0: palette(colours),
+        obj1: palette(colours),
+    }
+}
+
+const WHITE_BLUE: [u32; 4] = [0xFFFFFF, 0x63A5FF, 0x0000FF, 0x000000];
+const WHITE_RED: [u32; 4] = [0xFFFFFF, 0xFF8484, 0x943A3A, 0x000000];
+const WHITE_ORANGE: [u32; 4] = [0xFFFFFF, 0xFFAD63, 0x843100, 0x000000];
+const WHITE_GREEN: [u32; 4] = [0xFFFFFF, 0x7BFF31, 0x008400, 0x000000];
+
+const UP: CompatibilityPalettes = single(WHITE_ORANGE);
+const UP_A: CompatibilityPalettes = single(WHITE_RED);
+const UP_B: CompatibilityPalettes = single([0xFFE6C5, 0xCE9C84, 0x846B29, 0x5A3108]);
+
+const LEFT: CompatibilityPalettes = CompatibilityPalettes {
+    bg: palette(WHITE_BLUE),
+    obj0: palette(WHITE_RED),
+    obj1: palette(WHITE_BLUE),
+};
+const LEFT_A: CompatibilityPalettes = CompatibilityPalettes {
+    bg: palette([0xFFFFFF, 0x8C8CDE, 0x52528C, 0x000000]),
+    obj0: palette(WHITE_RED),
+    obj1: palette(WHITE_ORANGE),
+};
+const LEFT_B: CompatibilityPalettes = single([0xFFFFFF, 0xA5A5A5, 0x525252, 0x000000]);
+
+const DOWN: CompatibilityPalettes = single([0xFFFFA5, 0xFF9494, 0x9494FF, 0x000000]);
+const DOWN_A: CompatibilityPalettes = single([0xFFFFFF, 0xFFFF00, 0xFF0000, 0x000000]);
+const DOWN_B: CompatibilityPalettes = CompatibilityPalettes {
+    bg: palette([0xFFFFFF, 0xFFFF00, 0x7B4A00, 0x000000]),
+    obj0: palette(WHITE_BLUE),
+    obj1: palette(WHITE_GREEN),
+};
+
+const RIGHT: CompatibilityPalettes = single([0xFFFFFF, 0x52FF00, 0xFF4200, 0x000000]);
+const RIGHT_A: CompatibilityPalettes = CompatibilityPalettes {
+    bg: palette([0xFFFFFF, 0x7BFF31, 0x0063C5, 0x000000]),
+    obj0: palette(WHITE_RED),
+    obj1: palette(WHITE_RED),
+};
+const RIGHT_B: CompatibilityPalettes = single([0x000000, 0x008484, 0xFFDE00, 0xFFFFFF]);
+
+/// Used for every game that isn't in the checksum table.
+const DEFAULT: CompatibilityPalettes = RIGHT_A;
+
+/// Nintendo titles the boot ROM recognises, keyed by the sum of the title
+/// bytes. Some sums are shared, so those entries also check the fourth letter
+/// of the title.
+const TITLE_PALETTES: &[(u8, Option<u8>, CompatibilityPalettes)] = &[
+    // POKEMON RED
+    (0x14, None, UP_A),
+    // POKEMON BLUE
+    (0x61, Some(b'E'), LEFT),
+];
+
+/// The palettes the boot ROM picks automatically for a DMG game.
+pub(super) fn title_palettes(header: &CartridgeHeader) -> CompatibilityPalettes {
+    if !header.is_nintendo_licensed() {
+        return DEFAULT;
+    }
+
+    let checksum = header.title_checksum();
+    let fourth_letter = header.title().as_bytes().get(3).copied();
+
+    TITLE_PALETTES
+        .iter()
+        .find(|(sum, letter, _)| {
+            *sum == checksum && letter.is_none_or(|l| Some(l) == fourth_letter)
+        })
+        .map_or(DEFAULT, |(_, _, palettes)| *palettes)
+}
+
+/// Holding a direction, optionally with A or B, while the boot logo is shown
+/// overrides the automatic choice. `dpad` and `buttons` hold the pressed
+/// buttons in the same bit order as the joypad register.
+pub(super) fn button_palettes(dpad: u8, buttons: u8) -> Option<CompatibilityPalettes> {
+    let a = buttons & 0x1 == 0x1;
+    let b = buttons & 0x2 == 0x2;
+
+    let palettes = match dpad {
+        0x1 => [RIGHT, RIGHT_A, RIGHT_B],
+        0x2 => [LEFT, LEFT_A, LEFT_B],
+        0x4 => [UP, UP_A, UP_B],
+        0x8 => [DOWN, DOWN_A, DOWN_B],
+        _ => return None,
+    };
+
+    match (a, b) {
+        (false, false) => Some(palettes[0]),
+        (true, false) => Some(palettes[1]),
+        (false, true) => Some(palettes[2]),
+        (true, true) => None,
+    }
+}
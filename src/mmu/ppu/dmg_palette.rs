@@ -0,0 +1,114 @@
+use std::fs;
+
+use serde_json::Value;
+
+/// The colours used for the four DMG shades, lightest first. The background
+/// and both object palettes can each be given their own colours.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DmgPalette {
+    pub name: String,
+    pub bg: [u32; 4],
+    pub obj0: [u32; 4],
+    pub obj1: [u32; 4],
+}
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        DmgPalette::uniform("grey", [0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000])
+    }
+}
+
+impl DmgPalette {
+    fn uniform(name: &str, colours: [u32; 4]) -> DmgPalette {
+        let colours = colours.map(|colour| 0xFF000000 | colour);
+        DmgPalette {
+            name: name.to_string(),
+            bg: colours,
+            obj0: colours,
+            obj1: colours,
+        }
+    }
+
+    /// The built in palettes. The first one is used unless another is chosen.
+    pub fn presets() -> Vec<DmgPalette> {
+        vec![
+            DmgPalette::default(),
+            DmgPalette::uniform("green", [0x9BBC0F, 0x8BAC0F, 0x306230, 0x0F380F]),
+            DmgPalette::uniform("pocket", [0xC4CFA1, 0x8B956D, 0x4D533C, 0x1F1F1F]),
+            DmgPalette::uniform("light", [0x00B581, 0x009A71, 0x00694A, 0x004F3B]),
+        ]
+    }
+
+    /// Load user palettes from a JSON file. The file holds a list of palettes,
+    /// each with a name and four colours written as "#RRGGBB":
+    ///
+    /// ```json
+    /// [
+    ///   { "name": "sepia", "colours": ["#FFF5DD", "#C9A97A", "#7A5230", "#2B1A0F"] },
+    ///   { "name": "mixed", "colours": ["#FFFFFF", "#AAAAAA", "#555555", "#000000"],
+    ///     "obj0": ["#FFFFFF", "#FF8484", "#943A3A", "#000000"] }
+    /// ]
+    /// ```
+    ///
+    /// "bg", "obj0" and "obj1" override the colours of a single layer.
+    pub fn load(path: &str) -> Result<Vec<DmgPalette>, String> {
+        let contents = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let json: Value =
+            serde_json::from_str(&contents).map_err(|e| format!("{}: {}", path, e))?;
+
+        json.as_array()
+            .ok_or("expected a list of palettes")?
+            .iter()
+            .map(DmgPalette::from_json)
+            .collect()
+    }
+
+    fn from_json(json: &Value) -> Result<DmgPalette, String> {
+        let name = json["name"].as_str().ok_or("palette is missing a name")?;
+
+        let layer = |key: &str| -> Result<Option<[u32; 4]>, String> {
+            match json.get(key) {
+                Some(value) => parse_colours(value).map(Some).ok_or(format!(
+                    "{}: \"{}\" should be four \"#RRGGBB\" colours",
+                    name, key
+                )),
+                None => Ok(None),
+            }
+        };
+
+        let colours = layer("colours")?;
+        let pick = |key: &str| -> Result<[u32; 4], String> {
+            layer(key)?
+                .or(colours)
+                .ok_or(format!("{}: no colours given for \"{}\"", name, key))
+        };
+
+        Ok(DmgPalette {
+            name: name.to_string(),
+            bg: pick("bg")?,
+            obj0: pick("obj0")?,
+            obj1: pick("obj1")?,
+        })
+    }
+}
+
+fn parse_colours(value: &Value) -> Option<[u32; 4]> {
+    let colours = value
+        .as_array()?
+        .iter()
+        .map(|colour| parse_colour(colour.as_str()?))
+        .collect::<Option<Vec<u32>>>()?;
+
+    colours.try_into().ok()
+}
+
+fn parse_colour(colour: &str) -> Option<u32> {
+    let hex = colour.strip_prefix('#').unwrap_or(colour);
+    if hex.len() != 6 {
+        return None;
+    }
+
+    u32::from_str_radix(hex, 16)
+        .ok()
+        .map(|rgb| 0xFF000000 | rgb)
+}
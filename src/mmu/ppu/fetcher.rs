@@ -0,0 +1,208 @@
+use super::{fifo::BackgroundPixel, PPU};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// Each of the first three fetcher steps takes two dots. The push step is
+/// retried every dot until the background FIFO has room.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(super) enum FetcherStep {
+    Tile,
+    DataLow,
+    DataHigh,
+    Push,
+}
+
+/// Background/window tile fetcher. Feeds the background FIFO eight pixels at
+/// a time during mode 3.
+#[derive(Clone)]
+pub(super) struct Fetcher {
+    pub step: FetcherStep,
+    pub window: bool,
+    warmup: bool,
+    dots: u8,
+    tile_x: u8,
+    tile_index: u8,
+    tile_attributes: u8,
+    tile_low: u8,
+    tile_high: u8,
+}
+
+impl Fetcher {
+    pub fn new() -> Fetcher {
+        Fetcher {
+            step: FetcherStep::Tile,
+            window: false,
+            // The very first fetch of a line is thrown away
+            warmup: true,
+            dots: 0,
+            tile_x: 0,
+            tile_index: 0,
+            tile_attributes: 0,
+            tile_low: 0,
+            tile_high: 0,
+        }
+    }
+
+    /// Restart fetching from the first column of the window. A line that
+    /// starts with the window still pays for the discarded first fetch.
+    pub fn start_window(&mut self) {
+        *self = Fetcher {
+            window: true,
+            warmup: self.warmup,
+            ..Fetcher::new()
+        };
+    }
+}
+
+impl PPU {
+    pub(super) fn step_fetcher(&mut self) {
+        match self.fetcher.step {
+            FetcherStep::Push => {
+                if !self.bg_fifo.is_empty() {
+                    return;
+                }
+
+                let (low, high) = (self.fetcher.tile_low, self.fetcher.tile_high);
+                let attributes = self.fetcher.tile_attributes;
+                let x_flip = attributes & 0x20 == 0x20;
+
+                for column in 0..8 {
+                    let bit = if x_flip { column } else { 7 - column };
+                    self.bg_fifo.push(BackgroundPixel {
+                        colour: (((high >> bit) & 1) << 1) | ((low >> bit) & 1),
+                        palette: attributes & 0x7,
+                        priority: attributes & 0x80 == 0x80,
+                    });
+                }
+
+                self.fetcher.tile_x = self.fetcher.tile_x.wrapping_add(1);
+                self.fetcher.step = FetcherStep::Tile;
+            }
+
+            step => {
+                self.fetcher.dots += 1;
+                if self.fetcher.dots < 2 {
+                    return;
+                }
+                self.fetcher.dots = 0;
+
+                self.fetcher.step = match step {
+                    FetcherStep::Tile => {
+                        let (tile_index, tile_attributes) = self.fetch_tile_index();
+                        self.fetcher.tile_index = tile_index;
+                        self.fetcher.tile_attributes = tile_attributes;
+                        FetcherStep::DataLow
+                    }
+                    FetcherStep::DataLow => {
+                        self.fetcher.tile_low = self.fetch_tile_data(0);
+                        FetcherStep::DataHigh
+                    }
+                    _ if self.fetcher.warmup => {
+                        self.fetcher.warmup = false;
+                        FetcherStep::Tile
+                    }
+                    _ => {
+                        self.fetcher.tile_high = self.fetch_tile_data(1);
+                        FetcherStep::Push
+                    }
+                };
+            }
+        }
+    }
+
+    /// Read the tile index from the tile map. In CGB mode the same position in
+    /// VRAM bank 1 holds the tile's attributes.
+    fn fetch_tile_index(&self) -> (u8, u8) {
+        let (tile_map, map_x, map_y) = if self.fetcher.window {
+            let tile_map = if self.lcdc & 0x40 == 0x40 {
+                0x1C00
+            } else {
+                0x1800
+            };
+            (tile_map, self.fetcher.tile_x, self.window_line)
+        } else {
+            let tile_map = if self.lcdc & 0x8 == 0x8 {
+                0x1C00
+            } else {
+                0x1800
+            };
+            let map_x = (self.scx / 8).wrapping_add(self.fetcher.tile_x);
+            (tile_map, map_x, self.ly.wrapping_add(self.scy))
+        };
+
+        let address = tile_map + (map_y as usize / 8) * 32 + (map_x as usize % 32);
+        let attributes = if self.cgb_mode {
+            self.vram[1][address]
+        } else {
+            0
+        };
+
+        (self.vram[0][address], attributes)
+    }
+
+    fn fetch_tile_data(&self, offset: usize) -> u8 {
+        // The background and window can be switched off entirely on the DMG,
+        // in which case they are drawn with colour 0
+        if !self.cgb_mode && self.lcdc & 0x1 != 0x1 {
+            return 0;
+        }
+
+        let attributes = self.fetcher.tile_attributes;
+        let tile_line = if self.fetcher.window {
+            self.window_line
+        } else {
+            self.ly.wrapping_add(self.scy)
+        } % 8;
+
+        let tile_line = if attributes & 0x40 == 0x40 {
+            7 - tile_line
+        } else {
+            tile_line
+        };
+
+        let bank = ((attributes >> 3) & 1) as usize;
+        self.vram[bank]
+            [self.tile_data_address(self.fetcher.tile_index) + (tile_line as usize * 2) + offset]
+    }
+
+    /// Offset into VRAM of the given background tile, taking the LCDC
+    /// addressing mode into account
+    pub(super) fn tile_data_address(&self, tile_index: u8) -> usize {
+        if self.lcdc & 0x10 > 0 {
+            tile_index as usize * 16
+        } else {
+            (0x1000 + (tile_index as i8 as isize) * 16) as usize
+        }
+    }
+}
+
+impl SaveState for Fetcher {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.step as u8);
+        state.bool(self.window);
+        state.bool(self.warmup);
+        state.u8(self.dots);
+        state.u8(self.tile_x);
+        state.u8(self.tile_index);
+        state.u8(self.tile_attributes);
+        state.u8(self.tile_low);
+        state.u8(self.tile_high);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.step = match state.u8()? {
+            0 => FetcherStep::Tile,
+            1 => FetcherStep::DataLow,
+            2 => FetcherStep::DataHigh,
+            _ => FetcherStep::Push,
+        };
+        self.window = state.bool()?;
+        self.warmup = state.bool()?;
+        self.dots = state.u8()?;
+        self.tile_x = state.u8()?;
+        self.tile_index = state.u8()?;
+        self.tile_attributes = state.u8()?;
+        self.tile_low = state.u8()?;
+        self.tile_high = state.u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,131 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+const FIFO_CAPACITY: usize = 16;
+
+/// A single pixel waiting in the background FIFO. The palette and priority
+/// come from the BG map attributes and are always 0 outside of CGB mode.
+#[derive(Clone, Copy, Default)]
+pub(super) struct BackgroundPixel {
+    pub colour: u8,
+    pub palette: u8,
+    pub priority: bool,
+}
+
+/// A single pixel waiting in the object FIFO. On the DMG the palette is OBP0
+/// or OBP1, in CGB mode it is one of the eight object colour palettes.
+#[derive(Clone, Copy, Default)]
+pub(super) struct ObjectPixel {
+    pub colour: u8,
+    pub palette: u8,
+    pub bg_priority: bool,
+    pub oam_index: u8,
+}
+
+/// Fixed capacity ring buffer used for both the background and object pixel
+/// FIFOs. The hardware never holds more than 16 pixels in either.
+#[derive(Clone)]
+pub(super) struct PixelFifo<T: Copy + Default> {
+    pixels: [T; FIFO_CAPACITY],
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy + Default> PixelFifo<T> {
+    pub fn new() -> PixelFifo<T> {
+        PixelFifo {
+            pixels: [T::default(); FIFO_CAPACITY],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    pub fn push(&mut self, pixel: T) {
+        debug_assert!(self.len < FIFO_CAPACITY);
+        self.pixels[(self.head + self.len) % FIFO_CAPACITY] = pixel;
+        self.len += 1;
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let pixel = self.pixels[self.head];
+        self.head = (self.head + 1) % FIFO_CAPACITY;
+        self.len -= 1;
+        Some(pixel)
+    }
+
+    pub fn get(&self, index: usize) -> T {
+        self.pixels[(self.head + index) % FIFO_CAPACITY]
+    }
+
+    pub fn set(&mut self, index: usize, pixel: T) {
+        self.pixels[(self.head + index) % FIFO_CAPACITY] = pixel;
+    }
+}
+
+impl SaveState for BackgroundPixel {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.colour);
+        state.u8(self.palette);
+        state.bool(self.priority);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.colour = state.u8()? & 0x3;
+        self.palette = state.u8()? & 0x7;
+        self.priority = state.bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for ObjectPixel {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.colour);
+        state.u8(self.palette);
+        state.bool(self.bg_priority);
+        state.u8(self.oam_index);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.colour = state.u8()? & 0x3;
+        self.palette = state.u8()? & 0x7;
+        self.bg_priority = state.bool()?;
+        self.oam_index = state.u8()?;
+        Ok(())
+    }
+}
+
+/// Only the pixels waiting in the FIFO are saved, front first.
+impl<T: Copy + Default + SaveState> SaveState for PixelFifo<T> {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.len as u8);
+        for i in 0..self.len {
+            self.get(i).save(state);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.clear();
+        for _ in 0..(state.u8()? as usize).min(FIFO_CAPACITY) {
+            let mut pixel = T::default();
+            pixel.load(state)?;
+            self.push(pixel);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,869 @@
+pub mod colour_correction;
+mod colour_ram;
+mod compatibility;
+pub mod dmg_palette;
+mod fetcher;
+mod fifo;
+mod objects;
+mod stat;
+
+use std::fmt;
+
+use crate::cartridge::header::CartridgeHeader;
+use crate::debugger::is_gameboy_doctor;
+use crate::gameboy::model::Model;
+use crate::state::{SaveState, StateReader, StateWriter};
+use colour_correction::ColourCorrection;
+use colour_ram::ColourRam;
+use compatibility::{button_palettes, title_palettes, CompatibilityPalettes};
+use dmg_palette::DmgPalette;
+use fetcher::{Fetcher, FetcherStep};
+use fifo::{BackgroundPixel, ObjectPixel, PixelFifo};
+use objects::Object;
+
+const VRAM_SIZE: usize = 0x2000;
+const VOAM_SIZE: usize = 0xA0;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+const DOTS_PER_LINE: u32 = 456;
+const OAM_SCAN_DOTS: u32 = 80;
+const LINES_PER_FRAME: u8 = 154;
+const DOTS_PER_FRAME: u32 = DOTS_PER_LINE * LINES_PER_FRAME as u32;
+const MAX_OBJECTS_PER_LINE: usize = 10;
+
+// The boot ROM hands over shortly before the end of the last line of VBlank
+const POST_BOOT_DOT: u32 = 400;
+
+pub type Tile = [[u8; 8]; 8];
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Mode {
+    HBlank = 0,
+    VBlank = 1,
+    OamScan = 2,
+    Drawing = 3,
+}
+
+#[derive(Clone)]
+pub struct PPU {
+    pub frame_buffer: Vec<u32>,
+
+    // The DMG shade of every pixel, after BGP/OBP0/OBP1. The SGB colours the
+    // screen from these.
+    shades: Vec<u8>,
+
+    frame_available: bool,
+    frame_number: u32,
+
+    // Set on entering HBlank on a visible line. Drives HBlank VRAM DMA.
+    hblank_started: bool,
+    pub vblank_irq: bool,
+    pub stat_irq: bool,
+
+    vram: [[u8; VRAM_SIZE]; 2],
+    voam: [u8; VOAM_SIZE],
+
+    model: Model,
+
+    // Game Boy Color state. VRAM bank 1 and the colour palettes are only
+    // used when the cartridge asks for CGB mode.
+    pub cgb_mode: bool,
+    vram_bank: usize,
+    bg_colour_ram: ColourRam,
+    obj_colour_ram: ColourRam,
+
+    // A DMG game running on a CGB. The shades picked by BGP, OBP0 and OBP1
+    // are looked up in the colour palettes the boot ROM set up.
+    dmg_compatibility: bool,
+
+    colour_correction: ColourCorrection,
+    colour_table: Vec<u32>,
+
+    // Colours used for the four shades on the DMG
+    dmg_palette: DmgPalette,
+
+    pub scy: u8,
+    pub scx: u8,
+    pub ly: u8,
+    pub lyc: u8,
+    pub lcdc: u8,
+    pub bgp: u8,
+    pub mode: Mode,
+
+    // Dot within the current scanline
+    pub modeclock: u32,
+
+    pub wy: u8,
+    pub wx: u8,
+
+    pub obj_palette_0: u8,
+    pub obj_palette_1: u8,
+
+    pub stat: u8,
+
+    // The scanline being drawn. Usually the same as LY, except at the start
+    // of line 153 where LY already reads 0.
+    line: u8,
+
+    // Combined STAT interrupt sources. An interrupt is only requested when
+    // this goes from low to high.
+    stat_line: bool,
+
+    // The first line after the LCD is switched on skips OAM scan, and the
+    // first frame is never shown
+    starting_up: bool,
+    skip_frame: bool,
+    lcd_off_dots: u32,
+
+    // The window keeps its own line counter which only advances on lines
+    // where it was actually drawn. It can only appear once LY has matched WY
+    // at the start of a line during the current frame.
+    window_line: u8,
+    wy_triggered: bool,
+    window_drawn: bool,
+
+    // Mode 3 state
+    fetcher: Fetcher,
+    bg_fifo: PixelFifo<BackgroundPixel>,
+    obj_fifo: PixelFifo<ObjectPixel>,
+    lx: u8,
+    discard_pixels: u8,
+    objects: [Object; MAX_OBJECTS_PER_LINE],
+    object_count: usize,
+    object_fetch: Option<(usize, u8)>,
+}
+
+impl Default for PPU {
+    fn default() -> Self {
+        Self::new(Model::Dmg, false)
+    }
+}
+
+impl PPU {
+    pub fn new(model: Model, cgb_mode: bool) -> PPU {
+        PPU {
+            frame_buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            shades: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            frame_available: false,
+            frame_number: 1,
+            hblank_started: false,
+            vblank_irq: false,
+            stat_irq: false,
+
+            scy: 0,
+            scx: 0,
+            bgp: 0,
+            ly: 0,
+            lyc: 0,
+            lcdc: 0,
+            mode: Mode::HBlank,
+            modeclock: 0,
+            stat: 0,
+
+            line: 0,
+            stat_line: false,
+            starting_up: false,
+            skip_frame: false,
+            lcd_off_dots: 0,
+
+            window_line: 0,
+            wy_triggered: false,
+            window_drawn: false,
+
+            wy: 1,
+            wx: 1,
+
+            obj_palette_0: 0,
+            obj_palette_1: 0,
+
+            vram: [[0; VRAM_SIZE]; 2],
+            voam: [0; VOAM_SIZE],
+
+            model,
+            cgb_mode,
+            vram_bank: 0,
+            bg_colour_ram: ColourRam::new(),
+            obj_colour_ram: ColourRam::new(),
+            dmg_compatibility: false,
+
+            colour_correction: ColourCorrection::default(),
+            colour_table: ColourCorrection::default().table(),
+            dmg_palette: DmgPalette::default(),
+
+            fetcher: Fetcher::new(),
+            bg_fifo: PixelFifo::new(),
+            obj_fifo: PixelFifo::new(),
+            lx: 0,
+            discard_pixels: 0,
+            objects: [Object::default(); MAX_OBJECTS_PER_LINE],
+            object_count: 0,
+            object_fetch: None,
+        }
+    }
+
+    /// Run the PPU for the given number of dots (T-cycles).
+    pub fn do_cycle(&mut self, ticks: u32) {
+        for _ in 0..ticks {
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        if !self.lcd_enabled() {
+            self.tick_lcd_off();
+            return;
+        }
+
+        match self.mode {
+            Mode::OamScan => {
+                if self.modeclock % 2 == 1 {
+                    self.scan_oam_entry(self.modeclock as usize / 2);
+                }
+
+                if self.modeclock == OAM_SCAN_DOTS - 1 {
+                    self.start_drawing();
+                }
+            }
+            Mode::Drawing => self.step_drawing(),
+            Mode::HBlank => {
+                if self.starting_up && self.modeclock == OAM_SCAN_DOTS - 1 {
+                    self.starting_up = false;
+                    self.start_drawing();
+                }
+            }
+            Mode::VBlank => (),
+        }
+
+        self.modeclock += 1;
+        if self.modeclock == DOTS_PER_LINE {
+            self.modeclock = 0;
+            self.next_line();
+        }
+
+        // LY flips to 0 a few dots into the last line of VBlank
+        if self.line == LINES_PER_FRAME - 1 && self.modeclock == 4 {
+            self.ly = 0;
+        }
+
+        self.update_stat_line();
+    }
+
+    fn next_line(&mut self) {
+        if self.window_drawn {
+            self.window_drawn = false;
+            self.window_line += 1;
+        }
+
+        self.line = (self.line + 1) % LINES_PER_FRAME;
+        self.ly = self.line;
+
+        match self.line {
+            0..=143 => {
+                if self.line == 0 {
+                    self.window_line = 0;
+                    self.wy_triggered = false;
+                }
+
+                self.start_oam_scan();
+            }
+
+            // Enter mode 1 (VBLANK). The frame is complete.
+            144 => {
+                self.mode = Mode::VBlank;
+                self.vblank_irq = true;
+                self.finish_frame();
+            }
+
+            _ => (),
+        }
+    }
+
+    fn finish_frame(&mut self) {
+        if self.skip_frame {
+            self.skip_frame = false;
+            self.blank_frame();
+        }
+
+        self.frame_number += 1;
+        self.frame_available = true;
+    }
+
+    fn blank_frame(&mut self) {
+        let blank = if self.cgb_mode {
+            self.cgb_colour(0x7FFF)
+        } else {
+            self.dmg_colour(None, 0)
+        };
+        self.frame_buffer.fill(blank);
+        self.shades.fill(0);
+    }
+
+    fn start_oam_scan(&mut self) {
+        self.mode = Mode::OamScan;
+        self.object_count = 0;
+
+        if self.ly == self.wy {
+            self.wy_triggered = true;
+        }
+    }
+
+    fn start_drawing(&mut self) {
+        self.mode = Mode::Drawing;
+        self.fetcher = Fetcher::new();
+        self.bg_fifo.clear();
+        self.obj_fifo.clear();
+        self.lx = 0;
+        self.object_fetch = None;
+
+        // Fine scroll is applied by discarding pixels from the front of the FIFO
+        self.discard_pixels = self.scx % 8;
+    }
+
+    fn step_drawing(&mut self) {
+        if !self.window_drawn && self.window_triggered() {
+            self.window_drawn = true;
+            self.fetcher.start_window();
+            self.bg_fifo.clear();
+
+            // With WX below 7 the window starts partway off the left edge
+            self.discard_pixels = 7u8.saturating_sub(self.wx);
+        }
+
+        if self.object_fetch.is_none() {
+            self.object_fetch = self.pending_object().map(|i| (i, 0));
+        }
+
+        if let Some((index, dots)) = self.object_fetch {
+            // The background fetcher has to finish the tile it is working on
+            // before the object fetch can take over
+            if self.fetcher.step != FetcherStep::Push || self.bg_fifo.is_empty() {
+                self.step_fetcher();
+                return;
+            }
+
+            if dots < 5 {
+                self.object_fetch = Some((index, dots + 1));
+            } else {
+                self.fetch_object(index);
+                self.object_fetch = None;
+            }
+            return;
+        }
+
+        self.step_fetcher();
+        self.output_pixel();
+    }
+
+    fn window_triggered(&self) -> bool {
+        if self.lcdc & 0x20 != 0x20 || !self.wy_triggered {
+            return false;
+        }
+
+        // WX=0-6 start the window at the left edge, WX=166 only shows its
+        // first column and anything higher never reaches the screen
+        match self.wx {
+            0..=7 => self.lx == 0,
+            _ => self.lx + 7 == self.wx,
+        }
+    }
+
+    fn output_pixel(&mut self) {
+        let Some(bg) = self.bg_fifo.pop() else {
+            return;
+        };
+
+        if self.discard_pixels > 0 {
+            self.discard_pixels -= 1;
+            return;
+        }
+
+        let object = self
+            .obj_fifo
+            .pop()
+            .filter(|object| object.colour != 0 && self.object_wins(bg, *object));
+        let index = (self.ly as usize * SCREEN_WIDTH) + self.lx as usize;
+
+        self.frame_buffer[index] = if self.cgb_mode {
+            match object {
+                Some(object) => {
+                    self.cgb_colour(self.obj_colour_ram.colour(object.palette, object.colour))
+                }
+                None => self.cgb_colour(self.bg_colour_ram.colour(bg.palette, bg.colour)),
+            }
+        } else {
+            let (object_palette, palette, id) = match object {
+                Some(object) if object.palette == 1 => (Some(1), self.obj_palette_1, object.colour),
+                Some(object) => (Some(0), self.obj_palette_0, object.colour),
+                None => (None, self.bgp, bg.colour),
+            };
+
+            let shade = (palette >> (id * 2)) & 3;
+            self.shades[index] = shade;
+            self.dmg_colour(object_palette, shade)
+        };
+        self.lx += 1;
+
+        if self.lx as usize == SCREEN_WIDTH {
+            self.mode = Mode::HBlank;
+            self.hblank_started = true;
+        }
+    }
+
+    /// Whether an opaque object pixel is drawn over the background. Objects
+    /// only lose to a background pixel that isn't colour 0, and only when the
+    /// object or (in CGB mode) the BG map attributes ask for it. On the CGB
+    /// clearing LCDC bit 0 puts every object on top.
+    fn object_wins(&self, bg: BackgroundPixel, object: ObjectPixel) -> bool {
+        if self.lcdc & 0x2 != 0x2 {
+            return false;
+        }
+
+        if self.cgb_mode && self.lcdc & 0x1 != 0x1 {
+            return true;
+        }
+
+        bg.colour == 0 || !(object.bg_priority || bg.priority)
+    }
+
+    pub fn lcd_enabled(&self) -> bool {
+        self.lcdc & 0x80 == 0x80
+    }
+
+    fn vram_accessible(&self) -> bool {
+        !self.lcd_enabled() || self.mode != Mode::Drawing
+    }
+
+    fn oam_accessible(&self) -> bool {
+        !self.lcd_enabled() || !matches!(self.mode, Mode::OamScan | Mode::Drawing)
+    }
+
+    pub fn get_byte(&self, addr: u16) -> u8 {
+        match addr {
+            // VRAM
+            0x8000..=0x9FFF => {
+                if self.vram_accessible() {
+                    self.vram[self.vram_bank][(addr - 0x8000) as usize]
+                } else {
+                    0xFF
+                }
+            }
+
+            0xFF40 => self.lcdc,
+            0xFF41 => {
+                let mut ret = 0x80 | (self.stat & 0x78);
+                if self.ly == self.lyc {
+                    ret |= 0x4;
+                }
+
+                if self.lcd_enabled() {
+                    ret |= self.mode as u8;
+                }
+
+                ret
+            }
+            0xFF42 => self.scy,
+            0xFF43 => self.scx,
+            0xFF44 => {
+                if is_gameboy_doctor() {
+                    0x90
+                } else {
+                    self.ly
+                }
+            }
+            0xFF45 => self.lyc,
+            0xFF47 => self.bgp,
+
+            0xFF4A => self.wy,
+            0xFF4B => self.wx,
+
+            0xFF48 => self.obj_palette_0,
+            0xFF49 => self.obj_palette_1,
+
+            // CGB registers read as open bus on the DMG
+            0xFF4F if self.cgb_mode => 0xFE | self.vram_bank as u8,
+            0xFF68 if self.cgb_mode => self.bg_colour_ram.read_index(),
+            0xFF69 if self.cgb_mode => self.read_colour_data(&self.bg_colour_ram),
+            0xFF6A if self.cgb_mode => self.obj_colour_ram.read_index(),
+            0xFF6B if self.cgb_mode => self.read_colour_data(&self.obj_colour_ram),
+            0xFF4F | 0xFF68..=0xFF6B => 0xFF,
+
+            0xFE00..=0xFE9F => {
+                if self.oam_accessible() {
+                    self.voam[(addr - 0xFE00) as usize]
+                } else {
+                    0xFF
+                }
+            }
+
+            _ => {
+                println!("tried to read {:#04x}", addr);
+                todo!()
+            }
+        }
+    }
+
+    pub fn set_byte(&mut self, addr: u16, value: u8) {
+        match addr {
+            // VRAM
+            0x8000..=0x9FFF => {
+                if self.vram_accessible() {
+                    self.vram[self.vram_bank][(addr - 0x8000) as usize] = value
+                }
+            }
+
+            0xFF40 => self.write_lcdc(value),
+            0xFF41 => self.write_stat(value),
+            0xFF42 => self.scy = value,
+            0xFF43 => self.scx = value,
+            0xFF45 => {
+                self.lyc = value;
+                self.update_stat_line();
+            }
+            0xFF47 => self.bgp = value,
+            0xFF48 => self.obj_palette_0 = value,
+            0xFF49 => self.obj_palette_1 = value,
+
+            0xFF4A => self.wy = value,
+            0xFF4B => self.wx = value,
+            0xFF4F if self.cgb_mode => self.vram_bank = (value & 0x1) as usize,
+            0xFF68 if self.cgb_mode => self.bg_colour_ram.write_index(value),
+            0xFF69 if self.cgb_mode => {
+                let accessible = self.vram_accessible();
+                self.bg_colour_ram.write_data(value, accessible);
+            }
+            0xFF6A if self.cgb_mode => self.obj_colour_ram.write_index(value),
+            0xFF6B if self.cgb_mode => {
+                let accessible = self.vram_accessible();
+                self.obj_colour_ram.write_data(value, accessible);
+            }
+            0xFF4F | 0xFF68..=0xFF6B => (),
+
+            0xFE00..=0xFE9F => {
+                if self.oam_accessible() {
+                    self.voam[(addr - 0xFE00) as usize] = value
+                }
+            }
+
+            0xFF7F => (),
+
+            _ => {
+                println!("tried to write {:#04x}", addr);
+                todo!()
+            }
+        }
+    }
+
+    pub fn vram_bank(&self) -> usize {
+        self.vram_bank
+    }
+
+    /// OAM DMA writes go straight to OAM regardless of the PPU mode.
+    pub fn write_oam(&mut self, offset: u8, value: u8) {
+        self.voam[offset as usize] = value;
+    }
+
+    /// VRAM DMA writes go straight to the selected VRAM bank.
+    pub fn write_vram(&mut self, addr: u16, value: u8) {
+        self.vram[self.vram_bank][(addr - 0x8000) as usize] = value;
+    }
+
+    /// Whether the PPU has entered HBlank since this was last called.
+    pub fn take_hblank_started(&mut self) -> bool {
+        std::mem::take(&mut self.hblank_started)
+    }
+
+    /// The shade of every pixel of the last frame.
+    pub fn shades(&self) -> &[u8] {
+        &self.shades
+    }
+
+    /// The tile data of the first 256 background tiles on screen, 20 to a
+    /// row. This is how the SGB receives bulk data: the game puts it on
+    /// screen and the SGB captures the picture.
+    pub fn screen_tile_data(&self) -> Vec<u8> {
+        let tile_map = if self.lcdc & 0x8 == 0x8 {
+            0x1C00
+        } else {
+            0x1800
+        };
+
+        (0..256)
+            .flat_map(|tile| {
+                let tile_index = self.vram[0][tile_map + (tile / 20) * 32 + tile % 20];
+                let address = self.tile_data_address(tile_index);
+                self.vram[0][address..address + 16].iter().copied()
+            })
+            .collect()
+    }
+
+    /// Colour palette data can't be read while the PPU is drawing.
+    fn read_colour_data(&self, colour_ram: &ColourRam) -> u8 {
+        if self.vram_accessible() {
+            colour_ram.read_data()
+        } else {
+            0xFF
+        }
+    }
+
+    pub fn get_and_reset_frame_available(&mut self) -> bool {
+        let result = self.frame_available;
+        self.frame_available = false;
+        result
+    }
+
+    pub fn get_object(&self, tile_index: u8) -> Tile {
+        let start_address = (tile_index as u16) * 16;
+        let mut ret = [[0u8; 8]; 8];
+
+        for i in 0..8 {
+            let byte_a = self.vram[0][(start_address + (2 * i)) as usize];
+            let byte_b = self.vram[0][(start_address + (2 * i) + 1) as usize];
+
+            for j in 0..8 {
+                let bit1 = (byte_a >> (7 - j)) & 1;
+                let bit2 = (byte_b >> (7 - j)) & 1;
+
+                ret[j as usize][i as usize] = (bit2 << 1) | bit1;
+            }
+        }
+
+        ret
+    }
+
+    /// Put the LCD in the state the boot ROM leaves it in: switched on with
+    /// the background palette set, just before the first line is drawn.
+    pub fn skip_boot(&mut self) {
+        self.lcdc = 0x91;
+        self.bgp = 0xFC;
+        self.line = LINES_PER_FRAME - 1;
+        self.ly = 0;
+        self.mode = Mode::VBlank;
+        self.modeclock = POST_BOOT_DOT;
+        self.update_stat_line();
+    }
+
+    pub fn set_colour_correction(&mut self, colour_correction: ColourCorrection) {
+        self.colour_correction = colour_correction;
+        self.colour_table = colour_correction.table();
+    }
+
+    /// Run a DMG game the way a CGB does, with the colours its boot ROM
+    /// picks from the title.
+    pub fn enable_dmg_compatibility(&mut self, header: &CartridgeHeader) {
+        self.dmg_compatibility = true;
+        self.load_compatibility_palettes(title_palettes(header));
+    }
+
+    /// Called when the CGB boot ROM switches to DMG compatibility mode. The
+    /// boot ROM has already set up the colour palettes.
+    pub fn switch_to_dmg_compatibility(&mut self) {
+        self.cgb_mode = false;
+        self.dmg_compatibility = true;
+    }
+
+    /// Called as the boot ROM finishes. A direction and button combination
+    /// held during the logo overrides the colours picked from the title.
+    pub fn select_compatibility_palettes(&mut self, dpad: u8, buttons: u8) {
+        if !self.dmg_compatibility {
+            return;
+        }
+
+        if let Some(palettes) = button_palettes(dpad, buttons) {
+            self.load_compatibility_palettes(palettes);
+        }
+    }
+
+    fn load_compatibility_palettes(&mut self, palettes: CompatibilityPalettes) {
+        self.bg_colour_ram.set_palette(0, palettes.bg);
+        self.obj_colour_ram.set_palette(0, palettes.obj0);
+        self.obj_colour_ram.set_palette(1, palettes.obj1);
+    }
+
+    fn cgb_colour(&self, colour: u16) -> u32 {
+        self.colour_table[colour as usize & 0x7FFF]
+    }
+
+    pub fn dmg_palette(&self) -> &DmgPalette {
+        &self.dmg_palette
+    }
+
+    pub fn set_dmg_palette(&mut self, palette: DmgPalette) {
+        self.dmg_palette = palette;
+    }
+
+    /// Colour of a DMG shade. Object pixels pass which of OBP0/OBP1 they use
+    /// so they can be drawn with their own colours.
+    fn dmg_colour(&self, object_palette: Option<u8>, shade: u8) -> u32 {
+        // DMG games on a CGB use the colour palettes the boot ROM set up
+        if self.dmg_compatibility {
+            let colour = match object_palette {
+                Some(index) => self.obj_colour_ram.colour(index, shade),
+                None => self.bg_colour_ram.colour(0, shade),
+            };
+            return self.cgb_colour(colour);
+        }
+
+        let colours = match object_palette {
+            Some(1) => &self.dmg_palette.obj1,
+            Some(_) => &self.dmg_palette.obj0,
+            None => &self.dmg_palette.bg,
+        };
+        colours[shade as usize]
+    }
+}
+
+// Colour correction and the DMG palette are display settings, so they stay
+// as they are. The frame buffer is redrawn by the next frame.
+impl SaveState for PPU {
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.shades);
+        state.u32(self.frame_number);
+        state.bool(self.hblank_started);
+        state.bool(self.vblank_irq);
+        state.bool(self.stat_irq);
+
+        state.bytes(&self.vram[0]);
+        state.bytes(&self.vram[1]);
+        state.bytes(&self.voam);
+
+        state.bool(self.cgb_mode);
+        state.u8(self.vram_bank as u8);
+        self.bg_colour_ram.save(state);
+        self.obj_colour_ram.save(state);
+        state.bool(self.dmg_compatibility);
+
+        for register in [
+            self.scy,
+            self.scx,
+            self.ly,
+            self.lyc,
+            self.lcdc,
+            self.bgp,
+            self.wy,
+            self.wx,
+            self.obj_palette_0,
+            self.obj_palette_1,
+            self.stat,
+            self.line,
+        ] {
+            state.u8(register);
+        }
+        state.u8(self.mode as u8);
+        state.u32(self.modeclock);
+
+        state.bool(self.stat_line);
+        state.bool(self.starting_up);
+        state.bool(self.skip_frame);
+        state.u32(self.lcd_off_dots);
+
+        state.u8(self.window_line);
+        state.bool(self.wy_triggered);
+        state.bool(self.window_drawn);
+
+        self.fetcher.save(state);
+        self.bg_fifo.save(state);
+        self.obj_fifo.save(state);
+        state.u8(self.lx);
+        state.u8(self.discard_pixels);
+        for object in self.objects.iter() {
+            object.save(state);
+        }
+        state.u8(self.object_count as u8);
+        let (object, dots) = self.object_fetch.unwrap_or((0xFF, 0));
+        state.u8(object as u8);
+        state.u8(dots);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.shades)?;
+        self.shades.iter_mut().for_each(|shade| *shade &= 0x3);
+        self.frame_number = state.u32()?;
+        self.hblank_started = state.bool()?;
+        self.vblank_irq = state.bool()?;
+        self.stat_irq = state.bool()?;
+
+        state.bytes(&mut self.vram[0])?;
+        state.bytes(&mut self.vram[1])?;
+        state.bytes(&mut self.voam)?;
+
+        self.cgb_mode = state.bool()?;
+        self.vram_bank = state.u8()? as usize & 0x1;
+        self.bg_colour_ram.load(state)?;
+        self.obj_colour_ram.load(state)?;
+        self.dmg_compatibility = state.bool()?;
+
+        for register in [
+            &mut self.scy,
+            &mut self.scx,
+            &mut self.ly,
+            &mut self.lyc,
+            &mut self.lcdc,
+            &mut self.bgp,
+            &mut self.wy,
+            &mut self.wx,
+            &mut self.obj_palette_0,
+            &mut self.obj_palette_1,
+            &mut self.stat,
+            &mut self.line,
+        ] {
+            *register = state.u8()?;
+        }
+        self.mode = match state.u8()? {
+            0 => Mode::HBlank,
+            1 => Mode::VBlank,
+            2 => Mode::OamScan,
+            _ => Mode::Drawing,
+        };
+        self.modeclock = state.u32()?;
+
+        self.stat_line = state.bool()?;
+        self.starting_up = state.bool()?;
+        self.skip_frame = state.bool()?;
+        self.lcd_off_dots = state.u32()?;
+
+        self.window_line = state.u8()?;
+        self.wy_triggered = state.bool()?;
+        self.window_drawn = state.bool()?;
+
+        self.fetcher.load(state)?;
+        self.bg_fifo.load(state)?;
+        self.obj_fifo.load(state)?;
+        self.lx = state.u8()?;
+        self.discard_pixels = state.u8()?;
+        for object in self.objects.iter_mut() {
+            object.load(state)?;
+        }
+        self.object_count = (state.u8()? as usize).min(MAX_OBJECTS_PER_LINE);
+        self.object_fetch = match (state.u8()? as usize, state.u8()?) {
+            (object, dots) if object < MAX_OBJECTS_PER_LINE => Some((object, dots)),
+            _ => None,
+        };
+
+        // Nothing from before the state was loaded is waiting to be shown
+        self.frame_available = false;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for PPU {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PPU")
+            .field("scy", &self.scy)
+            .field("scx", &self.scx)
+            .field("ly", &self.ly)
+            .field("lyc", &self.lyc)
+            .field("wy", &self.wy)
+            .field("wx", &self.wx)
+            .field("lcdc", &self.lcdc)
+            .field("bgp", &self.bgp)
+            .field("mode", &self.mode)
+            .field("stat", &self.stat)
+            .field("model", &self.model)
+            .field("cgb_mode", &self.cgb_mode)
+            .field("vram_bank", &self.vram_bank)
+            .field("modeclock", &self.modeclock)
+            .finish()
+    }
+}
@@ -0,0 +1,172 @@
+use super::{fifo::ObjectPixel, MAX_OBJECTS_PER_LINE, PPU};
+use crate::state::{SaveState, StateReader, StateWriter};
+
+/// An object selected during OAM scan for the current line.
+#[derive(Clone, Copy, Default)]
+pub(super) struct Object {
+    pub index: u8,
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub flags: u8,
+    pub fetched: bool,
+}
+
+impl PPU {
+    fn object_height(&self) -> u8 {
+        if self.lcdc & 0x4 == 0x4 {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// OAM scan checks one of the 40 entries every two dots of mode 2. Only
+    /// the first ten objects that overlap the line are kept, whether or not
+    /// they end up visible.
+    pub(super) fn scan_oam_entry(&mut self, index: usize) {
+        if self.object_count >= MAX_OBJECTS_PER_LINE {
+            return;
+        }
+
+        let start_position = index * 4;
+        let object_y = self.voam[start_position];
+        let object_line = self.ly.wrapping_sub(object_y).wrapping_add(16);
+        if object_line >= self.object_height() {
+            return;
+        }
+
+        self.objects[self.object_count] = Object {
+            index: index as u8,
+            y: object_y,
+            x: self.voam[start_position + 1],
+            tile: self.voam[start_position + 2],
+            flags: self.voam[start_position + 3],
+            fetched: false,
+        };
+        self.object_count += 1;
+    }
+
+    /// The next object whose left edge has been reached by the pixel output.
+    /// When several are waiting the leftmost is fetched first, then the one
+    /// earliest in OAM, which gives the DMG its X-coordinate priority. CGB
+    /// priority is settled when the pixels are mixed instead.
+    pub(super) fn pending_object(&self) -> Option<usize> {
+        if self.lcdc & 0x2 != 0x2 {
+            return None;
+        }
+
+        (0..self.object_count)
+            .filter(|&i| !self.objects[i].fetched && self.objects[i].x <= self.lx + 8)
+            .min_by_key(|&i| self.objects[i].x)
+    }
+
+    /// Fetch one row of an object and mix it into the object FIFO. On the DMG
+    /// pixels already in the FIFO belong to higher priority objects, so they
+    /// are only replaced where they are transparent. In CGB mode the object
+    /// earliest in OAM wins instead.
+    pub(super) fn fetch_object(&mut self, index: usize) {
+        let object = self.objects[index];
+        self.objects[index].fetched = true;
+
+        let height = self.object_height();
+        let x_flip = object.flags & 0x20 == 0x20;
+        let y_flip = object.flags & 0x40 == 0x40;
+
+        let object_line = self.ly.wrapping_sub(object.y).wrapping_add(16) % height;
+        let tile_line = if y_flip {
+            height - 1 - object_line
+        } else {
+            object_line
+        };
+
+        // Tall objects ignore the lowest bit of the tile index
+        let tile = if height == 16 {
+            object.tile & 0xFE
+        } else {
+            object.tile
+        };
+
+        // CGB objects can take their tile from either VRAM bank and use one of
+        // eight colour palettes
+        let (bank, palette) = if self.cgb_mode {
+            (((object.flags >> 3) & 1) as usize, object.flags & 0x7)
+        } else {
+            (0, (object.flags >> 4) & 1)
+        };
+
+        let tile_address = (tile as usize * 16) + (tile_line as usize * 2);
+        let byte_a = self.vram[bank][tile_address];
+        let byte_b = self.vram[bank][tile_address + 1];
+
+        // Objects partially off the left edge of the screen lose their first
+        // few columns
+        let hidden_columns = (self.lx + 8 - object.x) as usize;
+
+        for column in hidden_columns..8 {
+            let bit = if x_flip { column } else { 7 - column };
+            let pixel = ObjectPixel {
+                colour: (((byte_b >> bit) & 1) << 1) | ((byte_a >> bit) & 1),
+                palette,
+                bg_priority: object.flags & 0x80 == 0x80,
+                oam_index: object.index,
+            };
+
+            let slot = column - hidden_columns;
+            if slot >= self.obj_fifo.len() {
+                self.obj_fifo.push(pixel);
+                continue;
+            }
+
+            let existing = self.obj_fifo.get(slot);
+            let replace = existing.colour == 0
+                || (self.cgb_mode && pixel.colour != 0 && pixel.oam_index < existing.oam_index);
+            if replace {
+                self.obj_fifo.set(slot, pixel);
+            }
+        }
+    }
+}
+
+impl SaveState for Object {
+    fn save(&self, state: &mut StateWriter) {
+        state.u8(self.index);
+        state.u8(self.y);
+        state.u8(self.x);
+        state.u8(self.tile);
+        state.u8(self.flags);
+        state.bool(self.fetched);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.index = state.u8()?;
+        self.y = state.u8()?;
+        self.x = state.u8()?;
+        self.tile = state.u8()?;
+        self.flags = state.u8()?;
+        self.fetched = state.bool()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::cartridge::crc32;
+    use crate::gameboy::model::Model;
+    use crate::gameboy::{Boot, GameBoy};
+
+    #[test]
+    fn dmg_acid2() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms/test_roms/dmg-acid2.gb");
+        let mut gameboy = GameBoy::new(std::fs::read(path).unwrap(), Model::Dmg, Boot::Skip);
+        while gameboy.mmu.ppu.frame_number < 60 {
+            gameboy.step();
+        }
+
+        // The shades of the reference image, which the test draws once and
+        // then leaves on screen
+        assert_eq!(crc32(gameboy.mmu.ppu.shades()), 0x9F51_DD25);
+    }
+}
@@ -0,0 +1,176 @@
+use super::{Mode, DOTS_PER_FRAME, PPU};
+
+impl PPU {
+    /// Recalculate the shared STAT interrupt line. Every enabled source is
+    /// ORed together, so a new interrupt is only requested on a rising edge -
+    /// a source becoming true while another is already holding the line high
+    /// is blocked.
+    pub(super) fn update_stat_line(&mut self) {
+        let stat_line = self.stat_sources(self.stat);
+        if stat_line && !self.stat_line {
+            self.stat_irq = true;
+        }
+
+        self.stat_line = stat_line;
+    }
+
+    fn stat_sources(&self, stat: u8) -> bool {
+        if !self.lcd_enabled() {
+            return false;
+        }
+
+        // The mode 2 source also fires at the very start of VBlank
+        let oam_scan = self.mode == Mode::OamScan || (self.line == 144 && self.modeclock == 0);
+
+        (stat & 0x08 == 0x08 && self.mode == Mode::HBlank)
+            || (stat & 0x10 == 0x10 && self.mode == Mode::VBlank)
+            || (stat & 0x20 == 0x20 && oam_scan)
+            || (stat & 0x40 == 0x40 && self.ly == self.lyc)
+    }
+
+    pub(super) fn write_stat(&mut self, value: u8) {
+        // On the DMG writing to STAT briefly enables every source, which can
+        // raise a spurious interrupt during HBlank, VBlank or LY==LYC. Colour
+        // hardware fixed this, even for DMG games.
+        if !self.model.is_cgb() && self.stat_sources(0x78) && !self.stat_line {
+            self.stat_irq = true;
+        }
+
+        self.stat = value & 0x78;
+        self.stat_line = self.stat_sources(self.stat);
+    }
+
+    pub(super) fn write_lcdc(&mut self, value: u8) {
+        let was_enabled = self.lcd_enabled();
+        self.lcdc = value;
+
+        match (was_enabled, self.lcd_enabled()) {
+            (true, false) => self.disable_lcd(),
+            (false, true) => self.enable_lcd(),
+            _ => (),
+        }
+    }
+
+    fn disable_lcd(&mut self) {
+        self.ly = 0;
+        self.line = 0;
+        self.modeclock = 0;
+        self.mode = Mode::HBlank;
+        self.stat_line = false;
+        self.lcd_off_dots = 0;
+        self.blank_frame();
+    }
+
+    fn enable_lcd(&mut self) {
+        self.ly = 0;
+        self.line = 0;
+        self.modeclock = 0;
+        self.mode = Mode::HBlank;
+        self.starting_up = true;
+        self.skip_frame = true;
+        self.window_line = 0;
+        self.window_drawn = false;
+        self.wy_triggered = self.ly == self.wy;
+        self.update_stat_line();
+    }
+
+    /// The PPU is idle while the LCD is off, but frames are still signalled at
+    /// the usual rate so the screen shows blank and the emulator keeps pace.
+    pub(super) fn tick_lcd_off(&mut self) {
+        self.lcd_off_dots += 1;
+        if self.lcd_off_dots == DOTS_PER_FRAME {
+            self.lcd_off_dots = 0;
+            self.frame_number += 1;
+            self.frame_available = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::{Mode, DOTS_PER_FRAME, PPU};
+    use crate::gameboy::model::Model;
+
+    fn ppu(model: Model) -> PPU {
+        let mut ppu = PPU::new(model, false);
+        ppu.skip_boot();
+        ppu
+    }
+
+    // Runs the PPU a dot at a time until `done`, returning whether a STAT
+    // interrupt was requested on the way
+    fn run_until(ppu: &mut PPU, done: impl Fn(&PPU) -> bool) -> bool {
+        let mut irq = false;
+        for _ in 0..DOTS_PER_FRAME {
+            if done(ppu) {
+                return irq;
+            }
+            ppu.do_cycle(1);
+            irq |= std::mem::take(&mut ppu.stat_irq);
+        }
+        panic!("PPU never got there");
+    }
+
+    #[test]
+    fn every_hblank_raises_an_interrupt() {
+        let mut ppu = ppu(Model::Dmg);
+        ppu.set_byte(0xFF41, 0x08);
+
+        for line in 0..3 {
+            let irq = run_until(&mut ppu, |ppu| ppu.ly == line && ppu.mode == Mode::HBlank);
+            assert!(irq, "no interrupt on line {line}");
+            run_until(&mut ppu, |ppu| ppu.mode != Mode::HBlank);
+        }
+    }
+
+    #[test]
+    fn source_rising_while_the_line_is_high_is_blocked() {
+        let mut ppu = ppu(Model::Dmg);
+        ppu.set_byte(0xFF45, 1);
+        ppu.set_byte(0xFF41, 0x48);
+
+        run_until(&mut ppu, |ppu| ppu.ly == 0 && ppu.mode == Mode::HBlank);
+
+        // HBlank holds the line high right up until LY==LYC takes over, so
+        // starting line 1 doesn't request another interrupt
+        let irq = run_until(&mut ppu, |ppu| ppu.ly == 1 && ppu.mode == Mode::Drawing);
+        assert!(!irq);
+
+        // Nor does line 1's HBlank, with LY==LYC still holding it high
+        let irq = run_until(&mut ppu, |ppu| ppu.ly == 1 && ppu.mode == Mode::HBlank);
+        assert!(!irq);
+
+        // The line drops once LY moves past LYC, then HBlank raises it again
+        let irq = run_until(&mut ppu, |ppu| ppu.ly == 2 && ppu.mode == Mode::HBlank);
+        assert!(irq);
+    }
+
+    #[test]
+    fn source_rising_after_the_line_drops_raises_an_interrupt() {
+        let mut ppu = ppu(Model::Dmg);
+        ppu.set_byte(0xFF45, 1);
+        ppu.set_byte(0xFF41, 0x40);
+
+        // LY==LYC on its own rises at the start of line 1
+        let irq = run_until(&mut ppu, |ppu| ppu.ly == 1);
+        assert!(irq);
+    }
+
+    #[test]
+    fn dmg_stat_write_raises_a_spurious_interrupt() {
+        let mut ppu = ppu(Model::Dmg);
+        run_until(&mut ppu, |ppu| ppu.mode == Mode::HBlank);
+
+        ppu.set_byte(0xFF41, 0x00);
+        assert!(ppu.stat_irq);
+    }
+
+    #[test]
+    fn cgb_stat_write_does_not_raise_an_interrupt() {
+        let mut ppu = ppu(Model::Cgb);
+        run_until(&mut ppu, |ppu| ppu.mode == Mode::HBlank);
+
+        ppu.set_byte(0xFF41, 0x00);
+        assert!(!ppu.stat_irq);
+    }
+}
@@ -0,0 +1,184 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+pub(super) const COLUMNS: usize = 20;
+pub(super) const ROWS: usize = 18;
+
+const ATTRIBUTE_FILE_LENGTH: usize = COLUMNS * ROWS / 4;
+const ATTRIBUTE_FILES: usize = 45;
+
+/// Which of the four SGB palettes is used for each 8x8 cell of the screen.
+#[derive(Clone)]
+pub(super) struct Attributes {
+    cells: [u8; COLUMNS * ROWS],
+
+    // Attribute files sent with ATTR_TRN. Each one packs a palette for every
+    // cell of the screen into two bits, most significant bits first.
+    files: Vec<[u8; ATTRIBUTE_FILE_LENGTH]>,
+}
+
+impl Attributes {
+    pub fn new() -> Attributes {
+        Attributes {
+            cells: [0; COLUMNS * ROWS],
+            files: vec![[0; ATTRIBUTE_FILE_LENGTH]; ATTRIBUTE_FILES],
+        }
+    }
+
+    /// The palette used at the given screen pixel.
+    pub fn palette_at(&self, x: usize, y: usize) -> usize {
+        self.cells[(y / 8) * COLUMNS + x / 8] as usize
+    }
+
+    fn set(&mut self, x: usize, y: usize, palette: u8) {
+        if x < COLUMNS && y < ROWS {
+            self.cells[y * COLUMNS + x] = palette & 0x3;
+        }
+    }
+
+    /// ATTR_BLK. Each data set colours the inside, outline and outside of a
+    /// rectangle. Colouring only the inside or only the outside also colours
+    /// the outline.
+    pub fn blocks(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+
+        for block in data[2..].chunks_exact(6).take(count) {
+            let control = block[0] & 0x7;
+            let palettes = block[1];
+            let (x1, y1) = (block[2] as usize & 0x1F, block[3] as usize & 0x1F);
+            let (x2, y2) = (block[4] as usize & 0x1F, block[5] as usize & 0x1F);
+
+            let inside = palettes & 0x3;
+            let outline = match control {
+                0x1 => Some(inside),
+                0x4 => Some((palettes >> 4) & 0x3),
+                _ if control & 0x2 == 0x2 => Some((palettes >> 2) & 0x3),
+                _ => None,
+            };
+            let outside = (palettes >> 4) & 0x3;
+
+            for y in 0..ROWS {
+                for x in 0..COLUMNS {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+
+                    if on_edge {
+                        if let Some(palette) = outline {
+                            self.set(x, y, palette);
+                        }
+                    } else if within && control & 0x1 == 0x1 {
+                        self.set(x, y, inside);
+                    } else if !within && control & 0x4 == 0x4 {
+                        self.set(x, y, outside);
+                    }
+                }
+            }
+        }
+    }
+
+    /// ATTR_LIN. Colours whole rows or columns.
+    pub fn lines(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+
+        for &line in data[2..].iter().take(count) {
+            let index = line as usize & 0x1F;
+            let palette = (line >> 5) & 0x3;
+
+            if line & 0x80 == 0x80 {
+                (0..COLUMNS).for_each(|x| self.set(x, index, palette));
+            } else {
+                (0..ROWS).for_each(|y| self.set(index, y, palette));
+            }
+        }
+    }
+
+    /// ATTR_DIV. Splits the screen in two either side of a row or column.
+    pub fn divide(&mut self, data: &[u8]) {
+        let control = data[1];
+        let split = data[2] as usize & 0x1F;
+        let after = control & 0x3;
+        let before = (control >> 2) & 0x3;
+        let on_line = (control >> 4) & 0x3;
+        let horizontal = control & 0x40 == 0x40;
+
+        for y in 0..ROWS {
+            for x in 0..COLUMNS {
+                let position = if horizontal { y } else { x };
+                let palette = match position.cmp(&split) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => on_line,
+                    std::cmp::Ordering::Greater => after,
+                };
+                self.set(x, y, palette);
+            }
+        }
+    }
+
+    /// ATTR_CHR. Sets cells one at a time, two bits each, running either
+    /// across the screen or down it from a starting cell.
+    pub fn characters(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = u16::from_le_bytes([data[3], data[4]]) as usize;
+        let vertical = data[5] & 0x1 == 0x1;
+
+        for i in 0..count.min(COLUMNS * ROWS) {
+            let Some(&byte) = data.get(6 + i / 4) else {
+                break;
+            };
+            self.set(x, y, byte >> (6 - (i % 4) * 2));
+
+            if vertical {
+                y += 1;
+                if y == ROWS {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == COLUMNS {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+
+    /// ATTR_TRN. Stores attribute files to be applied later.
+    pub fn load_files(&mut self, data: &[u8]) {
+        for (file, chunk) in self
+            .files
+            .iter_mut()
+            .zip(data.chunks_exact(ATTRIBUTE_FILE_LENGTH))
+        {
+            file.copy_from_slice(chunk);
+        }
+    }
+
+    /// ATTR_SET and PAL_SET. Colour the screen with a stored attribute file.
+    pub fn apply_file(&mut self, index: usize) {
+        let Some(file) = self.files.get(index) else {
+            return;
+        };
+
+        for (i, cell) in self.cells.iter_mut().enumerate() {
+            *cell = (file[i / 4] >> (6 - (i % 4) * 2)) & 0x3;
+        }
+    }
+}
+
+impl SaveState for Attributes {
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.cells);
+        for file in self.files.iter() {
+            state.bytes(file);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.cells)?;
+        self.cells.iter_mut().for_each(|palette| *palette &= 0x3);
+        for file in self.files.iter_mut() {
+            state.bytes(file)?;
+        }
+        Ok(())
+    }
+}
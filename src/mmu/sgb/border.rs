@@ -0,0 +1,98 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+const TILE_LENGTH: usize = 32;
+const TILES: usize = 256;
+const MAP_WIDTH: usize = 32;
+const MAP_HEIGHT: usize = 28;
+const PALETTES: usize = 4;
+const COLOURS_PER_PALETTE: usize = 16;
+
+// PCT_TRN sends the tile map first and the border palettes 0x800 bytes in
+const PALETTE_OFFSET: usize = 0x800;
+
+/// The picture frame drawn around the game. Made of SNES style 4bpp tiles,
+/// coloured by SGB palettes 4-7.
+#[derive(Clone)]
+pub(super) struct Border {
+    tiles: Vec<u8>,
+    map: [u16; MAP_WIDTH * MAP_HEIGHT],
+    palettes: [[u16; COLOURS_PER_PALETTE]; PALETTES],
+}
+
+impl Border {
+    pub fn new() -> Border {
+        Border {
+            tiles: vec![0; TILE_LENGTH * TILES],
+            map: [0; MAP_WIDTH * MAP_HEIGHT],
+            palettes: [[0; COLOURS_PER_PALETTE]; PALETTES],
+        }
+    }
+
+    /// CHR_TRN. Each transfer holds half of the tiles.
+    pub fn load_tiles(&mut self, upper_half: bool, data: &[u8]) {
+        let start = if upper_half { data.len() } else { 0 };
+        self.tiles[start..start + data.len()].copy_from_slice(data);
+    }
+
+    /// PCT_TRN. The tile map followed by the border's palettes.
+    pub fn load_map(&mut self, data: &[u8]) {
+        for (entry, bytes) in self.map.iter_mut().zip(data.chunks_exact(2)) {
+            *entry = u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+
+        let colours = data[PALETTE_OFFSET..].chunks_exact(2);
+        for (i, bytes) in colours.take(PALETTES * COLOURS_PER_PALETTE).enumerate() {
+            self.palettes[i / COLOURS_PER_PALETTE][i % COLOURS_PER_PALETTE] =
+                u16::from_le_bytes([bytes[0], bytes[1]]);
+        }
+    }
+
+    /// The colour of the border at the given pixel, or None where it is
+    /// transparent.
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u16> {
+        let entry = self.map[(y / 8) * MAP_WIDTH + x / 8];
+        let tile = (entry & 0xFF) as usize;
+        let palette = ((entry >> 10) & 0x3) as usize;
+        let x_flip = entry & 0x4000 == 0x4000;
+        let y_flip = entry & 0x8000 == 0x8000;
+
+        let row = if y_flip { 7 - y % 8 } else { y % 8 };
+        let bit = if x_flip { x % 8 } else { 7 - x % 8 };
+
+        // Bitplanes 0 and 1 are interleaved in the first 16 bytes of a tile,
+        // bitplanes 2 and 3 in the last 16
+        let start = tile * TILE_LENGTH + row * 2;
+        let colour = [0, 1, 16, 17]
+            .iter()
+            .enumerate()
+            .fold(0, |colour, (plane, offset)| {
+                colour | (((self.tiles[start + offset] >> bit) & 1) << plane)
+            });
+
+        match colour {
+            0 => None,
+            _ => Some(self.palettes[palette][colour as usize]),
+        }
+    }
+}
+
+impl SaveState for Border {
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.tiles);
+        for entry in self.map.iter().chain(self.palettes.iter().flatten()) {
+            state.u16(*entry);
+        }
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.tiles)?;
+        for entry in self
+            .map
+            .iter_mut()
+            .chain(self.palettes.iter_mut().flatten())
+        {
+            *entry = state.u16()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+mod attributes;
+mod border;
+mod packet;
+
+use super::ppu::colour_correction::ColourCorrection;
+use super::ppu::{PPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+use crate::state::{SaveState, StateReader, StateWriter};
+use attributes::Attributes;
+use border::Border;
+use packet::{PacketReceiver, PACKET_LENGTH};
+
+pub const SGB_SCREEN_WIDTH: usize = 256;
+pub const SGB_SCREEN_HEIGHT: usize = 224;
+
+// Where the game's picture sits inside the border
+const GAME_X: usize = 48;
+const GAME_Y: usize = 40;
+
+const SYSTEM_PALETTES: usize = 512;
+
+// The beige to brown palette the SGB starts up with
+const DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+
+/// What MASK_EN shows in place of the game while it redraws the screen.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Mask {
+    None,
+    Freeze,
+    Black,
+    Colour0,
+}
+
+/// Super Game Boy. Games talk to it with packets sent through the joypad
+/// register, which set up the colours of the screen, the border drawn around
+/// it and multiplayer. Bulk data is sent by putting it on screen and asking
+/// the SGB to capture it.
+#[derive(Clone)]
+pub struct Sgb {
+    receiver: PacketReceiver,
+
+    // Packets received so far for a command that spans several
+    command: Vec<u8>,
+
+    palettes: [[u16; 4]; 4],
+    system_palettes: Vec<[u16; 4]>,
+    attributes: Attributes,
+    border: Border,
+    mask: Mask,
+
+    frame_buffer: Vec<u32>,
+
+    // The SGB is plugged into a TV, so its colours are shown as they are
+    colour_table: Vec<u32>,
+}
+
+impl Default for Sgb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        Sgb {
+            receiver: PacketReceiver::new(),
+            command: Vec::with_capacity(PACKET_LENGTH * 7),
+
+            palettes: [DEFAULT_PALETTE; 4],
+            system_palettes: vec![[0; 4]; SYSTEM_PALETTES],
+            attributes: Attributes::new(),
+            border: Border::new(),
+            mask: Mask::None,
+
+            frame_buffer: vec![0xFF000000; SGB_SCREEN_WIDTH * SGB_SCREEN_HEIGHT],
+            colour_table: ColourCorrection::Raw.table(),
+        }
+    }
+
+    /// Called on every write to the joypad register. Returns the number of
+    /// joypads to read from when the game sends MLT_REQ.
+    pub fn write_joypad(&mut self, value: u8, ppu: &PPU) -> Option<usize> {
+        let packet = self.receiver.write(value)?;
+        self.command.extend_from_slice(&packet);
+
+        // The first packet gives the command and how many packets it takes
+        let packets = (self.command[0] & 0x7).max(1) as usize;
+        if self.command.len() < packets * PACKET_LENGTH {
+            return None;
+        }
+
+        let command = std::mem::take(&mut self.command);
+        self.run_command(&command, ppu)
+    }
+
+    fn run_command(&mut self, data: &[u8], ppu: &PPU) -> Option<usize> {
+        match data[0] >> 3 {
+            // PAL01, PAL23, PAL03 and PAL12
+            0x00 => self.set_palettes(0, 1, data),
+            0x01 => self.set_palettes(2, 3, data),
+            0x02 => self.set_palettes(0, 3, data),
+            0x03 => self.set_palettes(1, 2, data),
+
+            0x04 => self.attributes.blocks(data),
+            0x05 => self.attributes.lines(data),
+            0x06 => self.attributes.divide(data),
+            0x07 => self.attributes.characters(data),
+
+            0x0A => self.set_system_palettes(data),
+            0x0B => self.load_system_palettes(&ppu.screen_tile_data()),
+
+            // MLT_REQ
+            0x11 => {
+                return Some(match data[1] & 0x3 {
+                    0x1 => 2,
+                    0x3 => 4,
+                    _ => 1,
+                })
+            }
+
+            0x13 => self
+                .border
+                .load_tiles(data[1] & 0x1 == 0x1, &ppu.screen_tile_data()),
+            0x14 => self.border.load_map(&ppu.screen_tile_data()),
+
+            0x15 => self.attributes.load_files(&ppu.screen_tile_data()),
+            0x16 => {
+                self.attributes.apply_file(data[1] as usize & 0x3F);
+                if data[1] & 0x40 == 0x40 {
+                    self.mask = Mask::None;
+                }
+            }
+
+            0x17 => {
+                self.mask = match data[1] & 0x3 {
+                    0x0 => Mask::None,
+                    0x1 => Mask::Freeze,
+                    0x2 => Mask::Black,
+                    _ => Mask::Colour0,
+                }
+            }
+
+            // Sound, SNES code uploads and the like have nothing to show
+            _ => (),
+        }
+
+        None
+    }
+
+    /// PAL01-PAL23. Colour 0 is shared between all four palettes, so setting
+    /// it for one sets it for all of them.
+    fn set_palettes(&mut self, first: usize, second: usize, data: &[u8]) {
+        let colours: Vec<u16> = data[1..15]
+            .chunks_exact(2)
+            .map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]]))
+            .collect();
+
+        self.palettes[first][1..].copy_from_slice(&colours[1..4]);
+        self.palettes[second][1..].copy_from_slice(&colours[4..7]);
+        self.set_shared_colour(colours[0]);
+    }
+
+    fn set_shared_colour(&mut self, colour: u16) {
+        for palette in self.palettes.iter_mut() {
+            palette[0] = colour;
+        }
+    }
+
+    /// PAL_SET. Pick all four palettes from the ones sent with PAL_TRN.
+    fn set_system_palettes(&mut self, data: &[u8]) {
+        for (palette, bytes) in self.palettes.iter_mut().zip(data[1..9].chunks_exact(2)) {
+            let index = u16::from_le_bytes([bytes[0], bytes[1]]) as usize & 0x1FF;
+            *palette = self.system_palettes[index];
+        }
+        self.set_shared_colour(self.palettes[0][0]);
+
+        let flags = data[9];
+        if flags & 0x80 == 0x80 {
+            self.attributes.apply_file(flags as usize & 0x3F);
+        }
+        if flags & 0x40 == 0x40 {
+            self.mask = Mask::None;
+        }
+    }
+
+    /// PAL_TRN
+    fn load_system_palettes(&mut self, data: &[u8]) {
+        let colours = data
+            .chunks_exact(2)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]));
+        for (i, colour) in colours.enumerate().take(SYSTEM_PALETTES * 4) {
+            self.system_palettes[i / 4][i % 4] = colour;
+        }
+    }
+
+    /// Draw the border with the game's picture inside it. `shades` holds the
+    /// shade of every pixel of the game's last frame.
+    pub fn render(&mut self, shades: &[u8]) -> &[u32] {
+        let backdrop = self.colour(self.palettes[0][0]);
+
+        for y in 0..SGB_SCREEN_HEIGHT {
+            for x in 0..SGB_SCREEN_WIDTH {
+                let in_game = (GAME_X..GAME_X + SCREEN_WIDTH).contains(&x)
+                    && (GAME_Y..GAME_Y + SCREEN_HEIGHT).contains(&y);
+
+                let colour = if in_game {
+                    let (game_x, game_y) = (x - GAME_X, y - GAME_Y);
+                    match self.mask {
+                        Mask::Freeze => continue,
+                        Mask::Black => 0xFF000000,
+                        Mask::Colour0 => backdrop,
+                        Mask::None => {
+                            let palette = self.attributes.palette_at(game_x, game_y);
+                            let shade = shades[game_y * SCREEN_WIDTH + game_x] as usize;
+                            self.colour(self.palettes[palette][shade])
+                        }
+                    }
+                } else {
+                    self.border
+                        .pixel(x, y)
+                        .map_or(backdrop, |colour| self.colour(colour))
+                };
+
+                self.frame_buffer[y * SGB_SCREEN_WIDTH + x] = colour;
+            }
+        }
+
+        &self.frame_buffer
+    }
+
+    fn colour(&self, colour: u16) -> u32 {
+        self.colour_table[colour as usize & 0x7FFF]
+    }
+}
+
+impl SaveState for Sgb {
+    fn save(&self, state: &mut StateWriter) {
+        self.receiver.save(state);
+        state.u8(self.command.len() as u8);
+        state.bytes(&self.command);
+
+        let palettes = self.palettes.iter().chain(self.system_palettes.iter());
+        for colour in palettes.flatten() {
+            state.u16(*colour);
+        }
+
+        self.attributes.save(state);
+        self.border.save(state);
+        state.u8(self.mask as u8);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.receiver.load(state)?;
+        self.command = vec![0; state.u8()? as usize];
+        state.bytes(&mut self.command)?;
+
+        let palettes = self
+            .palettes
+            .iter_mut()
+            .chain(self.system_palettes.iter_mut());
+        for colour in palettes.flatten() {
+            *colour = state.u16()?;
+        }
+
+        self.attributes.load(state)?;
+        self.border.load(state)?;
+        self.mask = match state.u8()? {
+            1 => Mask::Freeze,
+            2 => Mask::Black,
+            3 => Mask::Colour0,
+            _ => Mask::None,
+        };
+        Ok(())
+    }
+}
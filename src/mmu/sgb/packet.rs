@@ -0,0 +1,80 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
+pub(super) const PACKET_LENGTH: usize = 16;
+
+/// Reassembles the 16 byte packets a game sends to the SGB by pulsing the
+/// joypad select lines. Pulling both lines low starts a packet, then each bit
+/// is sent by pulling P14 low for a 0 or P15 low for a 1, least significant
+/// bit first. A final 0 bit ends the packet.
+#[derive(Clone)]
+pub(super) struct PacketReceiver {
+    data: [u8; PACKET_LENGTH],
+
+    // Bit being received, or None when waiting for a reset pulse
+    bit: Option<usize>,
+    lines: u8,
+}
+
+impl PacketReceiver {
+    pub fn new() -> PacketReceiver {
+        PacketReceiver {
+            data: [0; PACKET_LENGTH],
+            bit: None,
+            lines: 0x30,
+        }
+    }
+
+    /// Feed a write to the joypad register. Returns the packet once its stop
+    /// bit has been received.
+    pub fn write(&mut self, value: u8) -> Option<[u8; PACKET_LENGTH]> {
+        let lines = value & 0x30;
+        if lines == self.lines {
+            return None;
+        }
+        self.lines = lines;
+
+        match (lines, self.bit) {
+            (0x00, _) => {
+                self.data = [0; PACKET_LENGTH];
+                self.bit = Some(0);
+            }
+
+            // Pulses outside of a packet are ordinary joypad reads
+            (0x30, _) | (_, None) => (),
+
+            (_, Some(bit)) if bit == PACKET_LENGTH * 8 => {
+                self.bit = None;
+                if lines == 0x20 {
+                    return Some(self.data);
+                }
+            }
+
+            (_, Some(bit)) => {
+                if lines == 0x10 {
+                    self.data[bit / 8] |= 1 << (bit % 8);
+                }
+                self.bit = Some(bit + 1);
+            }
+        }
+
+        None
+    }
+}
+
+impl SaveState for PacketReceiver {
+    fn save(&self, state: &mut StateWriter) {
+        state.bytes(&self.data);
+        state.u8(self.bit.map_or(0xFF, |bit| bit as u8));
+        state.u8(self.lines);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        state.bytes(&mut self.data)?;
+        self.bit = match state.u8()? {
+            0xFF => None,
+            bit => Some((bit as usize).min(PACKET_LENGTH * 8)),
+        };
+        self.lines = state.u8()?;
+        Ok(())
+    }
+}
@@ -1,13 +1,16 @@
+use crate::state::{SaveState, StateReader, StateWriter};
+
 pub struct Timer {
     pub timer_irq: bool,
     pub enabled: bool,
 
-    cycles_since_div: u8,
-    cycles_since_tima: u32,
-    div: u8,
+    // The 16-bit system counter, incremented every T-cycle. DIV is its upper
+    // byte and TIMA ticks whenever the counter bit picked by TAC falls from
+    // 1 to 0.
+    counter: u16,
     tima: u8,
     tma: u8,
-    step: u32,
+    bit: u16,
 }
 
 impl Default for Timer {
@@ -20,51 +23,59 @@ impl Timer {
     pub fn new() -> Timer {
         Timer {
             timer_irq: false,
-            div: 0,
-            cycles_since_div: 0,
-            cycles_since_tima: 0,
+            counter: 0,
             tima: 0,
             tma: 0,
 
-            // tca
-            step: 256,
+            // Bit 9 of the counter gives 4096 Hz
+            bit: 1 << 9,
             enabled: false,
         }
     }
 
+    /// Set the system counter, the way it is left at the end of the boot ROM.
+    pub fn set_counter(&mut self, counter: u16) {
+        self.counter = counter;
+    }
+
     pub fn do_cycles(&mut self, n: u8) {
-        if 0xff - self.cycles_since_div >= n {
-            self.div = self.div.wrapping_add(1);
+        for _ in 0..n {
+            let signal = self.signal();
+            self.counter = self.counter.wrapping_add(1);
+            self.tick_if_fallen(signal);
         }
+    }
 
-        self.cycles_since_div = self.cycles_since_div.wrapping_add(n);
-
-        if self.enabled {
-            self.cycles_since_tima += n as u32;
+    /// The input to TIMA's falling edge detector.
+    fn signal(&self) -> bool {
+        self.enabled && self.counter & self.bit != 0
+    }
 
-            while self.cycles_since_tima >= self.step {
-                self.tima = self.tima.wrapping_add(1);
-                if self.tima == 0 {
-                    self.tima = self.tma;
-                    self.timer_irq = true
-                }
+    /// Increment TIMA if the signal dropped. Besides the counter running,
+    /// this happens when DIV is reset or TAC changes.
+    fn tick_if_fallen(&mut self, previous_signal: bool) {
+        if !previous_signal || self.signal() {
+            return;
+        }
 
-                self.cycles_since_tima -= self.step;
-            }
+        self.tima = self.tima.wrapping_add(1);
+        if self.tima == 0 {
+            self.tima = self.tma;
+            self.timer_irq = true
         }
     }
 
     pub fn read_byte(&self, addr: u16) -> u8 {
         match addr {
-            0xFF04 => self.div,
+            0xFF04 => (self.counter >> 8) as u8,
             0xFF05 => self.tima,
             0xFF06 => self.tma,
             0xFF07 => {
                 0xF8 | (if self.enabled { 0x4 } else { 0 })
-                    | (match self.step {
-                        16 => 1,
-                        64 => 2,
-                        256 => 3,
+                    | (match self.bit {
+                        0x0008 => 1,
+                        0x0020 => 2,
+                        0x0080 => 3,
                         _ => 0,
                     })
             }
@@ -74,24 +85,49 @@ impl Timer {
     }
 
     pub fn write_byte(&mut self, addr: u16, value: u8) {
+        let signal = self.signal();
+
         match addr {
-            // Div register
-            0xFF04 => self.div = 0x0,
+            // Div register. Writing any value resets the whole counter.
+            0xFF04 => self.counter = 0,
 
             // Interrupt registers
             0xFF05 => self.tima = value,
             0xFF06 => self.tma = value,
             0xFF07 => {
                 self.enabled = value & 0x4 != 0;
-                self.step = match value & 0x3 {
-                    1 => 16,
-                    2 => 64,
-                    3 => 256,
-                    _ => 1024,
+                self.bit = match value & 0x3 {
+                    1 => 1 << 3,
+                    2 => 1 << 5,
+                    3 => 1 << 7,
+                    _ => 1 << 9,
                 };
             }
 
             _ => unreachable!(),
         }
+
+        self.tick_if_fallen(signal);
+    }
+}
+
+impl SaveState for Timer {
+    fn save(&self, state: &mut StateWriter) {
+        state.bool(self.timer_irq);
+        state.bool(self.enabled);
+        state.u16(self.counter);
+        state.u8(self.tima);
+        state.u8(self.tma);
+        state.u16(self.bit);
+    }
+
+    fn load(&mut self, state: &mut StateReader) -> Result<(), String> {
+        self.timer_irq = state.bool()?;
+        self.enabled = state.bool()?;
+        self.counter = state.u16()?;
+        self.tima = state.u8()?;
+        self.tma = state.u8()?;
+        self.bit = state.u16()?;
+        Ok(())
     }
 }
@@ -0,0 +1,204 @@
+use std::fmt;
+
+use super::address::BankedAddress;
+
+/// The kind of access a watchpoint traps.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Access {
+    Read,
+    Write,
+
+    /// Reads and writes
+    Any,
+}
+
+/// Which accesses to an address a watchpoint fires on.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ValueFilter {
+    Any,
+
+    /// Only when this value is read or written.
+    Equals(u8),
+
+    /// Only writes that change the value. Reads never change it.
+    Changes,
+}
+
+/// Stops the emulator when the CPU touches memory in a range of addresses.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,
+
+    // Only watch the range while this bank is mapped there
+    pub bank: Option<u16>,
+    pub access: Access,
+    pub filter: ValueFilter,
+}
+
+/// An access that set off a watchpoint. For reads `old` and `new` are both
+/// the value read.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct WatchHit {
+    pub addr: BankedAddress,
+    pub write: bool,
+    pub old: u8,
+    pub new: u8,
+}
+
+impl Watchpoint {
+    pub fn contains(&self, addr: u16) -> bool {
+        (self.start..=self.end).contains(&addr)
+    }
+
+    pub fn matches(&self, hit: &WatchHit) -> bool {
+        let access = match self.access {
+            Access::Read => !hit.write,
+            Access::Write => hit.write,
+            Access::Any => true,
+        };
+
+        let value = match self.filter {
+            ValueFilter::Any => true,
+            ValueFilter::Equals(value) => hit.new == value,
+            ValueFilter::Changes => hit.old != hit.new,
+        };
+
+        let bank = self.bank.is_none() || self.bank == hit.addr.bank;
+
+        access && value && bank && self.contains(hit.addr.addr)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = match self.access {
+            Access::Read => "read",
+            Access::Write => "write",
+            Access::Any => "access",
+        };
+        let start = BankedAddress {
+            bank: self.bank,
+            addr: self.start,
+        };
+        write!(f, "{:<6} {}", access, start)?;
+
+        if self.end != self.start {
+            write!(
+                f,
+                "-{}",
+                BankedAddress {
+                    addr: self.end,
+                    ..start
+                }
+            )?;
+        }
+
+        match self.filter {
+            ValueFilter::Any => Ok(()),
+            ValueFilter::Equals(value) => write!(f, " == {:#04X}", value),
+            ValueFilter::Changes => write!(f, " when changed"),
+        }
+    }
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.write {
+            write!(
+                f,
+                "write {}: {:#04X} -> {:#04X}",
+                self.addr, self.old, self.new
+            )
+        } else {
+            write!(f, "read  {}: {:#04X}", self.addr, self.new)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Access, ValueFilter, WatchHit, Watchpoint};
+    use crate::mmu::address::BankedAddress;
+
+    fn watchpoint(access: Access, filter: ValueFilter) -> Watchpoint {
+        Watchpoint {
+            start: 0xC000,
+            end: 0xC00F,
+            bank: None,
+            access,
+            filter,
+        }
+    }
+
+    fn read(addr: u16, value: u8) -> WatchHit {
+        WatchHit {
+            addr: BankedAddress::new(addr),
+            write: false,
+            old: value,
+            new: value,
+        }
+    }
+
+    fn write(addr: u16, old: u8, new: u8) -> WatchHit {
+        WatchHit {
+            addr: BankedAddress::new(addr),
+            write: true,
+            old,
+            new,
+        }
+    }
+
+    #[test]
+    fn matches_the_range() {
+        let watchpoint = watchpoint(Access::Any, ValueFilter::Any);
+        assert!(watchpoint.matches(&read(0xC000, 0)));
+        assert!(watchpoint.matches(&read(0xC00F, 0)));
+        assert!(!watchpoint.matches(&read(0xBFFF, 0)));
+        assert!(!watchpoint.matches(&write(0xC010, 0, 1)));
+    }
+
+    #[test]
+    fn matches_the_access() {
+        let reads = watchpoint(Access::Read, ValueFilter::Any);
+        assert!(reads.matches(&read(0xC000, 0)));
+        assert!(!reads.matches(&write(0xC000, 0, 1)));
+
+        let writes = watchpoint(Access::Write, ValueFilter::Any);
+        assert!(!writes.matches(&read(0xC000, 0)));
+        assert!(writes.matches(&write(0xC000, 0, 1)));
+
+        let any = watchpoint(Access::Any, ValueFilter::Any);
+        assert!(any.matches(&read(0xC000, 0)));
+        assert!(any.matches(&write(0xC000, 0, 1)));
+    }
+
+    #[test]
+    fn matches_the_value() {
+        let equals = watchpoint(Access::Any, ValueFilter::Equals(0x42));
+        assert!(equals.matches(&read(0xC000, 0x42)));
+        assert!(!equals.matches(&read(0xC000, 0x43)));
+        assert!(equals.matches(&write(0xC000, 0x00, 0x42)));
+        assert!(!equals.matches(&write(0xC000, 0x42, 0x00)));
+
+        let changes = watchpoint(Access::Any, ValueFilter::Changes);
+        assert!(changes.matches(&write(0xC000, 0x00, 0x42)));
+        assert!(!changes.matches(&write(0xC000, 0x42, 0x42)));
+        assert!(!changes.matches(&read(0xC000, 0x42)));
+    }
+
+    #[test]
+    fn matches_the_bank() {
+        let watchpoint = Watchpoint {
+            bank: Some(2),
+            ..watchpoint(Access::Any, ValueFilter::Any)
+        };
+        let in_bank = |bank| WatchHit {
+            addr: BankedAddress::in_bank(bank, 0xC000),
+            ..read(0xC000, 0)
+        };
+
+        assert!(watchpoint.matches(&in_bank(2)));
+        assert!(!watchpoint.matches(&in_bank(1)));
+    }
+}
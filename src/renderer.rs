@@ -3,40 +3,96 @@ use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 
 use crate::mmu::ppu::{SCREEN_HEIGHT, SCREEN_WIDTH};
 
-pub fn window_loop(rx: Receiver<Vec<u32>>, tx: Sender<(bool, Key)>, game_title: &String) {
-    let mut window = Window::new(
+// F1-F9 pick a save state slot
+const SLOT_KEYS: [Key; 9] = [
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+];
+
+/// What the window passes on to the emulator.
+pub enum Input {
+    KeyDown(Key),
+    KeyUp(Key),
+    SaveState(usize),
+    LoadState(usize),
+
+    /// R was pressed or released. The game rewinds while it is held.
+    Rewind(bool),
+
+    /// The window was closed.
+    Quit,
+}
+
+pub struct Frame {
+    pub buffer: Vec<u32>,
+    pub width: usize,
+    pub height: usize,
+}
+
+fn open_window(game_title: &String, width: usize, height: usize) -> Window {
+    Window::new(
         format!("Cowboy Emulator - {}", game_title).as_str(),
-        SCREEN_WIDTH,
-        SCREEN_HEIGHT,
+        width,
+        height,
         WindowOptions {
             scale: Scale::X4,
             ..WindowOptions::default()
         },
     )
-    .unwrap();
+    .unwrap()
+}
+
+pub fn window_loop(rx: Receiver<Frame>, tx: Sender<Input>, game_title: &String) {
+    let mut size = (SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut window = open_window(game_title, size.0, size.1);
 
     while window.is_open() && !window.is_key_down(Key::Escape) {
-        if let Some(frame_buffer) = most_recent_frame(&rx) {
+        if let Some(frame) = most_recent_frame(&rx) {
+            // The SGB border makes the picture bigger
+            if (frame.width, frame.height) != size {
+                size = (frame.width, frame.height);
+                window = open_window(game_title, size.0, size.1);
+            }
+
             window
-                .update_with_buffer(&frame_buffer, SCREEN_WIDTH, SCREEN_HEIGHT)
+                .update_with_buffer(&frame.buffer, frame.width, frame.height)
                 .unwrap();
         }
 
-        // dispatch pressed keys
-        window
-            .get_keys_pressed(KeyRepeat::No)
-            .iter()
-            .for_each(|key| tx.send((true, *key)).unwrap());
+        // dispatch pressed keys. Shift+F1-F9 saves to a slot and F1-F9 loads
+        // from it.
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        for key in window.get_keys_pressed(KeyRepeat::No) {
+            let input = match SLOT_KEYS.iter().position(|k| *k == key) {
+                Some(slot) if shift => Input::SaveState(slot + 1),
+                Some(slot) => Input::LoadState(slot + 1),
+                None if key == Key::R => Input::Rewind(true),
+                None => Input::KeyDown(key),
+            };
+            tx.send(input).unwrap();
+        }
 
         // dispatch released keys
-        window
-            .get_keys_released()
-            .iter()
-            .for_each(|key| tx.send((false, *key)).unwrap());
+        for key in window.get_keys_released() {
+            let input = match key {
+                Key::R => Input::Rewind(false),
+                _ => Input::KeyUp(key),
+            };
+            tx.send(input).unwrap();
+        }
     }
+
+    let _ = tx.send(Input::Quit);
 }
 
-fn most_recent_frame(rx: &Receiver<Vec<u32>>) -> Option<Vec<u32>> {
+fn most_recent_frame(rx: &Receiver<Frame>) -> Option<Frame> {
     let mut latest_frame = None;
 
     loop {